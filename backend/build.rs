@@ -272,7 +272,28 @@ fn main() {
         <div class="joined-button" id="load-raw-path"><button type="button" id="load-raw-file">Load raw data file</button><button type="button" id="load-raw-folder" title="Open a Bruker TDF .d directory">Bruker TDF</button></div>
         <button type="button" id="load-clipboard">Load Clipboard</button>
         <button type="button" id="load-identified-peptides">Load PSM file</button>
+        <button type="button" id="watch-folder" class="secondary" title="Watch a folder, new raw files (and optionally PSM files) are opened as soon as they are written">Watch folder</button>
       </div>
+      <p class="watched-folder" id="watched-folder" hidden>Watching <span id="watched-folder-path"></span><button type="button" id="stop-watching-folder" class="secondary">Stop watching</button></p>
+      <dialog id="watch-folder-dialog">
+        <h2>Watch folder</h2>
+        <p>Any raw file (and optionally PSM file) that appears in this folder is opened as soon as it is fully written. For every new raw file the newest MS2 spectrum with a precursor matching one of the target peptidoforms is annotated with the current annotation settings.</p>
+        <div class="flex-input">
+          <label for="watch-folder-select">Folder</label>
+          <div class="row"><span id="watch-folder-path">No folder selected</span><button type="button" id="watch-folder-select">Select</button></div>
+          <label for="watch-folder-psm-files">Open PSM files</label>
+          <input type="checkbox" switch id="watch-folder-psm-files"/>
+          <label for="watch-folder-targets">Target peptidoforms</label>
+          <textarea id="watch-folder-targets" placeholder="One ProForma definition per line"></textarea>
+          <label for="watch-folder-tolerance">Precursor tolerance (ppm)</label>
+          <input type="number" id="watch-folder-tolerance" value="20" min="0"/>
+        </div>
+        <output class="error hidden" id="watch-folder-error"></output>
+        <div class="row">
+          <button type="button" id="watch-folder-start">Start watching</button>
+          <button type="button" id="watch-folder-cancel" class="secondary">Cancel</button>
+        </div>
+      </dialog>
       <div class="input-flex">
         <div class="usi">
           <a target='_blank' href="https://www.psidev.info/usi" title="Universal Spectrum Identifier, a standard to reference any publicly available spectrum">USI:</a>
@@ -293,6 +314,19 @@ fn main() {
               <label for="search-peptide-amount">Number of results<input id="search-peptide-amount" type="number" min="0" value="25"></input></label>
               <button id="search-peptide">Search</button>
             </div>
+            <details class="search-peptide-metadata">
+              <summary>Filter on metadata</summary>
+              <div class="input-flex">
+                <label for="search-peptide-mass" title="The precursor mass, the experimental mass is used if available otherwise the theoretical mass">Mass (Da)<input id="search-peptide-mass" type="number" min="0"/></label>
+                <label for="search-peptide-mz" title="The precursor m/z, the experimental m/z is used if available otherwise the theoretical m/z">m/z (Th)<input id="search-peptide-mz" type="number" min="0"/></label>
+                <label for="search-peptide-ppm">Tolerance (ppm)<input id="search-peptide-ppm" type="number" min="0" value="10"/></label>
+                <label for="search-peptide-modification" title="A named modification (U:Oxidation) or mass shift (+15.995)">Modification<input id="search-peptide-modification" type="text"/></label>
+                <label for="search-peptide-modification-tolerance">Modification tolerance (Da)<input id="search-peptide-modification-tolerance" type="number" min="0" value="0.01"/></label>
+                <label for="search-peptide-protein">Protein<input id="search-peptide-protein" type="text"/></label>
+                <label for="search-peptide-rt-min">RT (min)<div class="combined-input"><input id="search-peptide-rt-min" type="number" min="0" placeholder="min"/><span>—</span><input id="search-peptide-rt-max" type="number" min="0" placeholder="max"/></div></label>
+                <label for="search-peptide-fdr" title="Only show target PSMs at or below this q-value, only used for files where the FDR is computed">Maximal FDR<input id="search-peptide-fdr" type="number" min="0" max="1" step="0.01" placeholder="any"/></label>
+              </div>
+            </details>
             <div id="resulting-peptides">Go and search!</div>
           </div>
           <div class="resize"></div>
@@ -313,6 +347,102 @@ fn main() {
             <div id="psm-details"></div>
           </div>
         </div>
+        <p class="missing-raw-files hidden" id="missing-raw-files"></p>
+        <h3>False discovery rate</h3>
+        <div class="input-flex">
+          <label for="fdr-decoy-prefixes" title="A PSM is a decoy when all its proteins start with one of these prefixes, separated by commas. If the search engine reports decoys this is used instead.">Decoy prefixes<input id="fdr-decoy-prefixes" type="text" value="DECOY_, rev_"/></label>
+          <label for="fdr-score">Score<select id="fdr-score">
+            <option value="Score" selected>Normalised score</option>
+            <option value="OriginalScore">Original score</option>
+            <option value="MassError">Absolute mass error</option>
+          </select></label>
+          <label for="fdr-lower-is-better">Lower is better<input id="fdr-lower-is-better" type="checkbox" switch/></label>
+          <button id="compute-fdr" type="button">Compute FDR</button>
+          <button id="export-pin" type="button" class="secondary" title="Annotate all PSMs with the current annotation settings and export a Percolator PIN file, the FDR has to be computed first so that the decoys are known">Export PIN</button>
+        </div>
+        <output class="error hidden" id="compute-fdr-error"></output>
+        <div id="compute-fdr-result"></div>
+        <h3>Recalibration</h3>
+        <p>Estimate the systematic fragment m/z error for every raw file from the PSMs in the selected PSM file, using the current annotation settings. The recalibration is applied to all spectra from these raw files until it is cleared.</p>
+        <div class="input-flex">
+          <label for="recalibration-minimal-score">Minimal peptide score<input id="recalibration-minimal-score" type="number" min="0" max="1" step="0.01" value="0.9"/></label>
+          <label for="recalibration-fdr" title="Only use target PSMs at or below this q-value, only used if the FDR is computed for this file">Maximal FDR<input id="recalibration-fdr" type="number" min="0" max="1" step="0.01" value="0.01"/></label>
+          <button id="estimate-recalibration" type="button">Estimate recalibration</button>
+          <button id="clear-recalibration" type="button" class="secondary" title="Remove the recalibration for all raw files">Clear all</button>
+        </div>
+        <output class="error hidden" id="recalibration-error"></output>
+        <div id="recalibration-result"></div>
+        <h3>PSM table</h3>
+        <p>Filter and sort all PSMs of the selected PSM file, all bounds are inclusive and all text filters search case insensitively. Click on a row to show the details of that PSM.</p>
+        <div class="input-flex psm-filter">
+          <label for="psm-filter-score-min">Score<div class="combined-input"><input id="psm-filter-score-min" type="number" placeholder="min"/><span>—</span><input id="psm-filter-score-max" type="number" placeholder="max"/></div></label>
+          <label for="psm-filter-charge-min">Charge<div class="combined-input"><input id="psm-filter-charge-min" type="number" step="1" placeholder="min"/><span>—</span><input id="psm-filter-charge-max" type="number" step="1" placeholder="max"/></div></label>
+          <label for="psm-filter-length-min">Length<div class="combined-input"><input id="psm-filter-length-min" type="number" min="0" step="1" placeholder="min"/><span>—</span><input id="psm-filter-length-max" type="number" min="0" step="1" placeholder="max"/></div></label>
+          <label for="psm-filter-mass-error" title="The maximal absolute precursor mass error">Mass error (ppm)<input id="psm-filter-mass-error" type="number" min="0" placeholder="any"/></label>
+          <label for="psm-filter-modification">Modification<input id="psm-filter-modification" type="text"/></label>
+          <label for="psm-filter-protein">Protein<input id="psm-filter-protein" type="text"/></label>
+          <label for="psm-filter-raw-file">Raw file<input id="psm-filter-raw-file" type="text"/></label>
+          <label for="psm-filter-fdr" title="Only show target PSMs at or below this q-value, only used if the FDR is computed for this file">Maximal FDR<input id="psm-filter-fdr" type="number" min="0" max="1" step="0.01" placeholder="any"/></label>
+        </div>
+        <div class="input-flex">
+          <label for="psm-table-sort">Sort by<select id="psm-table-sort">
+            <option value="Index" selected>Index</option>
+            <option value="Peptide">Peptide</option>
+            <option value="Score">Score</option>
+            <option value="Charge">Charge</option>
+            <option value="MassError">Mass error</option>
+            <option value="Length">Length</option>
+            <option value="Modifications">Number of modifications</option>
+            <option value="Protein">Protein</option>
+            <option value="RawFile">Raw file</option>
+            <option value="RetentionTime">Retention time</option>
+            <option value="QValue">q-value</option>
+          </select></label>
+          <label for="psm-table-descending">Descending<input id="psm-table-descending" type="checkbox" switch/></label>
+          <label for="psm-table-page-size">Page size<input id="psm-table-page-size" type="number" min="1" value="50"/></label>
+          <button id="psm-table-apply" type="button">Apply</button>
+          <button id="psm-table-previous" type="button" class="secondary">Previous</button>
+          <span id="psm-table-page">-</span>
+          <button id="psm-table-next" type="button" class="secondary">Next</button>
+          <button id="psm-table-export" type="button" class="secondary" title="Export all PSMs passing the filter as a TSV file">Export</button>
+        </div>
+        <output class="error hidden" id="psm-table-error"></output>
+        <div class="psm-table" id="psm-table"></div>
+        <h3>Statistics</h3>
+        <p>Show the distributions of the PSMs in the selected PSM file that pass the filter of the PSM table above.</p>
+        <div class="input-flex">
+          <label for="psm-statistics-protease" title="The protease used to count the missed cleavages">Protease<select id="psm-statistics-protease">
+            <option value="Trypsin" selected>Trypsin</option>
+            <option value="TrypsinP">Trypsin/P</option>
+            <option value="LysC">Lys-C</option>
+            <option value="LysN">Lys-N</option>
+            <option value="ArgC">Arg-C</option>
+            <option value="AspN">Asp-N</option>
+            <option value="GluC">Glu-C</option>
+            <option value="Chymotrypsin">Chymotrypsin</option>
+          </select></label>
+          <button id="psm-statistics" type="button">Show statistics</button>
+        </div>
+        <output class="error hidden" id="psm-statistics-error"></output>
+        <div id="psm-statistics-result"></div>
+        <h3>Consensus</h3>
+        <p>List all spectra that are identified in more than one open PSM file. PSMs that do not name their raw file are only compared within their own PSM file.</p>
+        <div class="input-flex">
+          <label for="consensus-only-disagreeing">Only disagreeing<input id="consensus-only-disagreeing" type="checkbox" switch checked/></label>
+          <button id="consensus-groups" type="button">Compare PSM files</button>
+        </div>
+        <output class="error hidden" id="consensus-groups-error"></output>
+        <div class="consensus" id="consensus-groups-result"></div>
+        <h3>Protein coverage</h3>
+        <div class="input-flex">
+          <button id="load-fasta" type="button">Load FASTA</button>
+          <span id="fasta-status">No FASTA loaded</span>
+          <label for="protein-coverage-filter">Protein<input id="protein-coverage-filter" type="text" placeholder="Filter on id or description"/></label>
+          <label for="protein-coverage-amount">Number of proteins<input id="protein-coverage-amount" type="number" min="1" value="10"/></label>
+          <button id="protein-coverage" type="button">Show coverage</button>
+        </div>
+        <output class="error hidden" id="protein-coverage-error"></output>
+        <div id="protein-coverage-result"></div>
       </div>
 
       <div>
@@ -615,6 +745,26 @@ fn main() {
           <legend>Fragment Table</legend>
           <output class="collapsible-content" id="spectrum-fragment-table"></output>
         </fieldset>
+
+        <input type="checkbox" id="collapsible-spectrum-analysis">
+        <fieldset class="collapsible" data-linked-item="collapsible-spectrum-analysis" id="spectrum-analysis">
+          <legend>Spectrum analysis</legend>
+          <p class="collapsible-content">These analyses use the selected spectrum, the peptidoform, and the annotation settings above.</p>
+          <h2>Rank fragmentation models</h2>
+          <div class="flex-input collapsible-content">
+            <button id="rank-models" type="button" title="Annotate the spectrum with every model and rank the models on the explained intensity">Rank models</button>
+          </div>
+          <output class="error collapsible-content hidden" id="rank-models-error"></output>
+          <output class="collapsible-content" id="rank-models-result"></output>
+          <h2>Explain unassigned peaks</h2>
+          <div class="flex-input collapsible-content">
+            <label for="unassigned-amount" title="The number of most intense unassigned peaks to explain">Peaks</label>
+            <input id="unassigned-amount" type="number" min="1" value="10" step="1"></input>
+            <button id="unassigned-peaks" type="button" title="List possible explanations for the most intense peaks that are not annotated">Explain peaks</button>
+          </div>
+          <output class="error collapsible-content hidden" id="unassigned-peaks-error"></output>
+          <output class="collapsible-content" id="unassigned-peaks-result"></output>
+        </fieldset>
       </div>
     
       <input type="checkbox" id="collapsible-tools">
@@ -639,6 +789,88 @@ fn main() {
         </div>
         <output class="error collapsible-content hidden" id="details-formula-error"></output>
         <output class="collapsible-content" id="details-formula-result"></output>
+
+        <h2>Compare peptidoforms</h2>
+        <div class="flex-input collapsible-content">
+          <label for="compare-a">Peptidoform A</label>
+          <input id="compare-a" type="text" placeholder="ProForma"></input>
+          <label for="compare-b">Peptidoform B</label>
+          <input id="compare-b" type="text" placeholder="ProForma"></input>
+          <label for="compare-annotate" title="Annotate both peptidoforms on the selected spectrum with the current annotation settings">Annotate on spectrum</label>
+          <input id="compare-annotate" type="checkbox" switch></input>
+          <button id="compare-button">Compare</button>
+        </div>
+        <output class="error collapsible-content hidden" id="compare-error"></output>
+        <output class="collapsible-content" id="compare-result"></output>
+
+        <h2>Modification localisation</h2>
+        <p class="collapsible-content">Score all positions of the ambiguous modifications on the selected spectrum with the current annotation settings.</p>
+        <div class="flex-input collapsible-content">
+          <label for="localise-peptide" title="Define the possible positions with an ambiguous modification, for example PS[Phospho#g1]T[#g1]Y or [Phospho]?PSTY">Peptidoform</label>
+          <input id="localise-peptide" type="text" placeholder="PS[Phospho#g1]T[#g1]Y"></input>
+          <button id="localise-button">Localise</button>
+        </div>
+        <output class="error collapsible-content hidden" id="localise-error"></output>
+        <output class="collapsible-content" id="localise-result"></output>
+        <h2>Explain precursor mass offset</h2>
+        <p class="collapsible-content">Explain the difference between the precursor mass of the selected spectrum and the peptidoform with modifications and amino acid substitutions.</p>
+        <div class="flex-input collapsible-content">
+          <label for="mass-offset-peptide">Peptidoform</label>
+          <input id="mass-offset-peptide" type="text" placeholder="PEPTIDE"></input>
+          <label for="mass-offset-tolerance" title="The tolerance for the mass of the candidate explanations">Mass tolerance (Da)</label>
+          <input id="mass-offset-tolerance" type="number" min="0" value="0.02" step="0.01"></input>
+          <button id="mass-offset-button">Explain</button>
+        </div>
+        <output class="error collapsible-content hidden" id="mass-offset-error"></output>
+        <output class="collapsible-content" id="mass-offset-result"></output>
+        <h2>Glycan inference</h2>
+        <p class="collapsible-content">Infer the glycan on a peptide backbone from the selected spectrum, use a model that generates glycan fragments.</p>
+        <div class="flex-input collapsible-content">
+          <label for="glycan-inference-peptide" title="The peptide backbone without the glycan">Peptide</label>
+          <input id="glycan-inference-peptide" type="text" placeholder="EVQLVESGGGLVQPGGSLRLSCAASGFNIK"></input>
+          <label for="glycan-inference-delta" title="The mass of the glycan, if left empty the difference between the precursor mass and the mass of the backbone is used">Mass delta (Da)</label>
+          <input id="glycan-inference-delta" type="number" step="0.0001"></input>
+          <label for="glycan-inference-tolerance" title="The tolerance for the mass of the glycan candidates">Mass tolerance (Da)</label>
+          <input id="glycan-inference-tolerance" type="number" min="0" value="0.02" step="0.01"></input>
+          <button id="glycan-inference-button">Infer</button>
+        </div>
+        <output class="error collapsible-content hidden" id="glycan-inference-error"></output>
+        <output class="collapsible-content" id="glycan-inference-result"></output>
+        <h2>Cross-link site search</h2>
+        <p class="collapsible-content">Search the cross-link sites between two peptides on the selected spectrum.</p>
+        <div class="flex-input collapsible-content">
+          <label for="cross-link-first" title="The first peptide without the cross-link">First peptide</label>
+          <input id="cross-link-first" type="text" placeholder="VAKLEK"></input>
+          <label for="cross-link-second" title="The second peptide without the cross-link">Second peptide</label>
+          <input id="cross-link-second" type="text" placeholder="EKAR"></input>
+          <label for="cross-link-linker" title="The cross-linker, for example X:DSS or XLMOD:02001">Linker</label>
+          <input id="cross-link-linker" type="text" placeholder="X:DSS"></input>
+          <button id="cross-link-button">Search</button>
+        </div>
+        <output class="error collapsible-content hidden" id="cross-link-error"></output>
+        <output class="collapsible-content" id="cross-link-result"></output>
+        <h2>Disulfide bond mapping</h2>
+        <p class="collapsible-content">Find which cysteines are bonded in one or more peptides on the selected spectrum.</p>
+        <div class="flex-input collapsible-content">
+          <label for="disulfide-peptides" title="One or more peptides separated by //, without the disulfide bonds">Peptides</label>
+          <input id="disulfide-peptides" type="text" placeholder="CAKC//PCR"></input>
+          <label for="disulfide-bonds" title="The number of disulfide bonds, if left empty as many bonds as possible are formed">Bonds</label>
+          <input id="disulfide-bonds" type="number" min="1" step="1"></input>
+          <button id="disulfide-button">Map</button>
+        </div>
+        <output class="error collapsible-content hidden" id="disulfide-error"></output>
+        <output class="collapsible-content" id="disulfide-result"></output>
+        <h2>Proteoform fragment map</h2>
+        <p class="collapsible-content">Show an overview of the cleaved bonds and the localisation of the modifications of a (long) proteoform on the selected spectrum.</p>
+        <div class="flex-input collapsible-content">
+          <label for="proteoform-map-peptide">Proteoform</label>
+          <input id="proteoform-map-peptide" type="text" placeholder="PEPTIDE"></input>
+          <label for="proteoform-map-deconvolved" title="All peaks of the spectrum are at charge 1, so the fragments are only matched at charge 1">Deconvolved</label>
+          <input id="proteoform-map-deconvolved" type="checkbox" switch></input>
+          <button id="proteoform-map-button">Map</button>
+        </div>
+        <output class="error collapsible-content hidden" id="proteoform-map-error"></output>
+        <output class="collapsible-content" id="proteoform-map-result"></output>
       </fieldset>
       <input type="checkbox" id="collapsible-custom-mods">
       <fieldset class="collapsible" data-linked-item="collapsible-custom-mods" id="custom-modifications">
@@ -897,6 +1129,69 @@ fn main() {
           <button class="cancel secondary" id="custom-model-cancel">Cancel</button>
         </dialog>
         <ul id="custom-models"></ul>
+        <div class="flex-input">
+          <button id="custom-models-export" type="button" title="Export the selected models to a JSON file that can be shared">Export selected</button>
+          <button id="custom-models-import" type="button" title="Import all models from a shared JSON file as custom models">Import</button>
+        </div>
+        <output class="error hidden" id="custom-models-transfer-error"></output>
+        <output id="custom-models-transfer-result"></output>
+      </fieldset>
+      <input type="checkbox" id="collapsible-psm-file-settings">
+      <fieldset class="collapsible" data-linked-item="collapsible-psm-file-settings" id="psm-file-settings">
+        <legend>PSM file settings</legend>
+        <h2>Raw file search roots</h2>
+        <p>Raw files referenced in PSM files that are not open are searched for in the folder of the PSM file and in these folders, including up to three levels of subfolders. Give one folder per line.</p>
+        <textarea id="raw-file-search-roots" class="wide" placeholder="One folder per line"></textarea>
+        <button id="raw-file-search-roots-save" type="button">Save</button>
+        <output class="error hidden" id="raw-file-search-roots-error"></output>
+        <h2>Custom PSM formats</h2>
+        <p>CSV and TSV files that are not recognised as any supported PSM format are opened with the first custom format that has all its columns in the header.</p>
+        <button id="custom-psm-format-create" type="button">Create new</button>
+        <output class="error hidden" id="custom-psm-formats-error"></output>
+        <ul id="custom-psm-formats"></ul>
+        <dialog id="custom-psm-format-dialog">
+          <h2>Custom PSM format</h2>
+          <div class="custom-psm-format">
+            <label for="custom-psm-format-name">Name</label>
+            <input type="text" id="custom-psm-format-name"/>
+            <label for="custom-psm-format-separator">Separator</label>
+            <select id="custom-psm-format-separator">
+              <option value=",">Comma</option>
+              <option value="tab">Tab</option>
+              <option value=";">Semicolon</option>
+              <option value="|">Pipe</option>
+            </select>
+            <label for="custom-psm-format-sequence">Sequence column</label>
+            <input type="text" id="custom-psm-format-sequence"/>
+            <label for="custom-psm-format-modifications">Modification syntax</label>
+            <select id="custom-psm-format-modifications">
+              <option value="ProForma" title="PEM[U:Oxidation]TIDE or PEM[+15.995]TIDE">ProForma</option>
+              <option value="RoundBrackets" title="PEM(Oxidation)TIDE or PEM(15.995)TIDE">Round brackets</option>
+              <option value="UnsignedMass" title="PEM[15.995]TIDE">Unsigned mass shifts</option>
+            </select>
+            <label for="custom-psm-format-strip-flanking" title="Remove flanking residues as in K.PEPTIDE.R">Strip flanking residues</label>
+            <input type="checkbox" id="custom-psm-format-strip-flanking" switch/>
+            <label for="custom-psm-format-charge">Charge column</label>
+            <input type="text" id="custom-psm-format-charge"/>
+            <label for="custom-psm-format-scan">Scan column</label>
+            <input type="text" id="custom-psm-format-scan"/>
+            <label for="custom-psm-format-one-based" title="The scan column contains one based scan numbers instead of zero based spectrum indices">One based scans</label>
+            <input type="checkbox" id="custom-psm-format-one-based" switch/>
+            <label for="custom-psm-format-raw-file">Raw file column</label>
+            <input type="text" id="custom-psm-format-raw-file"/>
+            <label for="custom-psm-format-score">Score column</label>
+            <input type="text" id="custom-psm-format-score" placeholder="Optional"/>
+            <label for="custom-psm-format-score-min" title="The score is normalised to this range, leave empty to use the score as is">Score range</label>
+            <span><input type="number" id="custom-psm-format-score-min" placeholder="Min"/> — <input type="number" id="custom-psm-format-score-max" placeholder="Max"/></span>
+            <label for="custom-psm-format-retention-time">Retention time column</label>
+            <input type="text" id="custom-psm-format-retention-time" placeholder="Optional"/>
+            <label for="custom-psm-format-retention-time-seconds">Retention time in seconds</label>
+            <input type="checkbox" id="custom-psm-format-retention-time-seconds" switch/>
+          </div>
+          <output class="error hidden" id="custom-psm-format-error"></output>
+          <button class="save" id="custom-psm-format-save">Save</button>
+          <button class="cancel secondary" id="custom-psm-format-cancel">Cancel</button>
+        </dialog>
       </fieldset>
       <input type="checkbox" id="collapsible-ontologies">
       <fieldset class="collapsible" data-linked-item="collapsible-ontologies" id="ontologies-collapsible">
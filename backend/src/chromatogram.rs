@@ -0,0 +1,259 @@
+use std::fmt::Write;
+
+use itertools::Itertools;
+use mzdata::{prelude::SpectrumLike, spectrum::MultiLayerSpectrum};
+
+use crate::{ModifiableState, raw_file::RawFile};
+
+/// The run level overview of a raw file, with separate traces for MS1 and MS2 spectra.
+pub struct Chromatogram {
+    pub ms1: Vec<ChromatogramPoint>,
+    pub ms2: Vec<ChromatogramPoint>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ChromatogramPoint {
+    pub index: usize,
+    pub rt: f64,
+    pub tic: f32,
+    pub base_peak: f32,
+}
+
+impl Chromatogram {
+    pub fn new(spectra: impl Iterator<Item = MultiLayerSpectrum>) -> Self {
+        let mut ms1 = Vec::new();
+        let mut ms2 = Vec::new();
+        for spectrum in spectra {
+            let summary = spectrum.peaks().fetch_summaries();
+            let point = ChromatogramPoint {
+                index: spectrum.index(),
+                rt: spectrum.start_time(),
+                tic: summary.tic,
+                base_peak: summary.base_peak.intensity,
+            };
+            match spectrum.ms_level() {
+                1 => ms1.push(point),
+                2 => ms2.push(point),
+                _ => (),
+            }
+        }
+        ms1.sort_unstable_by(|a, b| a.rt.total_cmp(&b.rt));
+        ms2.sort_unstable_by(|a, b| a.rt.total_cmp(&b.rt));
+        Self { ms1, ms2 }
+    }
+
+    /// Get the MS2 spectrum with the retention time closest to the given retention time
+    pub fn closest_ms2(&self, rt: f64) -> Option<&ChromatogramPoint> {
+        let index = self.ms2.partition_point(|p| p.rt < rt);
+        [index.checked_sub(1), Some(index)]
+            .into_iter()
+            .flatten()
+            .filter_map(|i| self.ms2.get(i))
+            .min_by(|a, b| (a.rt - rt).abs().total_cmp(&(b.rt - rt).abs()))
+    }
+
+    /// Get the retention time range of all spectra, or None if there are no MS1 or MS2 spectra
+    fn rt_range(&self) -> Option<(f64, f64)> {
+        self.ms1
+            .iter()
+            .chain(self.ms2.iter())
+            .map(|p| p.rt)
+            .minmax_by(f64::total_cmp)
+            .into_option()
+    }
+
+    fn find(&self, index: usize) -> Option<&ChromatogramPoint> {
+        self.ms1
+            .iter()
+            .chain(self.ms2.iter())
+            .find(|p| p.index == index)
+    }
+}
+
+/// Render the TIC and base peak chromatograms for MS1 and MS2 with the MS2 events and the selected spectra marked.
+fn render_chromatogram(
+    file_id: usize,
+    chromatogram: &Chromatogram,
+    selected: &[usize],
+) -> Result<String, &'static str> {
+    fn trace(
+        output: &mut String,
+        class: &str,
+        title: &str,
+        points: &[ChromatogramPoint],
+        value: impl Fn(&ChromatogramPoint) -> f32,
+        (min_rt, max_rt): (f64, f64),
+    ) {
+        let max = points.iter().map(&value).fold(0.0_f32, f32::max);
+        let width = (max_rt - min_rt).max(f64::EPSILON);
+        write!(
+            output,
+            "<div class='trace {class}'><span class='title'>{title}</span><span class='max'>{max:.3e}</span><svg viewBox='0 0 1000 100' preserveAspectRatio='none'><path class='line' d='M 0 100{}'></path></svg></div>",
+            points
+                .iter()
+                .map(|p| format!(
+                    " L {:.2} {:.2}",
+                    (p.rt - min_rt) / width * 1000.0,
+                    100.0 - f64::from(value(p) / max.max(f32::EPSILON)) * 100.0
+                ))
+                .join("")
+        )
+        .unwrap();
+    }
+
+    let range = chromatogram
+        .rt_range()
+        .ok_or("The raw file does not contain any MS1 or MS2 spectra")?;
+    let mut output = String::new();
+    write!(
+        output,
+        "<div class='chromatogram' data-file='{file_id}' data-min-rt='{0}' data-max-rt='{1}' style='--min-rt:{0};--max-rt:{1};'>",
+        range.0, range.1
+    )
+    .unwrap();
+    trace(
+        &mut output,
+        "ms1 tic",
+        "MS1 TIC",
+        &chromatogram.ms1,
+        |p| p.tic,
+        range,
+    );
+    trace(
+        &mut output,
+        "ms1 base-peak",
+        "MS1 base peak",
+        &chromatogram.ms1,
+        |p| p.base_peak,
+        range,
+    );
+    trace(
+        &mut output,
+        "ms2 tic",
+        "MS2 TIC",
+        &chromatogram.ms2,
+        |p| p.tic,
+        range,
+    );
+    trace(
+        &mut output,
+        "ms2 base-peak",
+        "MS2 base peak",
+        &chromatogram.ms2,
+        |p| p.base_peak,
+        range,
+    );
+    write!(output, "<div class='events'>").unwrap();
+    for point in &chromatogram.ms2 {
+        write!(
+            output,
+            "<span class='ms2-event' style='--rt:{};' data-index='{}' title='Index: {}, RT: {:.3} min'></span>",
+            point.rt,
+            point.index,
+            point.index,
+            point.rt / 60.0
+        )
+        .unwrap();
+    }
+    for point in selected
+        .iter()
+        .filter_map(|index| chromatogram.find(*index))
+    {
+        write!(
+            output,
+            "<span class='selected-spectrum' style='--rt:{};' data-index='{}' title='Selected, index: {}, RT: {:.3} min'></span>",
+            point.rt,
+            point.index,
+            point.index,
+            point.rt / 60.0
+        )
+        .unwrap();
+    }
+    write!(
+        output,
+        "</div><div class='x-axis'><span class='min'>{:.2} min</span><span class='max'>{:.2} min</span></div></div>",
+        range.0 / 60.0,
+        range.1 / 60.0
+    )
+    .unwrap();
+    Ok(output)
+}
+
+/// Get the rendered TIC and base peak chromatograms for the given raw file.
+#[tauri::command]
+pub async fn get_chromatogram(
+    file_index: usize,
+    state: ModifiableState<'_>,
+) -> Result<String, &'static str> {
+    let mut state = state.lock().await;
+    let file = state
+        .spectra
+        .iter_mut()
+        .find(|f| f.id() == file_index)
+        .ok_or("File index not valid")?;
+    let selected = match file {
+        RawFile::File {
+            selected_spectra, ..
+        } => selected_spectra.clone(),
+        RawFile::Single { .. } => Vec::new(),
+    };
+    let chromatogram = file.chromatogram()?;
+    render_chromatogram(file_index, chromatogram, &selected)
+}
+
+/// Select the MS2 spectrum closest to the clicked retention time in the chromatogram.
+#[tauri::command]
+pub async fn select_chromatogram_retention_time(
+    file_index: usize,
+    rt: f64,
+    state: ModifiableState<'_>,
+) -> Result<(), &'static str> {
+    state
+        .lock()
+        .await
+        .spectra
+        .iter_mut()
+        .find(|f| f.id() == file_index)
+        .ok_or("File index not valid")
+        .and_then(|file| file.select_closest_retention_time(rt))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(index: usize, rt: f64) -> ChromatogramPoint {
+        ChromatogramPoint {
+            index,
+            rt,
+            tic: 1.0,
+            base_peak: 1.0,
+        }
+    }
+
+    #[test]
+    fn rt_range() {
+        let empty = Chromatogram {
+            ms1: Vec::new(),
+            ms2: Vec::new(),
+        };
+        assert_eq!(empty.rt_range(), None);
+        assert!(render_chromatogram(0, &empty, &[]).is_err());
+        let chromatogram = Chromatogram {
+            ms1: vec![point(0, 1.0), point(2, 3.0)],
+            ms2: vec![point(1, 2.0), point(3, 4.0)],
+        };
+        assert_eq!(chromatogram.rt_range(), Some((1.0, 4.0)));
+    }
+
+    #[test]
+    fn closest_ms2() {
+        let chromatogram = Chromatogram {
+            ms1: Vec::new(),
+            ms2: vec![point(1, 2.0), point(3, 4.0)],
+        };
+        assert_eq!(chromatogram.closest_ms2(2.9).map(|p| p.index), Some(1));
+        assert_eq!(chromatogram.closest_ms2(3.1).map(|p| p.index), Some(3));
+        assert_eq!(chromatogram.closest_ms2(10.0).map(|p| p.index), Some(3));
+    }
+}
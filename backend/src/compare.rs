@@ -0,0 +1,240 @@
+use std::{collections::HashSet, fmt::Write};
+
+use context_error::{BasicKind, BoxedError, Context, CreateError, FullErrorContent};
+use mzalign::{AlignScoring, AlignType};
+use mzannotate::{annotation::Score, prelude::*};
+use mzcore::{
+    prelude::*,
+    sequence::{Linear, Linked},
+    system::{e, isize::Charge},
+};
+use mzdata::prelude::SpectrumLike;
+
+use crate::{ModifiableState, spectra::NoiseFilter};
+
+pub fn parse_linear(
+    text: &str,
+    ontologies: &mzcore::ontology::Ontologies,
+) -> Result<Peptidoform<Linear>, String> {
+    Peptidoform::<Linked>::pro_forma(text, ontologies)
+        .map_err(|errs| {
+            BoxedError::new(
+                BasicKind::Error,
+                "Invalid ProForma definition",
+                "The string could not be parsed as a ProForma definition",
+                Context::full_line(0, text),
+            )
+            .add_underlying_errors(errs)
+            .to_html(false)
+        })?
+        .0
+        .into_linear()
+        .ok_or_else(|| {
+            BoxedError::new(
+                BasicKind::Error,
+                "Invalid peptidoform",
+                "A peptidoform to compare should not be cross-linked",
+                Context::full_line(0, text),
+            )
+            .to_html(false)
+        })
+}
+
+/// Render the residues `start..end` of a peptidoform, marking the residues with fragment evidence
+fn render_segment(
+    output: &mut String,
+    peptidoform: &Peptidoform<Linear>,
+    start: usize,
+    end: usize,
+    supported: Option<&HashSet<usize>>,
+) {
+    for index in start..end {
+        let mut residue = String::new();
+        peptidoform
+            .sub_peptidoform(index..index + 1)
+            .unwrap()
+            .display(
+                &mut residue,
+                index == 0,
+                index + 1 == peptidoform.len(),
+                true,
+            )
+            .unwrap();
+        write!(
+            output,
+            "<span class='residue{}'>{residue}</span>",
+            if supported.is_some_and(|s| s.contains(&index)) {
+                " supported"
+            } else {
+                ""
+            }
+        )
+        .unwrap();
+    }
+}
+
+/// Get all sequence positions that have fragment evidence in the annotated spectrum
+fn supported_positions(spectrum: &AnnotatedSpectrum) -> HashSet<usize> {
+    spectrum
+        .peaks
+        .iter()
+        .flat_map(|p| p.annotations.iter())
+        .filter_map(|a| a.ion.position())
+        .filter_map(|p| match p.sequence_index {
+            SequencePosition::Index(i) => Some(i),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Align two peptidoforms with mass based alignment and render the alignment with all mass
+/// equivalent segments highlighted. If `annotate` is set both peptidoforms are also annotated
+/// on the selected spectrum and the residues with fragment evidence are marked.
+#[tauri::command]
+pub async fn compare_peptidoforms(
+    a: &str,
+    b: &str,
+    annotate: bool,
+    tolerance: (f64, &str),
+    charge: Option<isize>,
+    noise_filter: (NoiseFilter, f32),
+    model: usize,
+    mass_mode: &str,
+    mz_range: (Option<f64>, Option<f64>),
+    isotopes: bool,
+    isotope_filter: f64,
+    state: ModifiableState<'_>,
+) -> Result<String, String> {
+    let mut state = state.lock().await;
+    let peptidoform_a = parse_linear(a, &state.ontologies)?;
+    let peptidoform_b = parse_linear(b, &state.ontologies)?;
+    let alignment = mzalign::align::<4, _, _>(
+        &peptidoform_a,
+        &peptidoform_b,
+        AlignScoring::default(),
+        AlignType::GLOBAL,
+    );
+
+    let support = if annotate {
+        let (_, spectrum) = crate::spectra::create_selected_spectrum(&mut state, noise_filter)
+            .map_err(|err| err.to_html(false))?;
+        let model = crate::model::get_models(&state)
+            .1
+            .get(model)
+            .map(|m| m.2.clone())
+            .ok_or_else(|| {
+                BoxedError::small(BasicKind::Error, "Invalid model", "Model does not exist")
+                    .to_html(false)
+            })?;
+        let parameters = crate::model::parameters(tolerance, mz_range, isotopes, isotope_filter)
+            .map_err(|err| err.to_html(false))?;
+        let mass_mode = crate::model::mass_mode(mass_mode).map_err(|err| err.to_html(false))?;
+        let charge = Charge::new::<e>(
+            charge
+                .or_else(|| {
+                    spectrum
+                        .precursor()
+                        .and_then(|p| p.ions.first().and_then(|i| i.charge.map(|c| c as isize)))
+                })
+                .unwrap_or(1),
+        );
+        let mut support = Vec::new();
+        for text in [a, b] {
+            let (peptidoform, _) =
+                PeptidoformIonSet::pro_forma(text, &state.ontologies).map_err(|errs| {
+                    BoxedError::new(
+                        BasicKind::Error,
+                        "Invalid ProForma definition",
+                        "The string could not be parsed as a ProForma definition",
+                        Context::full_line(0, text),
+                    )
+                    .add_underlying_errors(errs)
+                    .to_html(false)
+                })?;
+            let fragments = peptidoform.generate_theoretical_fragments(charge, &model);
+            let annotated =
+                spectrum
+                    .clone()
+                    .annotate(peptidoform, &fragments, &parameters, mass_mode);
+            let (scores, _) = annotated.scores(&fragments, &parameters, mass_mode);
+            support.push((supported_positions(&annotated), scores.score));
+        }
+        Some(support)
+    } else {
+        None
+    };
+
+    let mut output = String::new();
+    write!(
+        output,
+        "<p>Normalised score: {:.3}, Mass difference: {}</p><div class='peptidoform-alignment'>",
+        alignment.normalised_score(),
+        crate::render::display_mass(alignment.mass_difference(), None),
+    )
+    .unwrap();
+    let mut row_a = String::new();
+    let mut row_b = String::new();
+    let mut index_a = alignment.start_a();
+    let mut index_b = alignment.start_b();
+    for piece in alignment.path() {
+        let class = format!("{:?}", piece.match_type).to_lowercase();
+        let end_a = index_a + piece.step_a as usize;
+        let end_b = index_b + piece.step_b as usize;
+        write!(row_a, "<span class='segment {class}' title='{class}'>").unwrap();
+        render_segment(
+            &mut row_a,
+            &peptidoform_a,
+            index_a,
+            end_a,
+            support.as_ref().map(|s| &s[0].0),
+        );
+        write!(row_a, "</span>").unwrap();
+        write!(row_b, "<span class='segment {class}' title='{class}'>").unwrap();
+        render_segment(
+            &mut row_b,
+            &peptidoform_b,
+            index_b,
+            end_b,
+            support.as_ref().map(|s| &s[1].0),
+        );
+        write!(row_b, "</span>").unwrap();
+        index_a = end_a;
+        index_b = end_b;
+    }
+    write!(
+        output,
+        "<div class='row'>{row_a}</div><div class='row'>{row_b}</div></div>"
+    )
+    .unwrap();
+    if let Some(support) = support {
+        write!(
+            output,
+            "<table><thead><tr><th>Peptidoform</th><th>Positions with fragments</th><th>Annotated peaks</th><th>Annotated intensity</th></tr></thead><tbody>"
+        )
+        .unwrap();
+        for ((supported, score), (text, peptidoform)) in support
+            .iter()
+            .zip([(a, &peptidoform_a), (b, &peptidoform_b)])
+        {
+            let (peaks, intensity) = match score {
+                Score::Position {
+                    peaks, intensity, ..
+                }
+                | Score::UniqueFormulas {
+                    peaks, intensity, ..
+                } => (peaks.fraction(), intensity.fraction()),
+            };
+            write!(
+                output,
+                "<tr><td>{text}</td><td>{}/{}</td><td>{:.2}%</td><td>{:.2}%</td></tr>",
+                supported.len(),
+                peptidoform.len(),
+                peaks * 100.0,
+                intensity * 100.0,
+            )
+            .unwrap();
+        }
+        write!(output, "</tbody></table>").unwrap();
+    }
+    Ok(output)
+}
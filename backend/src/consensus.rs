@@ -0,0 +1,224 @@
+use std::collections::{BTreeMap, HashMap};
+
+use itertools::Itertools;
+use mzident::{PSMMetaData, SpectrumId, SpectrumIds};
+
+use crate::{
+    ModifiableState, html_builder,
+    psm_file::PSMFile,
+    psms::{PSMSettings, select_scans},
+    raw_discovery::raw_file_stem,
+    raw_file::RawFile,
+};
+
+/// The raw file a PSM refers to. PSMs that do not name their raw file are only grouped with
+/// PSMs from the same PSM file, as the scans of different PSM files could refer to different runs.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum ConsensusRawFile {
+    /// The lowercase file stem
+    Known(String),
+    /// The PSM file id
+    Unknown(usize),
+}
+
+/// The spectrum a PSM refers to, resolved to the zero based spectrum index whenever possible so that
+/// the same spectrum referenced by index, scan number, or native ID ends up in the same group.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum ConsensusSpectrum {
+    Index(usize),
+    Other(String),
+}
+
+impl std::fmt::Display for ConsensusRawFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Known(stem) => write!(f, "{stem}"),
+            Self::Unknown(file) => write!(f, "Unknown (F{})", file + 1),
+        }
+    }
+}
+
+impl std::fmt::Display for ConsensusSpectrum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Index(index) => write!(f, "index {index}"),
+            Self::Other(id) => write!(f, "{id}"),
+        }
+    }
+}
+
+/// All PSMs from all open PSM files that identify the same spectrum.
+type ConsensusGroups = BTreeMap<(ConsensusRawFile, ConsensusSpectrum), Vec<(usize, usize)>>;
+
+/// Resolve a spectrum ID to the spectrum index, native IDs can only be resolved if the raw file is open.
+fn resolve_spectrum(id: &SpectrumId, raw_file: Option<&RawFile>) -> ConsensusSpectrum {
+    match id {
+        SpectrumId::Index(index) => ConsensusSpectrum::Index(*index),
+        SpectrumId::Number(number) => number.checked_sub(1).map_or_else(
+            || ConsensusSpectrum::Other(id.to_string()),
+            ConsensusSpectrum::Index,
+        ),
+        SpectrumId::Native(native) => raw_file
+            .and_then(|r| r.index_of_native_id(native))
+            .map_or_else(
+                || ConsensusSpectrum::Other(id.to_string()),
+                ConsensusSpectrum::Index,
+            ),
+        SpectrumId::RetentionTime(_) => ConsensusSpectrum::Other(id.to_string()),
+    }
+}
+
+fn consensus_groups(files: &[PSMFile], raw_files: &[RawFile]) -> ConsensusGroups {
+    let raw_files_by_stem: HashMap<String, &RawFile> = raw_files
+        .iter()
+        .map(|raw_file| {
+            (
+                raw_file_stem(std::path::Path::new(&raw_file.details().path)),
+                raw_file,
+            )
+        })
+        .collect();
+    let mut groups = ConsensusGroups::new();
+    for file in files {
+        for (index, psm) in file.peptides.iter().enumerate() {
+            let keys = match psm.scans() {
+                SpectrumIds::None => Vec::new(),
+                SpectrumIds::FileNotKnown(scans) => scans
+                    .iter()
+                    .map(|scan| {
+                        (
+                            ConsensusRawFile::Unknown(file.id),
+                            resolve_spectrum(scan, None),
+                        )
+                    })
+                    .collect(),
+                SpectrumIds::FileKnown(scans) => scans
+                    .iter()
+                    .flat_map(|(raw_file, scans)| {
+                        let stem = raw_file_stem(raw_file);
+                        let open = raw_files_by_stem.get(&stem).copied();
+                        scans.iter().map(move |scan| {
+                            (
+                                ConsensusRawFile::Known(stem.clone()),
+                                resolve_spectrum(scan, open),
+                            )
+                        })
+                    })
+                    .collect(),
+            };
+            for key in keys {
+                groups.entry(key).or_default().push((file.id, index));
+            }
+        }
+    }
+    groups
+}
+
+/// Get all spectra that are identified in multiple PSM files, optionally only those where the
+/// identifications disagree. Every group lists all competing peptidoforms side by side.
+#[tauri::command]
+pub async fn get_consensus_groups(
+    only_disagreeing: bool,
+    state: ModifiableState<'_>,
+) -> Result<String, &'static str> {
+    let state = state.lock().await;
+    let files = state.psm_files();
+    let rows = consensus_groups(&files, &state.spectra)
+        .into_iter()
+        .filter_map(|((raw_file, scan), psms)| {
+            let psms = psms
+                .into_iter()
+                .filter_map(|(file_id, index)| {
+                    files
+                        .iter()
+                        .find(|f| f.id == file_id)
+                        .and_then(|f| f.peptides.get(index).map(|p| (f, index, p)))
+                })
+                .collect_vec();
+            let distinct_files = psms.iter().map(|(f, _, _)| f.id).unique().count();
+            let distinct_peptidoforms = psms
+                .iter()
+                .map(|(_, _, p)| {
+                    p.peptidoform_ion_set()
+                        .map(|p| p.to_string())
+                        .unwrap_or_default()
+                })
+                .unique()
+                .count();
+            (distinct_files > 1 && (!only_disagreeing || distinct_peptidoforms > 1)).then(|| {
+                [
+                    raw_file.to_string(),
+                    scan.to_string(),
+                    psms.iter()
+                        .map(|(file, index, psm)| {
+                            format!(
+                                "<span class='consensus-psm'><a onclick=\"load_peptide({0}, {1})\">F{2}:{1}</a> {3} <span class='score'>{4}</span></span>",
+                                file.id,
+                                index,
+                                file.id + 1,
+                                psm.peptidoform_ion_set()
+                                    .map(|p| p.to_string())
+                                    .unwrap_or_default(),
+                                psm.score
+                                    .map(|score| format!("{score:.3}"))
+                                    .unwrap_or_default(),
+                            )
+                        })
+                        .join(""),
+                    format!(
+                        "<button class='consensus-annotate' onclick=\"load_consensus_group('{}')\">Annotate together</button>",
+                        psms.iter()
+                            .map(|(file, index, _)| format!("{}:{index}", file.id))
+                            .join(";")
+                    ),
+                ]
+            })
+        })
+        .collect_vec();
+    Ok(html_builder::HtmlElement::table(
+        Some(&["Raw file", "Scan", "Identifications", "Annotate"]),
+        rows,
+    )
+    .to_string())
+}
+
+/// Select the spectrum of a consensus group and combine all distinct peptidoforms into a
+/// single chimeric ProForma definition so that they can be annotated together.
+#[tauri::command]
+pub async fn load_consensus_group(
+    psms: Vec<(usize, usize)>,
+    state: ModifiableState<'_>,
+) -> Result<PSMSettings, &'static str> {
+    let peptides = {
+        let state = state.lock().await;
+        let files = state.psm_files();
+        psms.iter()
+            .map(|(file, index)| {
+                files
+                    .iter()
+                    .find(|f| f.id == *file)
+                    .and_then(|f| f.peptides.get(*index).map(|p| (f.path.clone(), p.clone())))
+                    .ok_or("The PSM could not be found")
+            })
+            .collect::<Result<Vec<_>, _>>()?
+    };
+    let (path, first) = peptides.first().ok_or("No PSMs given")?;
+    let open_message = crate::raw_discovery::open_referenced_raw_files(
+        &state,
+        crate::raw_discovery::referenced_raw_files(&first.scans()),
+        std::path::Path::new(path)
+            .parent()
+            .map(std::path::Path::to_path_buf),
+    )
+    .await;
+    let mut state = state.lock().await;
+    let (_, message) = select_scans(&mut state, first.scans());
+    let message = open_message.or(message);
+    let mut settings = PSMSettings::from_peptide(&state, first, message);
+    settings.peptide = peptides
+        .iter()
+        .filter_map(|(_, p)| p.peptidoform_ion_set().map(|p| p.to_string()))
+        .unique()
+        .join("+");
+    Ok(settings)
+}
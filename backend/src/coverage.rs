@@ -0,0 +1,354 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Write,
+};
+
+use context_error::{BasicKind, BoxedError, Context, CreateError, FullErrorContent};
+use itertools::Itertools;
+use mzident::{PSMData, PSMMetaData, open_psm_file};
+use rayon::prelude::*;
+
+use crate::{ModifiableState, html_builder, psm_file::PSMFile};
+
+/// A protein as read from a FASTA file
+pub struct FastaProtein {
+    pub id: String,
+    pub description: String,
+    /// The one letter amino acid codes
+    pub sequence: String,
+}
+
+/// The length of the k-mers in the FASTA index, peptides shorter than this are searched linearly
+const KMER: usize = 4;
+
+/// The loaded FASTA file with a k-mer index on the I/L normalised sequences so that peptides can be
+/// mapped to the proteins without scanning every protein for every peptide.
+#[derive(Default)]
+pub struct FastaDatabase {
+    pub proteins: Vec<FastaProtein>,
+    /// The I/L normalised sequence of every protein
+    normalised: Vec<Vec<u8>>,
+    /// The protein index and start position of every k-mer
+    index: HashMap<[u8; KMER], Vec<(u32, u32)>>,
+}
+
+impl FastaDatabase {
+    pub fn new(proteins: Vec<FastaProtein>) -> Self {
+        let normalised = proteins
+            .iter()
+            .map(|p| p.sequence.replace('I', "L").into_bytes())
+            .collect_vec();
+        let mut index: HashMap<[u8; KMER], Vec<(u32, u32)>> = HashMap::new();
+        for (protein, sequence) in normalised.iter().enumerate() {
+            for (start, kmer) in sequence.windows(KMER).enumerate() {
+                index
+                    .entry(kmer.try_into().unwrap())
+                    .or_default()
+                    .push((protein as u32, start as u32));
+            }
+        }
+        Self {
+            proteins,
+            normalised,
+            index,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.proteins.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.proteins.len()
+    }
+
+    /// Find all occurrences of the I/L normalised peptide as (protein index, start position).
+    fn find(&self, peptide: &[u8]) -> Vec<(usize, usize)> {
+        if peptide.len() >= KMER {
+            let kmer: [u8; KMER] = peptide[..KMER].try_into().unwrap();
+            self.index
+                .get(&kmer)
+                .into_iter()
+                .flatten()
+                .map(|(protein, start)| (*protein as usize, *start as usize))
+                .filter(|(protein, start)| {
+                    self.normalised[*protein]
+                        .get(*start..*start + peptide.len())
+                        .is_some_and(|s| s == peptide)
+                })
+                .collect()
+        } else if peptide.is_empty() {
+            Vec::new()
+        } else {
+            self.normalised
+                .iter()
+                .enumerate()
+                .flat_map(|(protein, sequence)| {
+                    sequence
+                        .windows(peptide.len())
+                        .enumerate()
+                        .filter(|(_, window)| *window == peptide)
+                        .map(move |(start, _)| (protein, start))
+                })
+                .collect()
+        }
+    }
+}
+
+/// All PSMs that have the same (I/L normalised) sequence, with the modifications per position
+type PeptideGroup = Vec<(usize, usize, Vec<Vec<String>>)>;
+
+/// Open a FASTA file to use for the protein coverage and index it, returns the number of proteins.
+#[tauri::command]
+pub async fn load_fasta(path: &str, state: ModifiableState<'_>) -> Result<usize, String> {
+    let mut state = state.lock().await;
+    let proteins = open_psm_file(std::path::Path::new(path), &state.ontologies, false)
+        .map_err(|e| e.to_html(false))?
+        .filter_map(|psm| {
+            let psm = psm.ok()?;
+            let PSMData::Fasta(data) = &psm.data else {
+                return None;
+            };
+            Some(FastaProtein {
+                id: data
+                    .header()
+                    .trim_start_matches('>')
+                    .split_whitespace()
+                    .next()
+                    .unwrap_or_default()
+                    .to_string(),
+                description: data.description().to_string(),
+                sequence: psm
+                    .peptidoform_ion_set()?
+                    .singular_peptidoform_ref()?
+                    .sequence()
+                    .iter()
+                    .map(|s| s.aminoacid.pro_forma_definition())
+                    .collect(),
+            })
+        })
+        .collect_vec();
+    if proteins.is_empty() {
+        return Err(BoxedError::new(
+            BasicKind::Error,
+            "Invalid FASTA file",
+            "The file does not contain any proteins",
+            Context::show(path).to_owned(),
+        )
+        .to_html(false));
+    }
+    state.fasta = FastaDatabase::new(proteins);
+    Ok(state.fasta.len())
+}
+
+/// Group all PSMs on their I/L normalised sequence
+fn peptide_groups(files: &[PSMFile]) -> HashMap<String, PeptideGroup> {
+    let mut groups: HashMap<String, PeptideGroup> = HashMap::new();
+    for file in files {
+        for (index, psm) in file.peptides.iter().enumerate() {
+            let Some(peptidoform) = psm
+                .peptidoform_ion_set()
+                .and_then(|p| p.singular_peptidoform_ref().cloned())
+            else {
+                continue;
+            };
+            let length = peptidoform.len();
+            let sequence = peptidoform
+                .sequence()
+                .iter()
+                .map(|s| s.aminoacid.pro_forma_definition())
+                .collect::<String>()
+                .replace('I', "L");
+            let modifications = peptidoform
+                .sequence()
+                .iter()
+                .enumerate()
+                .map(|(position, s)| {
+                    let mut modifications =
+                        s.modifications.iter().map(|m| m.to_string()).collect_vec();
+                    if position == 0 {
+                        modifications
+                            .extend(peptidoform.get_n_term().iter().map(|m| m.to_string()));
+                    }
+                    if position + 1 == length {
+                        modifications
+                            .extend(peptidoform.get_c_term().iter().map(|m| m.to_string()));
+                    }
+                    modifications
+                })
+                .collect();
+            groups
+                .entry(sequence)
+                .or_default()
+                .push((file.id, index, modifications));
+        }
+    }
+    groups
+}
+
+/// The coverage of a single protein
+struct ProteinCoverage<'a> {
+    protein: &'a FastaProtein,
+    /// Number of PSMs covering every residue
+    counts: Vec<usize>,
+    /// Observed modifications on every residue
+    modifications: Vec<BTreeSet<String>>,
+    /// All matching peptides with start position, length, and supporting PSMs
+    peptides: Vec<(usize, usize, &'a PeptideGroup)>,
+}
+
+impl<'a> ProteinCoverage<'a> {
+    /// Create the coverage from all peptide matches on this protein as (start, group)
+    fn new(protein: &'a FastaProtein, matches: &[(usize, &'a str, &'a PeptideGroup)]) -> Self {
+        let mut counts = vec![0; protein.sequence.len()];
+        let mut modifications = vec![BTreeSet::new(); protein.sequence.len()];
+        let mut peptides = Vec::with_capacity(matches.len());
+        for (start, sequence, psms) in matches {
+            let start = *start;
+            for position in start..start + sequence.len() {
+                counts[position] += psms.len();
+                for (_, _, mods) in psms.iter() {
+                    if let Some(mods) = mods.get(position - start) {
+                        modifications[position].extend(mods.iter().cloned());
+                    }
+                }
+            }
+            peptides.push((start, sequence.len(), *psms));
+        }
+        peptides.sort_unstable_by_key(|(start, length, _)| (*start, *length));
+        Self {
+            protein,
+            counts,
+            modifications,
+            peptides,
+        }
+    }
+
+    fn coverage(&self) -> f64 {
+        self.counts.iter().filter(|c| **c > 0).count() as f64 / self.counts.len().max(1) as f64
+    }
+
+    fn psms(&self) -> usize {
+        self.peptides.iter().map(|(_, _, psms)| psms.len()).sum()
+    }
+
+    fn render(&self, output: &mut String) {
+        let max = self.counts.iter().copied().max().unwrap_or_default();
+        write!(
+            output,
+            "<div class='protein-coverage' style='--max-count:{max};'><p class='title'><span class='id'>{}</span> <span class='description'>{}</span></p><p>Coverage: {:.1}%, PSMs: {}, Peptides: {}</p><div class='sequence'>",
+            html_builder::escape(&self.protein.id),
+            html_builder::escape(&self.protein.description),
+            self.coverage() * 100.0,
+            self.psms(),
+            self.peptides.len(),
+        )
+        .unwrap();
+        for (index, ((aa, count), modifications)) in self
+            .protein
+            .sequence
+            .chars()
+            .zip(&self.counts)
+            .zip(&self.modifications)
+            .enumerate()
+        {
+            write!(
+                output,
+                "<span class='aa{}{}' style='--count:{count};' title='Position: {}, PSMs: {count}{}'>{aa}</span>",
+                if *count > 0 { " covered" } else { "" },
+                if modifications.is_empty() {
+                    ""
+                } else {
+                    " modified"
+                },
+                index + 1,
+                if modifications.is_empty() {
+                    String::new()
+                } else {
+                    format!(
+                        ", Modifications: {}",
+                        html_builder::escape(&modifications.iter().join(", "))
+                    )
+                },
+            )
+            .unwrap();
+        }
+        write!(output, "</div><table class='peptides'><thead><tr><th>Position</th><th>Sequence</th><th>PSMs</th></tr></thead><tbody>").unwrap();
+        for (start, length, psms) in &self.peptides {
+            write!(
+                output,
+                "<tr><td>{}-{}</td><td>{}</td><td>{}</td></tr>",
+                start + 1,
+                start + length,
+                &self.protein.sequence[*start..start + length],
+                psms.iter()
+                    .map(|(file, index, _)| format!(
+                        "<a onclick=\"load_peptide({file}, {index})\">F{}:{index}</a>",
+                        file + 1
+                    ))
+                    .join(" ")
+            )
+            .unwrap();
+        }
+        write!(output, "</tbody></table></div>").unwrap();
+    }
+}
+
+/// Render the coverage of all proteins that are covered by at least one PSM, ordered by the
+/// number of PSMs. The filter is a case insensitive substring match on the protein id and
+/// description.
+#[tauri::command]
+pub async fn get_protein_coverage(
+    filter: &str,
+    amount: usize,
+    state: ModifiableState<'_>,
+) -> Result<String, &'static str> {
+    let state = state.lock().await;
+    if state.fasta.is_empty() {
+        return Err("No FASTA file is loaded");
+    }
+    let files = state.psm_files();
+    let groups = peptide_groups(&files);
+    let mut matches: HashMap<usize, Vec<(usize, &str, &PeptideGroup)>> = HashMap::new();
+    for (protein, start, sequence, psms) in groups
+        .par_iter()
+        .flat_map_iter(|(sequence, psms)| {
+            state
+                .fasta
+                .find(sequence.as_bytes())
+                .into_iter()
+                .map(move |(protein, start)| (protein, start, sequence.as_str(), psms))
+        })
+        .collect::<Vec<_>>()
+    {
+        matches
+            .entry(protein)
+            .or_default()
+            .push((start, sequence, psms));
+    }
+    let filter = filter.trim().to_lowercase();
+    let mut coverage = matches
+        .par_iter()
+        .map(|(protein, matches)| (&state.fasta.proteins[*protein], matches))
+        .filter(|(p, _)| {
+            filter.is_empty()
+                || p.id.to_lowercase().contains(&filter)
+                || p.description.to_lowercase().contains(&filter)
+        })
+        .map(|(p, matches)| ProteinCoverage::new(p, matches))
+        .collect::<Vec<_>>();
+    coverage.sort_unstable_by(|a, b| {
+        b.psms()
+            .cmp(&a.psms())
+            .then_with(|| a.protein.id.cmp(&b.protein.id))
+    });
+
+    let mut output = String::new();
+    for protein in coverage.iter().take(amount) {
+        protein.render(&mut output);
+    }
+    if output.is_empty() {
+        output.push_str("No proteins are covered by the open PSM files");
+    }
+    Ok(output)
+}
@@ -0,0 +1,376 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write,
+};
+
+use context_error::{BasicKind, BoxedError, CreateError, FullErrorContent};
+use itertools::Itertools;
+use mzannotate::{annotation::Score, prelude::*};
+use mzcore::{
+    chemistry::AmbiguousLabel,
+    prelude::*,
+    sequence::{
+        Linear, LinkerSpecificity, PlacementRule, ReturnModification, SimpleModificationInner,
+    },
+    system::{e, isize::Charge},
+};
+use mzdata::prelude::SpectrumLike;
+
+use crate::{
+    ModifiableState, compare::parse_linear, mass_offset::allowed, render::display_sequence_index,
+    spectra::NoiseFilter,
+};
+
+/// The maximal number of site pairs that are annotated
+const MAXIMAL_SITE_PAIRS: usize = 500;
+/// The maximal number of site pairs shown
+const MAXIMAL_ROWS: usize = 50;
+
+/// The support for a single pair of cross-link sites
+struct SiteScore {
+    sites: (SequencePosition, SequencePosition),
+    definition: String,
+    /// The number of peaks annotated with a fragment containing the intact cross-link
+    cross_linked: usize,
+    /// The number of peaks annotated with a fragment with a stub of a cleaved cross-linker
+    stubs: usize,
+    /// The number of fragments observed with at least two different stubs on different peaks
+    doublets: usize,
+    intensity: f64,
+    fragments: String,
+}
+
+/// Get all positions in the peptidoform that are allowed by any of the rules
+fn sites(peptidoform: &Peptidoform<Linear>, rules: &[PlacementRule]) -> Vec<SequencePosition> {
+    std::iter::once(SequencePosition::NTerm)
+        .chain((0..peptidoform.len()).map(SequencePosition::Index))
+        .chain(std::iter::once(SequencePosition::CTerm))
+        .filter(|position| {
+            rules
+                .iter()
+                .any(|rule| allowed(rule, peptidoform, *position))
+        })
+        .collect()
+}
+
+/// Write the peptidoform in ProForma with the given modification texts added at their positions,
+/// returns None if a terminal position already contains a modification.
+pub(crate) fn with_cross_links(
+    peptidoform: &Peptidoform<Linear>,
+    tags: &[(SequencePosition, String)],
+) -> Option<String> {
+    let at = |position: SequencePosition| {
+        tags.iter()
+            .filter(move |(p, _)| *p == position)
+            .map(|(_, tag)| format!("[{tag}]"))
+            .join("")
+    };
+    let mut text = String::new();
+    let n_term = at(SequencePosition::NTerm);
+    if !n_term.is_empty() {
+        if !peptidoform.get_n_term().is_empty() {
+            return None;
+        }
+        write!(text, "{n_term}-").unwrap();
+    }
+    for index in 0..peptidoform.len() {
+        peptidoform
+            .sub_peptidoform(index..index + 1)?
+            .display(&mut text, index == 0, index + 1 == peptidoform.len(), true)
+            .ok()?;
+        text.push_str(&at(SequencePosition::Index(index)));
+    }
+    let c_term = at(SequencePosition::CTerm);
+    if !c_term.is_empty() {
+        if !peptidoform.get_c_term().is_empty() {
+            return None;
+        }
+        write!(text, "-{c_term}").unwrap();
+    }
+    Some(text)
+}
+
+/// Check if this fragment contains an intact cross-link
+pub(crate) fn is_cross_linked(fragment: &Fragment) -> bool {
+    fragment
+        .formula
+        .iter()
+        .flat_map(|f| f.labels())
+        .any(|l| matches!(l, AmbiguousLabel::CrossLinkBound(_)))
+}
+
+/// Search the cross-link sites between two peptides for the given linker on the selected spectrum.
+/// All site pairs allowed by the specificities of the linker are enumerated and annotated. The
+/// site pairs are ranked on the number of peaks with a fragment that contains the intact
+/// cross-link, then on the number of stub doublets of cleavable linkers (the same fragment
+/// observed with two different stubs on different peaks), and lastly on the explained intensity.
+/// Site pairs that cannot be annotated are listed with the reason.
+#[tauri::command]
+pub async fn search_cross_link_sites(
+    first: &str,
+    second: &str,
+    linker: &str,
+    tolerance: (f64, &str),
+    charge: Option<isize>,
+    noise_filter: (NoiseFilter, f32),
+    model: usize,
+    mass_mode: &str,
+    mz_range: (Option<f64>, Option<f64>),
+    isotopes: bool,
+    isotope_filter: f64,
+    state: ModifiableState<'_>,
+) -> Result<String, String> {
+    let mut state = state.lock().await;
+    let peptidoforms = [
+        parse_linear(first, &state.ontologies)?,
+        parse_linear(second, &state.ontologies)?,
+    ];
+    let modification = SimpleModificationInner::pro_forma(
+        linker,
+        &mut Vec::new(),
+        &mut Vec::new(),
+        &state.ontologies,
+    )
+    .map_err(|err| {
+        BoxedError::small(
+            BasicKind::Error,
+            "Invalid linker",
+            "Could not parse the linker",
+        )
+        .add_underlying_errors(err)
+        .to_html(false)
+    })
+    .and_then(|((m, _), _)| match m {
+        ReturnModification::Defined(d) => Ok(d),
+        _ => Err(BoxedError::small(
+            BasicKind::Error,
+            "Invalid linker",
+            "The linker cannot be an ambiguous modification",
+        )
+        .to_html(false)),
+    })?;
+    let SimpleModificationInner::Linker { specificities, .. } = &*modification else {
+        return Err(BoxedError::small(
+            BasicKind::Error,
+            "Invalid linker",
+            format!("The modification '{modification}' is not a cross-linker"),
+        )
+        .to_html(false));
+    };
+
+    // Enumerate all site pairs, asymmetric linkers can bind in both orientations
+    let mut pairs = Vec::new();
+    for specificity in specificities {
+        let orientations = match specificity {
+            LinkerSpecificity::Symmetric { rules, .. } => vec![(rules, rules)],
+            LinkerSpecificity::Asymmetric {
+                rules: (left, right),
+                ..
+            } => vec![(left, right), (right, left)],
+        };
+        for (left, right) in orientations {
+            pairs.extend(
+                sites(&peptidoforms[0], left)
+                    .into_iter()
+                    .cartesian_product(sites(&peptidoforms[1], right)),
+            );
+        }
+    }
+    let pairs = pairs.into_iter().unique().collect_vec();
+    if pairs.is_empty() {
+        return Err(BoxedError::small(
+            BasicKind::Error,
+            "No cross-link sites",
+            "The peptides do not contain any site pair allowed by the linker",
+        )
+        .to_html(false));
+    }
+    if pairs.len() > MAXIMAL_SITE_PAIRS {
+        return Err(BoxedError::small(
+            BasicKind::Error,
+            "Too many cross-link sites",
+            format!(
+                "There are {} site pairs but at most {MAXIMAL_SITE_PAIRS} can be annotated",
+                pairs.len()
+            ),
+        )
+        .to_html(false));
+    }
+
+    let (_, spectrum) = crate::spectra::create_selected_spectrum(&mut state, noise_filter)
+        .map_err(|err| err.to_html(false))?;
+    let model = crate::model::get_models(&state)
+        .1
+        .get(model)
+        .map(|m| m.2.clone())
+        .ok_or_else(|| {
+            BoxedError::small(BasicKind::Error, "Invalid model", "Model does not exist")
+                .to_html(false)
+        })?;
+    let parameters = crate::model::parameters(tolerance, mz_range, isotopes, isotope_filter)
+        .map_err(|err| err.to_html(false))?;
+    let mass_mode = crate::model::mass_mode(mass_mode).map_err(|err| err.to_html(false))?;
+    let charge = Charge::new::<e>(
+        charge
+            .or_else(|| {
+                spectrum
+                    .precursor()
+                    .and_then(|p| p.ions.first().and_then(|i| i.charge.map(|c| c as isize)))
+            })
+            .unwrap_or(1),
+    );
+
+    // Annotate every site pair, the pairs that cannot be annotated are reported
+    let mut scores = Vec::new();
+    let mut skipped = Vec::new();
+    for (first_site, second_site) in pairs {
+        let (Some(first_text), Some(second_text)) = (
+            with_cross_links(&peptidoforms[0], &[(first_site, format!("{linker}#XL1"))]),
+            with_cross_links(&peptidoforms[1], &[(second_site, "#XL1".to_string())]),
+        ) else {
+            skipped.push((
+                (first_site, second_site),
+                "the terminus already has a modification",
+            ));
+            continue;
+        };
+        let definition = format!("{first_text}//{second_text}");
+        let Ok((peptidoform_ion_set, _)) =
+            PeptidoformIonSet::pro_forma(&definition, &state.ontologies)
+        else {
+            skipped.push((
+                (first_site, second_site),
+                "the cross-linked peptidoform is invalid",
+            ));
+            continue;
+        };
+        let fragments = peptidoform_ion_set.generate_theoretical_fragments(charge, &model);
+        let annotated =
+            spectrum
+                .clone()
+                .annotate(peptidoform_ion_set, &fragments, &parameters, mass_mode);
+        let mut cross_linked = 0;
+        let mut stubs = 0;
+        let mut stub_variants: HashMap<(Option<usize>, String, isize), HashSet<(usize, String)>> =
+            HashMap::new();
+        for (peak_index, peak) in annotated.peaks.iter().enumerate() {
+            if peak.annotations.iter().any(is_cross_linked) {
+                cross_linked += 1;
+            }
+            let mut stub_peak = false;
+            for annotation in &peak.annotations {
+                let broken = annotation
+                    .formula
+                    .iter()
+                    .flat_map(|f| f.labels())
+                    .filter_map(|l| match l {
+                        AmbiguousLabel::CrossLinkBroken(_, stub) => Some(stub.hill_notation()),
+                        _ => None,
+                    })
+                    .join(",");
+                if !broken.is_empty() {
+                    stub_peak = true;
+                    let label = annotation.ion.label().1.to_string();
+                    stub_variants
+                        .entry((
+                            annotation.peptidoform_index,
+                            annotation
+                                .ion
+                                .position()
+                                .map_or(label.clone(), |p| format!("{label}{}", p.series_number)),
+                            annotation.charge.value,
+                        ))
+                        .or_default()
+                        .insert((peak_index, broken));
+                }
+            }
+            if stub_peak {
+                stubs += 1;
+            }
+        }
+        let (scores_all, _) = annotated.scores(&fragments, &parameters, mass_mode);
+        let (found, intensity) = match scores_all.score {
+            Score::Position {
+                fragments,
+                intensity,
+                ..
+            }
+            | Score::UniqueFormulas {
+                fragments,
+                intensity,
+                ..
+            } => (fragments, intensity),
+        };
+        scores.push(SiteScore {
+            sites: (first_site, second_site),
+            definition,
+            cross_linked,
+            stubs,
+            doublets: stub_variants
+                .values()
+                .filter(|v| {
+                    v.iter()
+                        .tuple_combinations()
+                        .any(|(a, b)| a.0 != b.0 && a.1 != b.1)
+                })
+                .count(),
+            intensity: intensity.fraction(),
+            fragments: format!("{}/{}", found.found, found.total),
+        });
+    }
+    scores.sort_by(|a, b| {
+        b.cross_linked
+            .cmp(&a.cross_linked)
+            .then(b.doublets.cmp(&a.doublets))
+            .then(b.intensity.total_cmp(&a.intensity))
+    });
+
+    let residue = |peptidoform: &Peptidoform<Linear>, position: SequencePosition| match position {
+        SequencePosition::Index(i) => format!(
+            "{}{}",
+            peptidoform.sequence()[i].aminoacid.pro_forma_definition(),
+            display_sequence_index(position)
+        ),
+        _ => display_sequence_index(position),
+    };
+    let mut output = String::new();
+    if !skipped.is_empty() {
+        write!(
+            output,
+            "<p>{} site pairs could not be annotated: {}</p>",
+            skipped.len(),
+            skipped
+                .iter()
+                .map(|((first, second), reason)| format!(
+                    "{}–{} ({reason})",
+                    residue(&peptidoforms[0], *first),
+                    residue(&peptidoforms[1], *second),
+                ))
+                .join(", ")
+        )
+        .unwrap();
+    }
+    write!(
+        output,
+        "<table class='cross-link-search'><thead><tr><th>Rank</th><th>First site</th><th>Second site</th><th>Peaks with cross-linked fragments</th><th>Peaks with stub fragments</th><th>Stub doublets</th><th>Explained intensity</th><th>Fragments found</th><th>Peptidoform</th></tr></thead><tbody>"
+    )
+    .unwrap();
+    for (rank, score) in scores.iter().take(MAXIMAL_ROWS).enumerate() {
+        write!(
+            output,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{:.2}%</td><td>{}</td><td><span class='peptidoform'>{}</span></td></tr>",
+            rank + 1,
+            residue(&peptidoforms[0], score.sites.0),
+            residue(&peptidoforms[1], score.sites.1),
+            score.cross_linked,
+            score.stubs,
+            score.doublets,
+            score.intensity * 100.0,
+            score.fragments,
+            score.definition,
+        )
+        .unwrap();
+    }
+    write!(output, "</tbody></table>").unwrap();
+    Ok(output)
+}
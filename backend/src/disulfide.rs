@@ -0,0 +1,372 @@
+use std::{collections::HashSet, fmt::Write};
+
+use context_error::{BasicKind, BoxedError, Context, CreateError, FullErrorContent};
+use itertools::Itertools;
+use mzannotate::{annotation::Score, prelude::*};
+use mzcore::{
+    molecular_formula,
+    prelude::*,
+    quantities::WithinTolerance,
+    system::{MassOverCharge, e, isize::Charge, thomson},
+};
+use mzdata::prelude::SpectrumLike;
+
+use crate::{
+    ModifiableState,
+    compare::parse_linear,
+    cross_link_search::{is_cross_linked, with_cross_links},
+    spectra::NoiseFilter,
+};
+
+/// The PSI-MOD accession of a disulfide bond, L-cystine (cross link)
+const DISULFIDE: &str = "MOD:00034";
+/// The maximal number of connectivity patterns that are annotated
+const MAXIMAL_PATTERNS: usize = 500;
+/// The maximal number of connectivity patterns shown
+const MAXIMAL_ROWS: usize = 25;
+
+/// A cysteine as (peptide index, residue index)
+type Cysteine = (usize, usize);
+
+/// The support for a single disulfide connectivity pattern
+struct PatternScore {
+    bonds: Vec<(Cysteine, Cysteine)>,
+    definition: String,
+    /// The number of peaks annotated with a fragment containing an intact disulfide
+    cross_linked: usize,
+    /// The found and total number of S–S cleavage products
+    cleavage: (usize, usize),
+    /// The number of bonds that do not separate the molecule when broken, for example intra-chain
+    /// bonds, these do not have cleavage products
+    unseparated: usize,
+    intensity: f64,
+    fragments: String,
+}
+
+/// Enumerate all ways to pair the available cysteines into the given number of bonds
+fn matchings(
+    available: &[Cysteine],
+    bonds: usize,
+    current: &mut Vec<(Cysteine, Cysteine)>,
+    output: &mut Vec<Vec<(Cysteine, Cysteine)>>,
+) {
+    if output.len() > MAXIMAL_PATTERNS {
+        return;
+    }
+    if bonds == 0 {
+        output.push(current.clone());
+        return;
+    }
+    if available.len() < 2 * bonds {
+        return;
+    }
+    let (first, rest) = (available[0], &available[1..]);
+    // The first cysteine is free
+    matchings(rest, bonds, current, output);
+    // The first cysteine is bonded to any of the others
+    for (index, other) in rest.iter().enumerate() {
+        let remaining = rest
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != index)
+            .map(|(_, c)| *c)
+            .collect_vec();
+        current.push((first, *other));
+        matchings(&remaining, bonds - 1, current, output);
+        current.pop();
+    }
+}
+
+/// Get the connected groups of peptides given the bonds
+fn components(peptides: usize, bonds: &[(Cysteine, Cysteine)]) -> Vec<Vec<usize>> {
+    fn root(group: &mut [usize], index: usize) -> usize {
+        if group[index] == index {
+            index
+        } else {
+            let r = root(group, group[index]);
+            group[index] = r;
+            r
+        }
+    }
+    let mut group = (0..peptides).collect_vec();
+    for ((a, _), (b, _)) in bonds {
+        let (ra, rb) = (root(&mut group, *a), root(&mut group, *b));
+        group[ra] = rb;
+    }
+    (0..peptides)
+        .map(|i| (root(&mut group, i), i))
+        .into_group_map()
+        .into_values()
+        .sorted()
+        .collect()
+}
+
+/// The name of a cysteine, with the peptide number if there are multiple peptides
+fn cysteine_name(cysteine: Cysteine, multiple: bool) -> String {
+    if multiple {
+        format!("p{} C{}", cysteine.0 + 1, cysteine.1 + 1)
+    } else {
+        format!("C{}", cysteine.1 + 1)
+    }
+}
+
+/// Map the disulfide bonds in one or more peptides (separated by `//`) on the selected spectrum.
+/// All connectivity patterns of the free cysteines with the given number of bonds (by default as
+/// many as possible) that connect all peptides are annotated. Next to the fragments with an intact
+/// disulfide the products of S–S cleavage (typical for ETD and EAD) are matched: when breaking a
+/// single bond separates the molecule, the separated parts with one hydrogen less, equal, or one
+/// hydrogen more than the reduced form at all charges up to the precursor charge. Bonds that do
+/// not separate the molecule when broken, like intra-chain bonds, have no cleavage products so
+/// these are only supported by the fragments with an intact disulfide. The patterns are ranked on
+/// the sum of peaks with cross-linked fragments and observed cleavage products.
+#[tauri::command]
+pub async fn map_disulfide_bonds(
+    peptides: &str,
+    bonds: Option<usize>,
+    tolerance: (f64, &str),
+    charge: Option<isize>,
+    noise_filter: (NoiseFilter, f32),
+    model: usize,
+    mass_mode: &str,
+    mz_range: (Option<f64>, Option<f64>),
+    isotopes: bool,
+    isotope_filter: f64,
+    state: ModifiableState<'_>,
+) -> Result<String, String> {
+    let mut state = state.lock().await;
+    let peptidoforms = peptides
+        .split("//")
+        .map(|p| parse_linear(p.trim(), &state.ontologies))
+        .collect::<Result<Vec<_>, _>>()?;
+    let multiple = peptidoforms.len() > 1;
+    let cysteines = peptidoforms
+        .iter()
+        .enumerate()
+        .flat_map(|(peptide, p)| {
+            p.sequence()
+                .iter()
+                .enumerate()
+                .filter(|(_, s)| {
+                    s.aminoacid.pro_forma_definition() == "C" && s.modifications.is_empty()
+                })
+                .map(move |(index, _)| (peptide, index))
+        })
+        .collect_vec();
+    let bonds = bonds.unwrap_or(cysteines.len() / 2);
+    if bonds == 0 || cysteines.len() < 2 * bonds {
+        return Err(BoxedError::new(
+            BasicKind::Error,
+            "Not enough cysteines",
+            format!(
+                "There are {} unmodified cysteines, which is not enough to form {} disulfide bonds",
+                cysteines.len(),
+                bonds.max(1)
+            ),
+            Context::full_line(0, peptides),
+        )
+        .to_html(false));
+    }
+    let mut patterns = Vec::new();
+    matchings(&cysteines, bonds, &mut Vec::new(), &mut patterns);
+    if patterns.len() > MAXIMAL_PATTERNS {
+        return Err(BoxedError::small(
+            BasicKind::Error,
+            "Too many connectivity patterns",
+            format!(
+                "There are more than {MAXIMAL_PATTERNS} connectivity patterns, split the peptides or set the number of bonds"
+            ),
+        )
+        .to_html(false));
+    }
+    patterns.retain(|p| components(peptidoforms.len(), p).len() == 1);
+    if patterns.is_empty() {
+        return Err(BoxedError::small(
+            BasicKind::Error,
+            "No connectivity patterns",
+            "There is no pattern with this number of bonds that connects all peptides",
+        )
+        .to_html(false));
+    }
+
+    let (_, spectrum) = crate::spectra::create_selected_spectrum(&mut state, noise_filter)
+        .map_err(|err| err.to_html(false))?;
+    let model = crate::model::get_models(&state)
+        .1
+        .get(model)
+        .map(|m| m.2.clone())
+        .ok_or_else(|| {
+            BoxedError::small(BasicKind::Error, "Invalid model", "Model does not exist")
+                .to_html(false)
+        })?;
+    let parameters = crate::model::parameters(tolerance, mz_range, isotopes, isotope_filter)
+        .map_err(|err| err.to_html(false))?;
+    let mass_mode = crate::model::mass_mode(mass_mode).map_err(|err| err.to_html(false))?;
+    let charge = charge
+        .or_else(|| {
+            spectrum
+                .precursor()
+                .and_then(|p| p.ions.first().and_then(|i| i.charge.map(|c| c as isize)))
+        })
+        .unwrap_or(1)
+        .max(1);
+    let proton = molecular_formula!(H 1 Electron -1).mass(mass_mode).value;
+    let hydrogen = molecular_formula!(H 1).mass(mass_mode).value;
+    let masses = peptidoforms
+        .iter()
+        .map(|p| {
+            p.formulas()
+                .first()
+                .map_or(0.0, |f| f.mass(mass_mode).value)
+        })
+        .collect_vec();
+    let peaks = spectrum
+        .peaks
+        .as_ref()
+        .map(|p| p.peaks.iter().map(|p| p.mz).collect_vec())
+        .unwrap_or_default();
+    let observed = |mz: f64| {
+        peaks.iter().any(|p| {
+            parameters.tolerance.within(
+                &MassOverCharge::new::<thomson>(*p),
+                &MassOverCharge::new::<thomson>(mz),
+            )
+        })
+    };
+
+    // Annotate every pattern
+    let mut scores = Vec::new();
+    for pattern in patterns {
+        let Some(texts) = peptidoforms
+            .iter()
+            .enumerate()
+            .map(|(peptide, peptidoform)| {
+                let tags = pattern
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(bond, (a, b))| {
+                        [
+                            (*a, format!("{DISULFIDE}#XL{}", bond + 1)),
+                            (*b, format!("#XL{}", bond + 1)),
+                        ]
+                    })
+                    .filter(|((p, _), _)| *p == peptide)
+                    .map(|((_, index), tag)| (SequencePosition::Index(index), tag))
+                    .collect_vec();
+                with_cross_links(peptidoform, &tags)
+            })
+            .collect::<Option<Vec<_>>>()
+        else {
+            continue;
+        };
+        let definition = texts.join("//");
+        let Ok((peptidoform_ion_set, _)) =
+            PeptidoformIonSet::pro_forma(&definition, &state.ontologies)
+        else {
+            continue;
+        };
+        let fragments =
+            peptidoform_ion_set.generate_theoretical_fragments(Charge::new::<e>(charge), &model);
+        let annotated =
+            spectrum
+                .clone()
+                .annotate(peptidoform_ion_set, &fragments, &parameters, mass_mode);
+        let cross_linked = annotated
+            .peaks
+            .iter()
+            .filter(|p| p.annotations.iter().any(is_cross_linked))
+            .count();
+
+        // The products of breaking a single S–S bond that separates the molecule
+        let mut cleavage = HashSet::new();
+        let mut unseparated = 0;
+        for broken in 0..pattern.len() {
+            let remaining = pattern
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| *i != broken)
+                .map(|(_, b)| *b)
+                .collect_vec();
+            let parts = components(peptidoforms.len(), &remaining);
+            if parts.len() < 2 {
+                unseparated += 1;
+                continue;
+            }
+            for part in parts {
+                let mass = part.iter().map(|p| masses[*p]).sum::<f64>()
+                    - 2.0
+                        * hydrogen
+                        * remaining
+                            .iter()
+                            .filter(|((a, _), _)| part.contains(a))
+                            .count() as f64;
+                for offset in [-hydrogen, 0.0, hydrogen] {
+                    for z in 1..=charge {
+                        let mz = (mass + offset + z as f64 * proton) / z as f64;
+                        cleavage.insert(((mz * 1e4).round() as i64, observed(mz)));
+                    }
+                }
+            }
+        }
+
+        let (scores_all, _) = annotated.scores(&fragments, &parameters, mass_mode);
+        let (found, intensity) = match scores_all.score {
+            Score::Position {
+                fragments,
+                intensity,
+                ..
+            }
+            | Score::UniqueFormulas {
+                fragments,
+                intensity,
+                ..
+            } => (fragments, intensity),
+        };
+        scores.push(PatternScore {
+            bonds: pattern,
+            definition,
+            cross_linked,
+            cleavage: (cleavage.iter().filter(|(_, o)| *o).count(), cleavage.len()),
+            unseparated,
+            intensity: intensity.fraction(),
+            fragments: format!("{}/{}", found.found, found.total),
+        });
+    }
+    scores.sort_by(|a, b| {
+        (b.cross_linked + b.cleavage.0)
+            .cmp(&(a.cross_linked + a.cleavage.0))
+            .then(b.intensity.total_cmp(&a.intensity))
+    });
+
+    let mut output = String::new();
+    write!(
+        output,
+        "<table class='disulfide-bonds'><thead><tr><th>Rank</th><th>Disulfide bonds</th><th>Peaks with cross-linked fragments</th><th>S–S cleavage products</th><th>Bonds without cleavage products</th><th>Explained intensity</th><th>Fragments found</th><th>Peptidoform</th></tr></thead><tbody>"
+    )
+    .unwrap();
+    for (rank, score) in scores.iter().take(MAXIMAL_ROWS).enumerate() {
+        write!(
+            output,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}/{}</td><td>{}</td><td>{:.2}%</td><td>{}</td><td><span class='peptidoform'>{}</span></td></tr>",
+            rank + 1,
+            score
+                .bonds
+                .iter()
+                .map(|(a, b)| format!(
+                    "{}–{}",
+                    cysteine_name(*a, multiple),
+                    cysteine_name(*b, multiple)
+                ))
+                .join(", "),
+            score.cross_linked,
+            score.cleavage.0,
+            score.cleavage.1,
+            score.unseparated,
+            score.intensity * 100.0,
+            score.fragments,
+            score.definition,
+        )
+        .unwrap();
+    }
+    write!(output, "</tbody></table>").unwrap();
+    Ok(output)
+}
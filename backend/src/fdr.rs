@@ -0,0 +1,239 @@
+use itertools::Itertools;
+use mzcore::sequence::Linked;
+use mzident::{MaybePeptidoform, PSM, PSMData, PSMMetaData};
+use serde::{Deserialize, Serialize};
+
+use crate::{ModifiableState, html_builder};
+
+/// The score used to rank PSMs for the target–decoy FDR estimation
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub enum FdrScore {
+    /// The normalised score as given by the annotator
+    #[default]
+    Score,
+    /// The original score as given by the search engine
+    OriginalScore,
+    /// The absolute precursor mass error in ppm
+    MassError,
+}
+
+impl FdrScore {
+    fn get(self, psm: &PSM<Linked, MaybePeptidoform>) -> Option<f64> {
+        match self {
+            Self::Score => psm.score,
+            Self::OriginalScore => psm.original_confidence().map(|(score, _)| score),
+            Self::MassError => psm.ppm_error().map(|ppm| ppm.value.abs() * 1e6),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FdrSettings {
+    /// A PSM is a decoy if all its proteins start with any of these prefixes (case insensitive),
+    /// if the search engine reports decoys this is used instead
+    pub decoy_prefixes: Vec<String>,
+    pub score: FdrScore,
+    pub lower_is_better: bool,
+}
+
+/// The decoy flag as given by the search engine, for formats that report it
+fn engine_decoy(psm: &PSM<Linked, MaybePeptidoform>) -> Option<bool> {
+    match &psm.data {
+        PSMData::Sage(data) => Some(data.decoy),
+        PSMData::PLink(data) => Some(data.is_decoy),
+        _ => None,
+    }
+}
+
+/// Calculate the q-value for every PSM with a score. PSMs with the same score are accepted or
+/// rejected together, so they are counted as one group and all get the same q-value.
+fn q_values(scores: &[Option<f64>], decoy: &[bool], lower_is_better: bool) -> Vec<Option<f64>> {
+    // Rank all PSMs with a score from best to worst
+    let mut ranked = scores
+        .iter()
+        .enumerate()
+        .filter_map(|(index, score)| score.map(|score| (index, score)))
+        .collect_vec();
+    ranked.sort_unstable_by(|a, b| {
+        if lower_is_better {
+            a.1.total_cmp(&b.1)
+        } else {
+            b.1.total_cmp(&a.1)
+        }
+    });
+
+    let mut targets = 0_usize;
+    let mut decoys = 0_usize;
+    let groups = ranked
+        .chunk_by(|a, b| a.1.total_cmp(&b.1).is_eq())
+        .map(|group| {
+            for (index, _) in group {
+                if decoy[*index] {
+                    decoys += 1;
+                } else {
+                    targets += 1;
+                }
+            }
+            (group, decoys as f64 / targets.max(1) as f64)
+        })
+        .collect_vec();
+
+    // The q-value is the minimal FDR at which this PSM is accepted
+    let mut q_values = vec![None; scores.len()];
+    let mut minimum = f64::INFINITY;
+    for (group, fdr) in groups.into_iter().rev() {
+        minimum = minimum.min(fdr).min(1.0);
+        for (index, _) in group {
+            q_values[*index] = Some(minimum);
+        }
+    }
+    q_values
+}
+
+/// The result of the target–decoy FDR estimation for a single PSM file
+#[derive(Clone, Debug)]
+pub struct FdrResult {
+    pub settings: FdrSettings,
+    /// For every PSM if it is a decoy
+    pub decoy: Vec<bool>,
+    /// For every PSM the q-value, if the PSM has the selected score
+    pub q_values: Vec<Option<f64>>,
+}
+
+impl FdrResult {
+    pub fn new(peptides: &[PSM<Linked, MaybePeptidoform>], settings: FdrSettings) -> Self {
+        let prefixes = settings
+            .decoy_prefixes
+            .iter()
+            .map(|p| p.trim().to_lowercase())
+            .filter(|p| !p.is_empty())
+            .collect_vec();
+        let decoy = peptides
+            .iter()
+            .map(|psm| {
+                engine_decoy(psm).unwrap_or_else(|| {
+                    !psm.proteins().is_empty()
+                        && psm.proteins().iter().all(|p| {
+                            let name = p.id().name().to_lowercase();
+                            prefixes.iter().any(|prefix| name.starts_with(prefix))
+                        })
+                })
+            })
+            .collect_vec();
+        let scores = peptides
+            .iter()
+            .map(|psm| settings.score.get(psm))
+            .collect_vec();
+        let q_values = q_values(&scores, &decoy, settings.lower_is_better);
+
+        Self {
+            settings,
+            decoy,
+            q_values,
+        }
+    }
+
+    /// Check if the PSM is a target with a q-value at or below the given FDR
+    pub fn passes(&self, index: usize, maximal_fdr: f64) -> bool {
+        !self.decoy.get(index).copied().unwrap_or_default()
+            && self
+                .q_values
+                .get(index)
+                .copied()
+                .flatten()
+                .is_some_and(|q| q <= maximal_fdr)
+    }
+
+    fn render_summary(&self) -> String {
+        let targets = self.decoy.iter().filter(|d| !**d).count();
+        let decoys = self.decoy.len() - targets;
+        let mut summary = format!(
+            "<p>Targets: {targets}, Decoys: {decoys}, Without score: {}</p>",
+            self.q_values.iter().filter(|q| q.is_none()).count()
+        );
+        summary.push_str(
+            &html_builder::HtmlElement::table(
+                Some(&["FDR", "Accepted targets"]),
+                [0.001, 0.01, 0.05, 0.1].iter().map(|fdr| {
+                    [
+                        format!("{:.1}%", fdr * 100.0),
+                        (0..self.decoy.len())
+                            .filter(|index| self.passes(*index, *fdr))
+                            .count()
+                            .to_string(),
+                    ]
+                }),
+            )
+            .to_string(),
+        );
+        summary
+    }
+}
+
+/// Estimate the FDR with the target–decoy approach for the given PSM file and store the q-values
+/// so that the PSMs can be filtered on FDR.
+#[tauri::command]
+pub async fn compute_fdr(
+    file: usize,
+    settings: FdrSettings,
+    state: ModifiableState<'_>,
+) -> Result<String, &'static str> {
+    let state = state.lock().await;
+    let mut files = state.psm_files_mut();
+    let file = files
+        .iter_mut()
+        .find(|f| f.id == file)
+        .ok_or("PSM file index not valid")?;
+    let result = FdrResult::new(&file.peptides, settings);
+    if !result.decoy.iter().any(|d| *d) {
+        return Err("No decoys could be found, check the decoy prefixes");
+    }
+    let summary = result.render_summary();
+    file.set_fdr(result);
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::q_values;
+
+    #[test]
+    fn q_values_ranked() {
+        let scores = [Some(0.9), Some(0.8), Some(0.7), Some(0.6), None];
+        let decoy = [false, true, false, false, true];
+        assert_eq!(
+            q_values(&scores, &decoy, false),
+            vec![
+                Some(0.0),
+                Some(1.0 / 3.0),
+                Some(1.0 / 3.0),
+                Some(1.0 / 3.0),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn q_values_ties() {
+        // The target and decoy with the same score are accepted together, independent of their order
+        let scores = [Some(0.9), Some(0.5), Some(0.5)];
+        assert_eq!(
+            q_values(&scores, &[false, false, true], false),
+            vec![Some(0.0), Some(0.5), Some(0.5)]
+        );
+        assert_eq!(
+            q_values(&scores, &[false, true, false], false),
+            vec![Some(0.0), Some(0.5), Some(0.5)]
+        );
+    }
+
+    #[test]
+    fn q_values_lower_is_better() {
+        let scores = [Some(1.0), Some(5.0), Some(2.0)];
+        let decoy = [false, false, true];
+        assert_eq!(
+            q_values(&scores, &decoy, true),
+            vec![Some(0.0), Some(0.5), Some(0.5)]
+        );
+    }
+}
@@ -0,0 +1,359 @@
+use std::{collections::HashSet, fmt::Write};
+
+use context_error::{BasicKind, BoxedError, Context, CreateError, FullErrorContent};
+use itertools::Itertools;
+use mzannotate::{fragment::FragmentType, prelude::*};
+use mzcore::{
+    prelude::*,
+    quantities::Tolerance,
+    sequence::{
+        GnoComposition, Linear, SimpleModification, SimpleModificationInner,
+        modification_search_mass,
+    },
+    system::{Mass, dalton, e, isize::Charge},
+};
+use mzdata::prelude::SpectrumLike;
+
+use crate::{
+    ModifiableState, Theme,
+    compare::parse_linear,
+    mass_offset::{closest, place, precursor_offset},
+    render::{display_sequence_index, link_modification, render_full_glycan},
+    spectra::NoiseFilter,
+};
+
+/// The maximal number of glycan candidates that are annotated
+const MAXIMAL_CANDIDATES: usize = 200;
+/// The maximal number of glycan candidates shown
+const MAXIMAL_ROWS: usize = 25;
+
+/// The support for a single glycan candidate
+struct GlycanScore {
+    modification: SimpleModification,
+    /// Found and total number of unique oxonium ions
+    oxonium: (usize, usize),
+    /// Found and total number of unique Y ions
+    y: (usize, usize),
+    /// The fraction of the total intensity explained by glycan fragments
+    glycan_intensity: f64,
+    /// The fraction of the total intensity explained by all fragments
+    intensity: f64,
+}
+
+/// Get all possible glycosylation sites with the reason, from most to least likely: all N in a
+/// sequon (N-X-S/T with X not P), all S and T, and all other N.
+fn glycosylation_sites(peptidoform: &Peptidoform<Linear>) -> Vec<(usize, &'static str)> {
+    let residues = peptidoform
+        .sequence()
+        .iter()
+        .map(|s| s.aminoacid.pro_forma_definition().to_string())
+        .collect_vec();
+    let sequon = |i: usize| {
+        residues[i] == "N"
+            && residues.get(i + 1).is_some_and(|r| r != "P")
+            && residues.get(i + 2).is_some_and(|r| r == "S" || r == "T")
+    };
+    let mut sites = (0..residues.len())
+        .filter(|i| sequon(*i))
+        .map(|i| (i, "N in a sequon"))
+        .collect_vec();
+    sites.extend(
+        (0..residues.len())
+            .filter(|i| residues[*i] == "S" || residues[*i] == "T")
+            .map(|i| (i, "S or T")),
+    );
+    sites.extend(
+        (0..residues.len())
+            .filter(|i| residues[*i] == "N" && !sequon(*i))
+            .map(|i| (i, "N outside a sequon")),
+    );
+    sites
+}
+
+/// Infer the glycan on a peptide backbone from the selected spectrum. All GNOme compositions and
+/// topologies that fit the mass delta (or the precursor mass offset if no delta is given) within
+/// the mass tolerance (in Dalton) are placed on the most likely glycosylation site and annotated
+/// on the spectrum, the other possible sites are listed as the chosen site is a guess. The candidates are ranked on the intensity explained by oxonium and Y ions,
+/// and the best supported structure is shown.
+#[tauri::command]
+pub async fn infer_glycan(
+    peptide: &str,
+    delta: Option<f64>,
+    mass_tolerance: f64,
+    tolerance: (f64, &str),
+    charge: Option<isize>,
+    noise_filter: (NoiseFilter, f32),
+    model: usize,
+    mass_mode: &str,
+    mz_range: (Option<f64>, Option<f64>),
+    isotopes: bool,
+    isotope_filter: f64,
+    theme: Theme,
+    state: ModifiableState<'_>,
+) -> Result<String, String> {
+    let mut state = state.lock().await;
+    let peptidoform = parse_linear(peptide, &state.ontologies)?;
+    let sites = glycosylation_sites(&peptidoform);
+    let (site, reason) = *sites.first().ok_or_else(|| {
+        BoxedError::new(
+            BasicKind::Error,
+            "No glycosylation site",
+            "The peptide backbone does not contain an N, S, or T to place the glycan on",
+            Context::full_line(0, peptide),
+        )
+        .to_html(false)
+    })?;
+    let (_, spectrum) = crate::spectra::create_selected_spectrum(&mut state, noise_filter)
+        .map_err(|err| err.to_html(false))?;
+    let model = crate::model::get_models(&state)
+        .1
+        .get(model)
+        .map(|m| m.2.clone())
+        .ok_or_else(|| {
+            BoxedError::small(BasicKind::Error, "Invalid model", "Model does not exist")
+                .to_html(false)
+        })?;
+    let parameters = crate::model::parameters(tolerance, mz_range, isotopes, isotope_filter)
+        .map_err(|err| err.to_html(false))?;
+    let mass_mode = crate::model::mass_mode(mass_mode).map_err(|err| err.to_html(false))?;
+    let charge = charge
+        .or_else(|| {
+            spectrum
+                .precursor()
+                .and_then(|p| p.ions.first().and_then(|i| i.charge.map(|c| c as isize)))
+        })
+        .unwrap_or(1);
+    let delta = match delta {
+        Some(delta) => delta,
+        None => {
+            let (precursor, theoretical) =
+                precursor_offset(&spectrum, &peptidoform, charge, mass_mode)?;
+            precursor - theoretical
+        }
+    };
+
+    let mut candidates = modification_search_mass(
+        Mass::new::<dalton>(delta),
+        Tolerance::new_absolute(Mass::new::<dalton>(mass_tolerance)),
+        None,
+        mass_mode,
+        &state.ontologies,
+    )
+    .filter(|m| {
+        matches!(
+            &**m,
+            SimpleModificationInner::Gno {
+                composition: GnoComposition::Topology(_) | GnoComposition::Composition(_),
+                ..
+            }
+        )
+    })
+    .collect_vec();
+    if candidates.is_empty() {
+        return Err(BoxedError::small(
+            BasicKind::Error,
+            "No glycans found",
+            format!(
+                "No GNOme composition or topology has a mass within {mass_tolerance} Da of {delta:.4} Da"
+            ),
+        )
+        .to_html(false));
+    }
+    // Annotate the candidates closest in mass if there are too many
+    let truncated = closest(
+        &mut candidates,
+        |m| delta - m.formula().mass(mass_mode).value,
+        MAXIMAL_CANDIDATES,
+    );
+
+    // Annotate every candidate and score the glycan fragments
+    let charge = Charge::new::<e>(charge);
+    let total_intensity: f64 = spectrum
+        .peaks
+        .as_ref()
+        .map_or(0.0, |p| {
+            p.peaks.iter().map(|p| f64::from(p.intensity)).sum()
+        })
+        .max(f64::EPSILON);
+    let key = |mz: f64| (mz * 1e4).round() as i64;
+    let mut scores = Vec::new();
+    for modification in candidates {
+        let placed = place(
+            &peptidoform,
+            std::slice::from_ref(&modification),
+            &[SequencePosition::Index(site)],
+        );
+        let Ok((peptidoform_ion_set, _)) =
+            PeptidoformIonSet::pro_forma(&placed.to_string(), &state.ontologies)
+        else {
+            continue;
+        };
+        let fragments = peptidoform_ion_set.generate_theoretical_fragments(charge, &model);
+        let annotated =
+            spectrum
+                .clone()
+                .annotate(peptidoform_ion_set, &fragments, &parameters, mass_mode);
+        let unique = |oxonium: bool| {
+            fragments
+                .iter()
+                .filter(|f| {
+                    if oxonium {
+                        matches!(f.ion, FragmentType::B { .. })
+                    } else {
+                        matches!(f.ion, FragmentType::Y(_))
+                    }
+                })
+                .filter_map(|f| f.mz(mass_mode).map(|mz| key(mz.value)))
+                .collect::<HashSet<_>>()
+        };
+        let (oxonium, y) = (unique(true), unique(false));
+        let mut found_oxonium = HashSet::new();
+        let mut found_y = HashSet::new();
+        let mut glycan_intensity = 0.0;
+        let mut intensity = 0.0;
+        for peak in &annotated.peaks {
+            if peak.annotations.is_empty() {
+                continue;
+            }
+            intensity += f64::from(peak.intensity);
+            let mut glycan = false;
+            for annotation in &peak.annotations {
+                let Some(mz) = annotation.mz(mass_mode).map(|mz| key(mz.value)) else {
+                    continue;
+                };
+                match annotation.ion {
+                    FragmentType::B { .. } => {
+                        found_oxonium.insert(mz);
+                        glycan = true;
+                    }
+                    FragmentType::Y(_) => {
+                        found_y.insert(mz);
+                        glycan = true;
+                    }
+                    _ => (),
+                }
+            }
+            if glycan {
+                glycan_intensity += f64::from(peak.intensity);
+            }
+        }
+        scores.push(GlycanScore {
+            modification,
+            oxonium: (found_oxonium.len(), oxonium.len()),
+            y: (found_y.len(), y.len()),
+            glycan_intensity: glycan_intensity / total_intensity,
+            intensity: intensity / total_intensity,
+        });
+    }
+    scores.sort_by(|a, b| {
+        b.glycan_intensity
+            .total_cmp(&a.glycan_intensity)
+            .then((b.oxonium.0 + b.y.0).cmp(&(a.oxonium.0 + a.y.0)))
+    });
+
+    let mut glycan_footnotes = Vec::new();
+    let mut output = String::new();
+    write!(
+        output,
+        "<div class='glycan-inference'><p>Mass delta {delta:.4} Da, glycan placed on {}{} ({reason}), this site is a guess{}. {} candidates{}.</p>",
+        peptidoform.sequence()[site].aminoacid.pro_forma_definition(),
+        display_sequence_index(SequencePosition::Index(site)),
+        if sites.len() > 1 {
+            format!(
+                ", other possible sites: {}",
+                sites[1..]
+                    .iter()
+                    .map(|(index, reason)| format!(
+                        "{}{} ({reason})",
+                        peptidoform.sequence()[*index].aminoacid.pro_forma_definition(),
+                        display_sequence_index(SequencePosition::Index(*index))
+                    ))
+                    .join(", ")
+            )
+        } else {
+            String::new()
+        },
+        scores.len(),
+        if truncated {
+            format!(
+                ", only the {MAXIMAL_CANDIDATES} candidates closest in mass are annotated"
+            )
+        } else {
+            String::new()
+        }
+    )
+    .unwrap();
+    if let Some(SimpleModificationInner::Gno {
+        composition: GnoComposition::Topology(structure),
+        ..
+    }) = scores.first().map(|s| &*s.modification)
+    {
+        write!(
+            output,
+            "<div class='best'>{}</div>",
+            render_full_glycan(
+                structure,
+                true,
+                false,
+                theme,
+                &mut glycan_footnotes,
+                false,
+                false,
+                0,
+                0,
+            )
+        )
+        .unwrap();
+    }
+    write!(
+        output,
+        "<table><thead><tr><th>Rank</th><th>Glycan</th><th>Structure</th><th>Oxonium ions</th><th>Y ions</th><th>Glycan fragment intensity</th><th>Explained intensity</th></tr></thead><tbody>"
+    )
+    .unwrap();
+    for (rank, score) in scores.iter().take(MAXIMAL_ROWS).enumerate() {
+        let structure = if let SimpleModificationInner::Gno {
+            composition: GnoComposition::Topology(structure),
+            ..
+        } = &*score.modification
+        {
+            render_full_glycan(
+                structure,
+                false,
+                false,
+                theme,
+                &mut glycan_footnotes,
+                false,
+                false,
+                0,
+                0,
+            )
+        } else {
+            "-".to_string()
+        };
+        write!(
+            output,
+            "<tr><td>{}</td><td>{}</td><td>{structure}</td><td>{}/{}</td><td>{}/{}</td><td>{:.2}%</td><td>{:.2}%</td></tr>",
+            rank + 1,
+            link_modification(score.modification.clone()),
+            score.oxonium.0,
+            score.oxonium.1,
+            score.y.0,
+            score.y.1,
+            score.glycan_intensity * 100.0,
+            score.intensity * 100.0,
+        )
+        .unwrap();
+    }
+    write!(output, "</tbody></table>").unwrap();
+    for (index, note) in glycan_footnotes.iter().enumerate() {
+        write!(
+            output,
+            "<span class='glycan-footnote'>{}{}: {note}</span>",
+            if index != 0 { ", " } else { "" },
+            index + 1
+        )
+        .unwrap();
+    }
+    write!(output, "</div>").unwrap();
+    Ok(output)
+}
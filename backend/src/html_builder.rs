@@ -599,3 +599,19 @@ impl Display for HtmlTag {
         write!(f, "{self:?}")
     }
 }
+
+/// Escape text so that it can be safely placed in HTML content or attribute values.
+pub fn escape(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            '"' => output.push_str("&quot;"),
+            '\'' => output.push_str("&#39;"),
+            c => output.push(c),
+        }
+    }
+    output
+}
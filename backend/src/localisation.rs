@@ -0,0 +1,438 @@
+use std::{collections::BTreeMap, fmt::Write};
+
+use context_error::{BasicKind, BoxedError, Context, CreateError, FullErrorContent};
+use itertools::Itertools;
+use mzannotate::prelude::*;
+use mzcore::{
+    prelude::*,
+    quantities::{Tolerance, WithinTolerance},
+    sequence::{Linear, SimpleModification},
+    system::{MassOverCharge, e, isize::Charge},
+};
+use mzdata::prelude::SpectrumLike;
+
+use crate::{
+    ModifiableState, compare::parse_linear, render::display_sequence_index, spectra::NoiseFilter,
+};
+
+/// The maximal number of positional isomers that are annotated
+const MAXIMAL_ISOMERS: usize = 256;
+
+/// An ambiguous modification with all its possible positions
+struct AmbiguousModification {
+    group: String,
+    modification: SimpleModification,
+    positions: Vec<SequencePosition>,
+}
+
+/// A single positional isomer with its site-determining ions
+struct Isomer {
+    peptidoform: Peptidoform<Linear>,
+    /// The chosen position for every ambiguous modification
+    positions: Vec<SequencePosition>,
+    /// The site-determining fragments as (label, m/z, observed)
+    site_determining: Vec<(String, f64, bool)>,
+    score: f64,
+    probability: f64,
+}
+
+/// Get all ambiguous modifications with all their possible locations
+fn ambiguous_modifications(peptidoform: &Peptidoform<Linear>) -> Vec<AmbiguousModification> {
+    let mut found: BTreeMap<usize, AmbiguousModification> = BTreeMap::new();
+    let locations = std::iter::once((SequencePosition::NTerm, peptidoform.get_n_term()))
+        .chain(
+            peptidoform
+                .sequence()
+                .iter()
+                .enumerate()
+                .map(|(i, s)| (SequencePosition::Index(i), s.modifications.as_slice())),
+        )
+        .chain(std::iter::once((
+            SequencePosition::CTerm,
+            peptidoform.get_c_term(),
+        )));
+    for (position, modifications) in locations {
+        for modification in modifications {
+            if let Modification::Ambiguous {
+                id,
+                group,
+                modification,
+                ..
+            } = modification
+            {
+                found
+                    .entry(*id)
+                    .or_insert_with(|| AmbiguousModification {
+                        group: group.to_string(),
+                        modification: modification.clone(),
+                        positions: Vec::new(),
+                    })
+                    .positions
+                    .push(position);
+            }
+        }
+    }
+    found.into_values().collect()
+}
+
+/// Create the peptidoform with all ambiguous modifications placed at the given positions
+fn place(
+    peptidoform: &Peptidoform<Linear>,
+    ambiguous: &[AmbiguousModification],
+    positions: &[SequencePosition],
+) -> Peptidoform<Linear> {
+    let mut isomer = peptidoform.clone();
+    let mut n_term = isomer
+        .get_n_term()
+        .iter()
+        .filter(|m| !m.is_ambiguous())
+        .cloned()
+        .collect_vec();
+    let mut c_term = isomer
+        .get_c_term()
+        .iter()
+        .filter(|m| !m.is_ambiguous())
+        .cloned()
+        .collect_vec();
+    for element in isomer.sequence_mut() {
+        element.modifications.retain(|m| !m.is_ambiguous());
+    }
+    for (modification, position) in ambiguous.iter().zip(positions) {
+        let modification = Modification::Simple(modification.modification.clone());
+        match position {
+            SequencePosition::NTerm => n_term.push(modification),
+            SequencePosition::Index(i) => {
+                isomer.sequence_mut()[*i].modifications.push(modification)
+            }
+            SequencePosition::CTerm => c_term.push(modification),
+        }
+    }
+    isomer.set_n_term(n_term);
+    isomer.set_c_term(c_term);
+    isomer
+}
+
+/// The natural logarithm of the binomial coefficient
+fn ln_choose(n: usize, k: usize) -> f64 {
+    (1..=k).map(|i| ((n - k + i) as f64 / i as f64).ln()).sum()
+}
+
+/// The natural logarithm of the sum of the exponents of the values, without overflowing
+fn log_sum_exp(values: &[f64]) -> f64 {
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    if !max.is_finite() {
+        return max;
+    }
+    max + values.iter().map(|v| (v - max).exp()).sum::<f64>().ln()
+}
+
+/// Normalise weights given as natural logarithms to probabilities that sum to one
+fn normalise_ln(weights: &[f64]) -> Vec<f64> {
+    let total = log_sum_exp(weights);
+    weights.iter().map(|w| (w - total).exp()).collect()
+}
+
+/// The natural logarithm of the probability of observing at least `k` of `n` fragments by chance
+/// with probability `p`
+fn ln_binomial_tail(n: usize, k: usize, p: f64) -> f64 {
+    if k == 0 {
+        return 0.0;
+    }
+    let terms = (k..=n)
+        .map(|j| ln_choose(n, j) + j as f64 * p.ln() + (n - j) as f64 * (1.0 - p).ln())
+        .collect_vec();
+    log_sum_exp(&terms).min(0.0)
+}
+
+/// Check if any of the m/z values, sorted ascending, is within the tolerance of the given m/z.
+/// Only the neighbours of the insertion point have to be checked.
+fn contains_within(
+    sorted: &[MassOverCharge],
+    mz: MassOverCharge,
+    tolerance: &Tolerance<MassOverCharge>,
+) -> bool {
+    let index = sorted.partition_point(|v| v.value < mz.value);
+    index
+        .checked_sub(1)
+        .into_iter()
+        .chain(std::iter::once(index))
+        .filter_map(|i| sorted.get(i))
+        .any(|v| tolerance.within(v, &mz))
+}
+
+/// Score the localisation of all ambiguous modifications (`#g1` groups and `[mod]?` unknown
+/// positions) in the peptidoform on the selected spectrum. Every positional isomer is annotated
+/// and the site-determining ions, the fragments that are not shared by all isomers within the
+/// tolerance, are determined. The isomers are scored by the binomial probability of matching this
+/// many site-determining ions by chance, following PhosphoRS, and these are combined into a
+/// probability per site.
+#[tauri::command]
+pub async fn localise_modifications(
+    peptide: &str,
+    tolerance: (f64, &str),
+    charge: Option<isize>,
+    noise_filter: (NoiseFilter, f32),
+    model: usize,
+    mass_mode: &str,
+    mz_range: (Option<f64>, Option<f64>),
+    isotopes: bool,
+    isotope_filter: f64,
+    state: ModifiableState<'_>,
+) -> Result<String, String> {
+    let mut state = state.lock().await;
+    let peptidoform = parse_linear(peptide, &state.ontologies)?;
+    let ambiguous = ambiguous_modifications(&peptidoform);
+    if ambiguous.is_empty() {
+        return Err(BoxedError::new(
+            BasicKind::Error,
+            "No ambiguous modifications",
+            "Define the possible positions with an ambiguous modification, for example 'PS[Phospho#g1]T[#g1]Y' or '[Phospho]?PSTY'",
+            Context::full_line(0, peptide),
+        )
+        .to_html(false));
+    }
+    let (_, spectrum) = crate::spectra::create_selected_spectrum(&mut state, noise_filter)
+        .map_err(|err| err.to_html(false))?;
+    let model = crate::model::get_models(&state)
+        .1
+        .get(model)
+        .map(|m| m.2.clone())
+        .ok_or_else(|| {
+            BoxedError::small(BasicKind::Error, "Invalid model", "Model does not exist")
+                .to_html(false)
+        })?;
+    let parameters = crate::model::parameters(tolerance, mz_range, isotopes, isotope_filter)
+        .map_err(|err| err.to_html(false))?;
+    let mass_mode = crate::model::mass_mode(mass_mode).map_err(|err| err.to_html(false))?;
+    let charge = Charge::new::<e>(
+        charge
+            .or_else(|| {
+                spectrum
+                    .precursor()
+                    .and_then(|p| p.ions.first().and_then(|i| i.charge.map(|c| c as isize)))
+            })
+            .unwrap_or(1),
+    );
+
+    // Enumerate all isomers, two modifications cannot be placed on the same position
+    let placements = ambiguous
+        .iter()
+        .map(|a| a.positions.iter().copied())
+        .multi_cartesian_product()
+        .filter(|positions| positions.iter().all_unique())
+        .take(MAXIMAL_ISOMERS + 1)
+        .collect_vec();
+    if placements.len() > MAXIMAL_ISOMERS {
+        return Err(BoxedError::small(
+            BasicKind::Error,
+            "Too many positional isomers",
+            format!(
+                "There are more than {MAXIMAL_ISOMERS} positional isomers, restrict the possible positions of the ambiguous modifications"
+            ),
+        )
+        .to_html(false));
+    }
+
+    // Annotate all isomers and find the theoretical and observed fragments, sorted on m/z
+    let mut annotated_isomers = Vec::new();
+    for positions in placements {
+        let isomer = place(&peptidoform, &ambiguous, &positions);
+        let (peptidoform_ion_set, _) =
+            PeptidoformIonSet::pro_forma(&isomer.to_string(), &state.ontologies).map_err(
+                |errs| {
+                    BoxedError::small(
+                        BasicKind::Error,
+                        "Could not create positional isomer",
+                        isomer.to_string(),
+                    )
+                    .add_underlying_errors(errs)
+                    .to_html(false)
+                },
+            )?;
+        let fragments = peptidoform_ion_set.generate_theoretical_fragments(charge, &model);
+        let annotated =
+            spectrum
+                .clone()
+                .annotate(peptidoform_ion_set, &fragments, &parameters, mass_mode);
+        let observed = annotated
+            .peaks
+            .iter()
+            .flat_map(|p| p.annotations.iter())
+            .filter_map(|f| f.mz(mass_mode))
+            .sorted_by(|a, b| a.value.total_cmp(&b.value))
+            .collect_vec();
+        let theoretical = fragments
+            .into_iter()
+            .filter_map(|f| f.mz(mass_mode).map(|mz| (mz, f)))
+            .sorted_by(|a, b| a.0.value.total_cmp(&b.0.value))
+            .collect_vec();
+        annotated_isomers.push((isomer, positions, theoretical, observed));
+    }
+    let theoretical_mzs = annotated_isomers
+        .iter()
+        .map(|(_, _, theoretical, _)| theoretical.iter().map(|(mz, _)| *mz).collect_vec())
+        .collect_vec();
+
+    // The chance of a random match for a single fragment
+    let peaks = spectrum.peaks.as_ref().map_or(0, |p| p.peaks.len());
+    let (min_mz, max_mz) = spectrum
+        .peaks
+        .as_ref()
+        .and_then(|p| p.peaks.iter().map(|p| p.mz).minmax().into_option())
+        .unwrap_or((0.0, 1.0));
+    let window = if tolerance.1 == "ppm" {
+        2.0 * tolerance.0 * 1e-6 * (min_mz + max_mz) / 2.0
+    } else {
+        2.0 * tolerance.0
+    };
+    let p = (peaks as f64 * window / (max_mz - min_mz).max(1.0)).clamp(1e-6, 1.0 - 1e-6);
+
+    let mut isomers = annotated_isomers
+        .into_iter()
+        .enumerate()
+        .map(|(index, (peptidoform, positions, theoretical, observed))| {
+            // A fragment is shared if all other isomers have a fragment within the tolerance
+            let mut site_determining: Vec<(String, f64, bool)> = Vec::new();
+            let mut last: Option<MassOverCharge> = None;
+            for (mz, f) in theoretical {
+                if last.is_some_and(|last| parameters.tolerance.within(&last, &mz))
+                    || theoretical_mzs.iter().enumerate().all(|(other, mzs)| {
+                        other == index || contains_within(mzs, mz, &parameters.tolerance)
+                    })
+                {
+                    continue;
+                }
+                last = Some(mz);
+                let label = f.ion.label().1.to_string();
+                site_determining.push((
+                    f.ion
+                        .position()
+                        .map_or(label.clone(), |p| format!("{label}{}", p.series_number)),
+                    mz.value,
+                    contains_within(&observed, mz, &parameters.tolerance),
+                ));
+            }
+            let n = site_determining.len();
+            let k = site_determining.iter().filter(|(_, _, o)| *o).count();
+            let ln_chance = ln_binomial_tail(n, k, p);
+            Isomer {
+                peptidoform,
+                positions,
+                site_determining,
+                score: -10.0 * ln_chance / std::f64::consts::LN_10,
+                probability: -ln_chance,
+            }
+        })
+        .collect_vec();
+    // The probabilities are proportional to one over the chance, normalised in log space
+    let probabilities = normalise_ln(&isomers.iter().map(|i| i.probability).collect_vec());
+    for (isomer, probability) in isomers.iter_mut().zip(probabilities) {
+        isomer.probability = probability;
+    }
+    isomers.sort_by(|a, b| b.probability.total_cmp(&a.probability));
+
+    // Render the site probabilities
+    let mut output = String::new();
+    write!(
+        output,
+        "<table class='localisation'><thead><tr><th>Modification</th><th>Position</th><th>Probability</th></tr></thead><tbody>"
+    )
+    .unwrap();
+    for (index, modification) in ambiguous.iter().enumerate() {
+        for position in &modification.positions {
+            let probability: f64 = isomers
+                .iter()
+                .filter(|i| i.positions[index] == *position)
+                .map(|i| i.probability)
+                .sum();
+            let residue = match position {
+                SequencePosition::Index(i) => peptidoform.sequence()[*i]
+                    .aminoacid
+                    .pro_forma_definition()
+                    .to_string(),
+                _ => String::new(),
+            };
+            write!(
+                output,
+                "<tr><td>{}#{}</td><td>{residue}{}</td><td style='--probability:{probability}'>{:.2}%</td></tr>",
+                modification.modification,
+                modification.group,
+                display_sequence_index(*position),
+                probability * 100.0,
+            )
+            .unwrap();
+        }
+    }
+    write!(output, "</tbody></table>").unwrap();
+
+    // Render the isomers with their site-determining ions
+    write!(
+        output,
+        "<table class='localisation'><thead><tr><th>Positional isomer</th><th>Site-determining ions</th><th>Score</th><th>Probability</th></tr></thead><tbody>"
+    )
+    .unwrap();
+    for isomer in &isomers {
+        write!(
+            output,
+            "<tr><td><span class='peptidoform'>{}</span></td><td><span class='site-determining'>",
+            isomer.peptidoform
+        )
+        .unwrap();
+        for (label, mz, observed) in &isomer.site_determining {
+            write!(
+                output,
+                "<span class='ion{}' title='m/z {mz:.4}{}'>{label}</span>",
+                if *observed { " observed" } else { "" },
+                if *observed {
+                    ", observed"
+                } else {
+                    ", not observed"
+                }
+            )
+            .unwrap();
+        }
+        write!(
+            output,
+            "</span> {}/{}</td><td>{:.2}</td><td>{:.2}%</td></tr>",
+            isomer
+                .site_determining
+                .iter()
+                .filter(|(_, _, o)| *o)
+                .count(),
+            isomer.site_determining.len(),
+            isomer.score,
+            isomer.probability * 100.0,
+        )
+        .unwrap();
+    }
+    write!(output, "</tbody></table>").unwrap();
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ln_binomial_tail, normalise_ln};
+
+    #[test]
+    fn normalisation() {
+        // Weights that overflow when exponentiated directly
+        for (weights, expected) in [
+            (vec![0.0, 2000.0, 2000.0], [0.0, 0.5, 0.5]),
+            (vec![-2000.0, -1.0, -1.0], [0.0, 0.5, 0.5]),
+        ] {
+            let probabilities = normalise_ln(&weights);
+            for (probability, expected) in probabilities.iter().zip(expected) {
+                assert!((probability - expected).abs() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn binomial_tail() {
+        assert_eq!(ln_binomial_tail(10, 0, 0.1), 0.0);
+        let ln = ln_binomial_tail(1000, 1000, 1e-6);
+        assert!(ln.is_finite() && ln < -10000.0);
+        let ln = ln_binomial_tail(10, 1, 0.5);
+        assert!((ln - (1.0 - 0.5_f64.powi(10)).ln()).abs() < 1e-12);
+    }
+}
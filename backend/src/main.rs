@@ -25,18 +25,39 @@ use render::{display_formula, display_mass};
 use serde::{Deserialize, Serialize};
 use tauri::Manager;
 
+mod chromatogram;
+mod compare;
+mod consensus;
+mod coverage;
+mod cross_link_search;
 mod custom_modifications;
+mod disulfide;
+mod fdr;
+mod glycan_inference;
 mod html_builder;
+mod localisation;
+mod mass_offset;
 mod metadata_render;
 mod model;
+mod model_ranking;
+mod msp;
+mod pin;
+mod proteoform;
 mod psm_file;
+mod psm_format;
+mod psm_table;
 mod psms;
+mod raw_discovery;
 mod raw_file;
+mod recalibration;
 mod render;
 mod search_modification;
 mod spectra;
 mod state;
+mod stats;
+mod unassigned;
 mod validate;
+mod watch;
 
 use crate::{
     html_builder::{HtmlContent, HtmlElement, HtmlTag},
@@ -68,6 +89,7 @@ impl Theme {
 
 const CUSTOM_MODIFICATIONS_FILE: &str = "custom_modifications.json";
 const CUSTOM_MODELS_FILE: &str = "custom_models.json";
+const CUSTOM_PSM_FORMATS_FILE: &str = "custom_psm_formats.json";
 type ModifiableState<'a> = tauri::State<'a, tokio::sync::Mutex<State>>;
 
 #[tauri::command]
@@ -321,16 +343,7 @@ async fn annotate_spectrum<'a>(
         .2;
     let parameters = model::parameters(tolerance, mz_range, isotopes, isotope_filter)
         .map_err(|err| vec![err.to_html(false)])?;
-    let mass_mode = match mass_mode {
-        "monoisotopic" => MassMode::Monoisotopic,
-        "average_weight" => MassMode::Average,
-        "most_abundant" => MassMode::MostAbundant,
-        _ => {
-            return Err(vec![
-                BoxedError::small(BasicKind::Error, "Invalid mass mode", "").to_html(false),
-            ]);
-        }
-    };
+    let mass_mode = model::mass_mode(mass_mode).map_err(|err| vec![err.to_html(false)])?;
     let (peptide, warnings) =
         PeptidoformIonSet::pro_forma(peptide, &state.ontologies).map_err(|errs| {
             errs.into_iter()
@@ -461,26 +474,37 @@ fn load_custom_mods_and_models(
             }
         }
     }
+
+    match crate::psm_format::load_custom_psm_formats(path) {
+        Ok(formats) => state.custom_psm_formats = formats,
+        Err(error) => {
+            eprintln!("Error while parsing custom PSM formats:\n{error}");
+            state.auto_open_errors.push(error);
+        }
+    }
+}
+
+const RAW_EXTENSIONS: &[&str] = &["xy", "mgf", "mzml", "imzml", "mzmlb", "raw"];
+
+/// Get the lowercase extension of a file, skipping over a final `.gz` extension.
+fn file_extension(path: &std::path::Path) -> Option<String> {
+    path.extension()
+        .map(|ex| {
+            ex.eq_ignore_ascii_case("gz")
+                .then_some(path)
+                .and_then(|p| p.file_stem())
+                .and_then(|p| std::path::Path::new(p).extension())
+                .unwrap_or(ex)
+        })
+        .map(|ex| ex.to_string_lossy().to_lowercase())
 }
 
 fn auto_open(
     state: &mut tokio::sync::MutexGuard<'_, crate::State>,
-    args: Args,
+    args: &Args,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    const RAW_EXTENSIONS: &[&str] = &["xy", "mgf", "mzml", "imzml", "mzmlb", "raw"];
-
     for path in &args.paths {
-        let actual_extension = path
-            .extension()
-            .map(|ex| {
-                ex.eq_ignore_ascii_case("gz")
-                    .then_some(path)
-                    .and_then(|p| p.file_stem())
-                    .and_then(|p| std::path::Path::new(p).extension())
-                    .unwrap_or(ex)
-            })
-            .map(|ex| ex.to_string_lossy().to_lowercase());
-        if let Some(ext) = actual_extension {
+        if let Some(ext) = file_extension(path) {
             if RAW_EXTENSIONS.contains(&ext.as_str()) {
                 match crate::spectra::annotator_open_raw_file(path, state) {
                     Ok(_) => (),
@@ -516,7 +540,23 @@ fn setup(app: tauri::AppHandle, args: Args) -> Result<(), Box<dyn std::error::Er
         return Err("Could not find configuration directory path".into());
     };
     load_custom_mods_and_models(&mut state, &path);
-    auto_open(&mut state, args)
+    state.raw_file_search_roots = args.raw_file_roots.clone();
+    state.cache_dir = app.path().app_cache_dir().ok();
+    auto_open(&mut state, &args)?;
+    if let Some(folder) = args.watch {
+        drop(state);
+        watch::start_watching(
+            app.clone(),
+            watch::WatchSettings {
+                path: folder,
+                psm_files: args.watch_psm_files,
+                targets: args.watch_targets,
+                tolerance: args.watch_tolerance,
+                annotation: watch::WatchAnnotationSettings::default(),
+            },
+        )?;
+    }
+    Ok(())
 }
 
 #[tauri::command]
@@ -717,30 +757,64 @@ fn main() {
             custom_modifications_error: None,
             custom_models: Vec::new(),
             custom_models_error: None,
+            custom_psm_formats: Vec::new(),
             auto_open_errors: Vec::new(),
+            watched_folder: None,
+            fasta: coverage::FastaDatabase::default(),
+            raw_file_search_roots: Vec::new(),
+            cache_dir: None,
+            recalibrations: std::collections::HashMap::new(),
         }))
         .setup(|app| setup(app.app_handle().clone(), args))
         .invoke_handler(tauri::generate_handler![
             annotate_spectrum,
+            chromatogram::get_chromatogram,
+            chromatogram::select_chromatogram_retention_time,
+            compare::compare_peptidoforms,
+            consensus::get_consensus_groups,
+            consensus::load_consensus_group,
+            coverage::get_protein_coverage,
+            coverage::load_fasta,
+            cross_link_search::search_cross_link_sites,
             custom_modifications::delete_custom_modification,
             custom_modifications::duplicate_custom_modification,
             custom_modifications::get_custom_modification,
             custom_modifications::get_custom_modifications,
             custom_modifications::update_modification,
             details_formula,
+            disulfide::map_disulfide_bonds,
+            fdr::compute_fdr,
             get_custom_configuration_path,
+            glycan_inference::infer_glycan,
+            pin::export_pin,
+            proteoform::proteoform_map,
             psm_details,
+            psm_format::delete_custom_psm_format,
+            psm_format::get_custom_psm_formats,
+            psm_format::update_custom_psm_format,
+            psm_table::export_psm_table,
+            psm_table::get_psm_table,
             psms::close_identified_peptides_file,
             psms::get_identified_peptides_files,
             psms::load_identified_peptide,
             psms::load_identified_peptides_file,
             psms::search_peptide,
+            raw_discovery::get_missing_raw_files,
+            raw_discovery::get_raw_file_search_roots,
+            raw_discovery::set_raw_file_search_roots,
             load_annotated_spectrum,
+            localisation::localise_modifications,
+            mass_offset::explain_mass_offset,
             model::delete_custom_model,
             model::duplicate_custom_model,
+            model::export_custom_models,
             model::get_custom_model,
             model::get_custom_models,
+            model::import_custom_models,
             model::update_model,
+            model_ranking::rank_models,
+            recalibration::clear_recalibration,
+            recalibration::estimate_recalibration,
             refresh,
             render::density_graph,
             search_modification::search_modification,
@@ -755,6 +829,8 @@ fn main() {
             spectra::select_retention_time,
             spectra::select_spectrum_index,
             spectra::select_spectrum_native_id,
+            stats::get_psm_statistics,
+            unassigned::explain_unassigned_peaks,
             update_ontology_internet,
             update_ontology_file,
             validate::validate_aa_neutral_loss,
@@ -769,6 +845,9 @@ fn main() {
             validate::validate_placement_rule,
             validate::validate_satellite_ion,
             validate::validate_stub,
+            watch::get_watched_folder,
+            watch::stop_watching_folder,
+            watch::watch_folder,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -778,6 +857,21 @@ fn main() {
 struct Args {
     /// The paths to open in the annotator
     paths: Vec<std::path::PathBuf>,
+    /// A folder to watch, any new raw files appearing in this folder are opened automatically
+    #[arg(long)]
+    watch: Option<std::path::PathBuf>,
+    /// Also open new PSM files appearing in the watched folder
+    #[arg(long)]
+    watch_psm_files: bool,
+    /// Target peptidoforms (ProForma), the newest MS2 spectrum in a new raw file matching one of these is annotated
+    #[arg(long = "watch-target")]
+    watch_targets: Vec<String>,
+    /// The precursor tolerance in ppm for matching the target peptidoforms
+    #[arg(long, default_value_t = 20.0)]
+    watch_tolerance: f64,
+    /// Additional folders to search for raw files referenced in PSM files
+    #[arg(long = "raw-file-root")]
+    raw_file_roots: Vec<std::path::PathBuf>,
 }
 
 pub trait InvertResult<T, E> {
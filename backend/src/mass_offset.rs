@@ -0,0 +1,481 @@
+use std::fmt::Write;
+
+use context_error::{BasicKind, BoxedError, CreateError, FullErrorContent};
+use itertools::Itertools;
+use mzannotate::{annotation::Score, prelude::*};
+use mzcore::{
+    molecular_formula,
+    ontology::Ontology,
+    prelude::*,
+    quantities::Tolerance,
+    sequence::{
+        Linear, PlacementRule, Position, SimpleModification, SimpleModificationInner,
+        modification_search_mass,
+    },
+    system::{Mass, dalton, e, isize::Charge},
+};
+use mzdata::{prelude::SpectrumLike, spectrum::MultiLayerSpectrum};
+
+use crate::{
+    ModifiableState, compare::parse_linear, render::display_sequence_index, spectra::NoiseFilter,
+};
+
+/// The canonical amino acids considered for substitutions
+const SUBSTITUTIONS: &str = "ACDEFGHIKLMNPQRSTVWY";
+/// The maximal number of placements that are annotated, the placements with the smallest mass
+/// error are annotated first
+const MAXIMAL_PLACEMENTS: usize = 500;
+/// The maximal number of placements shown
+const MAXIMAL_ROWS: usize = 50;
+/// The maximal number of modification pairs that are placed, the pairs with the smallest mass
+/// error are placed first
+const MAXIMAL_PAIRS: usize = 100;
+/// The mass (in Dalton) by which one modification of a pair can go beyond the offset, balanced
+/// by a loss of the same mass for the other modification (water, ammonia, and CO2 losses fit)
+const PAIR_MARGIN: f64 = 50.0;
+
+/// A change to the peptidoform that could explain the mass offset
+enum Explanation {
+    Modifications(Vec<SimpleModification>),
+    Substitution {
+        index: usize,
+        residue: char,
+        mass: f64,
+    },
+}
+
+/// A single placement of an explanation annotated on the spectrum
+struct Placement {
+    explanation: String,
+    mass: f64,
+    peptidoform: Peptidoform<Linear>,
+    intensity: f64,
+    peaks: f64,
+    fragments: String,
+}
+
+/// Check if a placement rule allows a modification at this position
+pub(crate) fn allowed(
+    rule: &PlacementRule,
+    peptidoform: &Peptidoform<Linear>,
+    position: SequencePosition,
+) -> bool {
+    let terminal = |pos: &Position, index: usize| match pos {
+        Position::Anywhere => true,
+        Position::AnyNTerm | Position::ProteinNTerm => index == 0,
+        Position::AnyCTerm | Position::ProteinCTerm => index + 1 == peptidoform.len(),
+    };
+    match (rule, position) {
+        (PlacementRule::AminoAcid(aa, pos), SequencePosition::Index(index)) => {
+            let residue = peptidoform.sequence()[index]
+                .aminoacid
+                .pro_forma_definition();
+            aa.iter().any(|a| a.pro_forma_definition() == residue) && terminal(pos, index)
+        }
+        (
+            PlacementRule::Terminal(Position::AnyNTerm | Position::ProteinNTerm),
+            SequencePosition::NTerm,
+        )
+        | (
+            PlacementRule::Terminal(Position::AnyCTerm | Position::ProteinCTerm),
+            SequencePosition::CTerm,
+        )
+        | (PlacementRule::Anywhere, SequencePosition::Index(_)) => true,
+        _ => false,
+    }
+}
+
+/// Get all positions where this modification is allowed, modifications without placement rules
+/// are allowed on any residue
+fn positions(
+    peptidoform: &Peptidoform<Linear>,
+    modification: &SimpleModification,
+) -> Vec<SequencePosition> {
+    let all = std::iter::once(SequencePosition::NTerm)
+        .chain((0..peptidoform.len()).map(SequencePosition::Index))
+        .chain(std::iter::once(SequencePosition::CTerm));
+    match &**modification {
+        SimpleModificationInner::Database { specificities, .. } => all
+            .filter(|position| {
+                specificities
+                    .iter()
+                    .flat_map(|s| s.0.iter())
+                    .any(|rule| allowed(rule, peptidoform, *position))
+            })
+            .collect(),
+        _ => all
+            .filter(|p| matches!(p, SequencePosition::Index(_)))
+            .collect(),
+    }
+}
+
+/// Get the precursor mass of the spectrum at the given charge and the mass of the peptidoform
+pub(crate) fn precursor_offset(
+    spectrum: &MultiLayerSpectrum,
+    peptidoform: &Peptidoform<Linear>,
+    charge: isize,
+    mass_mode: MassMode,
+) -> Result<(f64, f64), String> {
+    let proton = molecular_formula!(H 1 Electron -1).mass(mass_mode).value;
+    let precursor = spectrum
+        .precursor()
+        .and_then(|p| p.ions.first())
+        .map(|i| (i.mz - proton) * charge as f64)
+        .ok_or_else(|| {
+            BoxedError::small(
+                BasicKind::Error,
+                "No precursor",
+                "The selected spectrum does not have a precursor m/z",
+            )
+            .to_html(false)
+        })?;
+    let theoretical = peptidoform
+        .formulas()
+        .first()
+        .map_or(0.0, |f| f.mass(mass_mode).value);
+    Ok((precursor, theoretical))
+}
+
+/// Create the peptidoform with the modifications added at the given positions
+pub(crate) fn place(
+    peptidoform: &Peptidoform<Linear>,
+    modifications: &[SimpleModification],
+    positions: &[SequencePosition],
+) -> Peptidoform<Linear> {
+    let mut placed = peptidoform.clone();
+    let mut n_term = placed.get_n_term().to_vec();
+    let mut c_term = placed.get_c_term().to_vec();
+    for (modification, position) in modifications.iter().zip(positions) {
+        let modification = Modification::Simple(modification.clone());
+        match position {
+            SequencePosition::NTerm => n_term.push(modification),
+            SequencePosition::Index(i) => {
+                placed.sequence_mut()[*i].modifications.push(modification)
+            }
+            SequencePosition::CTerm => c_term.push(modification),
+        }
+    }
+    placed.set_n_term(n_term);
+    placed.set_c_term(c_term);
+    placed
+}
+
+/// Find all pairs (with the first index at most the second) in the pool, sorted on mass, with a
+/// combined mass within the tolerance of the delta. The partners are found with a binary search.
+fn matching_pairs<T>(pool: &[(f64, T)], delta: f64, tolerance: f64) -> Vec<(usize, usize)> {
+    let mut pairs = Vec::new();
+    for (first, (first_mass, _)) in pool.iter().enumerate() {
+        let start = pool.partition_point(|(mass, _)| *mass < delta - first_mass - tolerance);
+        for (second, (second_mass, _)) in pool.iter().enumerate().skip(start.max(first)) {
+            if *second_mass > delta - first_mass + tolerance {
+                break;
+            }
+            pairs.push((first, second));
+        }
+    }
+    pairs
+}
+
+/// Keep at most `maximum` candidates with the smallest absolute error, the order of candidates
+/// with the same error is kept. Returns if candidates were removed.
+pub(crate) fn closest<T>(
+    candidates: &mut Vec<T>,
+    error: impl Fn(&T) -> f64,
+    maximum: usize,
+) -> bool {
+    candidates.sort_by(|a, b| error(a).abs().total_cmp(&error(b).abs()));
+    let truncated = candidates.len() > maximum;
+    candidates.truncate(maximum);
+    truncated
+}
+
+/// Explain the difference between the precursor mass of the selected spectrum and the mass of
+/// the peptidoform. Candidates are single modifications and pairs of Unimod modifications that
+/// fit the mass difference within the given tolerance (in Dalton), as well as single amino acid
+/// substitutions. Every candidate is placed at all positions allowed by its placement rules, all
+/// placements are annotated on the spectrum and ranked on the explained intensity.
+#[tauri::command]
+pub async fn explain_mass_offset(
+    peptide: &str,
+    mass_tolerance: f64,
+    tolerance: (f64, &str),
+    charge: Option<isize>,
+    noise_filter: (NoiseFilter, f32),
+    model: usize,
+    mass_mode: &str,
+    mz_range: (Option<f64>, Option<f64>),
+    isotopes: bool,
+    isotope_filter: f64,
+    state: ModifiableState<'_>,
+) -> Result<String, String> {
+    let mut state = state.lock().await;
+    let peptidoform = parse_linear(peptide, &state.ontologies)?;
+    let (_, spectrum) = crate::spectra::create_selected_spectrum(&mut state, noise_filter)
+        .map_err(|err| err.to_html(false))?;
+    let model = crate::model::get_models(&state)
+        .1
+        .get(model)
+        .map(|m| m.2.clone())
+        .ok_or_else(|| {
+            BoxedError::small(BasicKind::Error, "Invalid model", "Model does not exist")
+                .to_html(false)
+        })?;
+    let parameters = crate::model::parameters(tolerance, mz_range, isotopes, isotope_filter)
+        .map_err(|err| err.to_html(false))?;
+    let mass_mode = crate::model::mass_mode(mass_mode).map_err(|err| err.to_html(false))?;
+    let charge = charge
+        .or_else(|| {
+            spectrum
+                .precursor()
+                .and_then(|p| p.ions.first().and_then(|i| i.charge.map(|c| c as isize)))
+        })
+        .unwrap_or(1);
+
+    let (precursor, theoretical) = precursor_offset(&spectrum, &peptidoform, charge, mass_mode)?;
+    let delta = precursor - theoretical;
+    let search_tolerance = Tolerance::new_absolute(Mass::new::<dalton>(mass_tolerance));
+
+    // Gather all candidate explanations
+    let mut explanations = modification_search_mass(
+        Mass::new::<dalton>(delta),
+        search_tolerance,
+        None,
+        mass_mode,
+        &state.ontologies,
+    )
+    .map(|m| Explanation::Modifications(vec![m]))
+    .collect_vec();
+    let unimod = |m: &SimpleModification| match &**m {
+        SimpleModificationInner::Database { id, .. } => id.ontology == Ontology::Unimod,
+        _ => false,
+    };
+    let mass = |m: &SimpleModification| m.formula().mass(mass_mode).value;
+    // Only pairs that together fit the offset are used, the pool contains all modifications
+    // between the offset and zero, widened by the margin on both sides
+    let pool = modification_search_mass(
+        Mass::new::<dalton>(delta / 2.0),
+        Tolerance::new_absolute(Mass::new::<dalton>(delta.abs() / 2.0 + PAIR_MARGIN)),
+        None,
+        mass_mode,
+        &state.ontologies,
+    )
+    .filter(unimod)
+    .map(|m| (mass(&m), m))
+    .sorted_by(|a, b| a.0.total_cmp(&b.0))
+    .collect_vec();
+    let mut pairs = matching_pairs(&pool, delta, mass_tolerance);
+    let pairs_truncated = closest(
+        &mut pairs,
+        |(first, second)| delta - pool[*first].0 - pool[*second].0,
+        MAXIMAL_PAIRS,
+    );
+    for (first, second) in pairs {
+        explanations.push(Explanation::Modifications(vec![
+            pool[first].1.clone(),
+            pool[second].1.clone(),
+        ]));
+    }
+    let residues = SUBSTITUTIONS
+        .chars()
+        .map(|residue| {
+            parse_linear(&residue.to_string(), &state.ontologies).map(|p| {
+                (
+                    residue,
+                    p.formulas()
+                        .first()
+                        .map_or(0.0, |f| f.mass(mass_mode).value),
+                )
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    for (index, element) in peptidoform.sequence().iter().enumerate() {
+        let original = element.aminoacid.pro_forma_definition().to_string();
+        let Some(original_mass) = residues
+            .iter()
+            .find(|(r, _)| original == r.to_string())
+            .map(|(_, m)| *m)
+        else {
+            continue;
+        };
+        for (residue, residue_mass) in &residues {
+            if original != residue.to_string()
+                && (residue_mass - original_mass - delta).abs() <= mass_tolerance
+            {
+                explanations.push(Explanation::Substitution {
+                    index,
+                    residue: *residue,
+                    mass: residue_mass - original_mass,
+                });
+            }
+        }
+    }
+
+    // Place all candidates
+    let mut placements = Vec::new();
+    for explanation in &explanations {
+        match explanation {
+            Explanation::Modifications(modifications) => {
+                for positions in modifications
+                    .iter()
+                    .map(|m| positions(&peptidoform, m))
+                    .multi_cartesian_product()
+                    .filter(|positions| positions.iter().all_unique())
+                {
+                    placements.push((
+                        modifications
+                            .iter()
+                            .zip(&positions)
+                            .map(|(m, p)| format!("{m}@{}", display_sequence_index(*p)))
+                            .join(" + "),
+                        modifications.iter().map(&mass).sum::<f64>(),
+                        place(&peptidoform, modifications, &positions),
+                    ));
+                }
+            }
+            Explanation::Substitution {
+                index,
+                residue,
+                mass,
+            } => {
+                let template = parse_linear(&residue.to_string(), &state.ontologies)?;
+                let mut substituted = peptidoform.clone();
+                let element = &mut substituted.sequence_mut()[*index];
+                let modifications = std::mem::take(&mut element.modifications);
+                *element = template.sequence()[0].clone();
+                element.modifications = modifications;
+                placements.push((
+                    format!(
+                        "{}{}→{residue}",
+                        peptidoform.sequence()[*index]
+                            .aminoacid
+                            .pro_forma_definition(),
+                        display_sequence_index(SequencePosition::Index(*index)),
+                    ),
+                    *mass,
+                    substituted,
+                ));
+            }
+        }
+    }
+    let truncated = closest(&mut placements, |p| delta - p.1, MAXIMAL_PLACEMENTS);
+
+    // Annotate all placements
+    let charge = Charge::new::<e>(charge);
+    let mut annotated = Vec::with_capacity(placements.len());
+    for (explanation, mass, placed) in placements {
+        let Ok((peptidoform_ion_set, _)) =
+            PeptidoformIonSet::pro_forma(&placed.to_string(), &state.ontologies)
+        else {
+            continue;
+        };
+        let fragments = peptidoform_ion_set.generate_theoretical_fragments(charge, &model);
+        let (scores, _) = spectrum
+            .clone()
+            .annotate(peptidoform_ion_set, &fragments, &parameters, mass_mode)
+            .scores(&fragments, &parameters, mass_mode);
+        let (fragments, peaks, intensity) = match scores.score {
+            Score::Position {
+                fragments,
+                peaks,
+                intensity,
+                ..
+            }
+            | Score::UniqueFormulas {
+                fragments,
+                peaks,
+                intensity,
+                ..
+            } => (fragments, peaks, intensity),
+        };
+        annotated.push(Placement {
+            explanation,
+            mass,
+            peptidoform: placed,
+            intensity: intensity.fraction(),
+            peaks: peaks.fraction(),
+            fragments: format!("{}/{}", fragments.found, fragments.total),
+        });
+    }
+    annotated.sort_by(|a, b| {
+        b.intensity
+            .total_cmp(&a.intensity)
+            .then(b.peaks.total_cmp(&a.peaks))
+    });
+
+    let mut output = String::new();
+    write!(
+        output,
+        "<p>Precursor mass {precursor:.4} Da, peptidoform mass {theoretical:.4} Da, offset {delta:+.4} Da. {} explanations{} with {} placements{}.</p>",
+        explanations.len(),
+        if pairs_truncated {
+            format!(" (only the {MAXIMAL_PAIRS} modification pairs with the smallest mass error)")
+        } else {
+            String::new()
+        },
+        annotated.len(),
+        if truncated {
+            format!(
+                ", only the {MAXIMAL_PLACEMENTS} placements with the smallest mass error are annotated"
+            )
+        } else {
+            String::new()
+        }
+    )
+    .unwrap();
+    if annotated.is_empty() {
+        return Ok(output);
+    }
+    write!(
+        output,
+        "<table class='mass-offset'><thead><tr><th>Rank</th><th>Explanation</th><th>Mass</th><th>Error</th><th>Explained intensity</th><th>Annotated peaks</th><th>Fragments found</th><th>Peptidoform</th></tr></thead><tbody>"
+    )
+    .unwrap();
+    for (rank, placement) in annotated.iter().take(MAXIMAL_ROWS).enumerate() {
+        write!(
+            output,
+            "<tr><td>{}</td><td>{}</td><td>{:+.4} Da</td><td>{:.4} Da</td><td>{:.2}%</td><td>{:.2}%</td><td>{}</td><td><span class='peptidoform'>{}</span></td></tr>",
+            rank + 1,
+            placement.explanation,
+            placement.mass,
+            delta - placement.mass,
+            placement.intensity * 100.0,
+            placement.peaks * 100.0,
+            placement.fragments,
+            placement.peptidoform,
+        )
+        .unwrap();
+    }
+    write!(output, "</tbody></table>").unwrap();
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{closest, matching_pairs};
+
+    #[test]
+    fn candidate_cap() {
+        let mut candidates = vec![5.0, -0.5, 2.0, 0.1, -3.0, 0.1];
+        assert!(closest(&mut candidates, |c| *c, 4));
+        assert_eq!(candidates, vec![0.1, 0.1, -0.5, 2.0]);
+        assert!(!closest(&mut candidates, |c| *c, 4));
+        assert_eq!(candidates.len(), 4);
+    }
+
+    #[test]
+    fn pairs() {
+        let pool = [
+            (-18.0, 'a'),
+            (14.0, 'b'),
+            (16.0, 'c'),
+            (28.0, 'd'),
+            (42.0, 'e'),
+        ];
+        assert_eq!(matching_pairs(&pool, 56.0, 0.5), vec![(1, 4), (3, 3)]);
+        assert_eq!(
+            matching_pairs(&pool, 30.0, 2.5),
+            vec![(1, 1), (1, 2), (2, 2)]
+        );
+        assert!(matching_pairs(&pool, 1000.0, 0.5).is_empty());
+    }
+}
@@ -251,7 +251,11 @@ pub async fn update_model(
         state.custom_models.push(model);
     }
 
-    // Store mods config file
+    store_custom_models(&app, &state)
+}
+
+/// Store the custom models in the configuration file
+fn store_custom_models(app: &tauri::AppHandle, state: &State) -> Result<(), String> {
     let path = app
         .path()
         .app_config_dir()
@@ -303,6 +307,152 @@ pub async fn update_model(
     Ok(())
 }
 
+/// A single model in a shareable model file
+#[derive(Debug, Deserialize, Serialize)]
+struct ModelFileEntry {
+    name: String,
+    model: ModelParameters,
+}
+
+/// A shareable model file, containing either a single model or a list of models
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ModelFile {
+    Single(ModelFileEntry),
+    Multiple(Vec<ModelFileEntry>),
+}
+
+/// Export the given models (built in or custom) to a standalone JSON file that can be imported by
+/// anyone else.
+#[tauri::command]
+pub async fn export_custom_models(
+    ids: Vec<usize>,
+    path: &str,
+    state: ModifiableState<'_>,
+) -> Result<(), String> {
+    let state = state.lock().await;
+    let models = get_models(&state).1;
+    let entries = ids
+        .iter()
+        .map(|id| {
+            models
+                .get(*id)
+                .map(|(_, name, model)| ModelFileEntry {
+                    name: name.to_string(),
+                    model: (*model).clone().into(),
+                })
+                .ok_or_else(|| {
+                    BoxedError::small(
+                        BasicKind::Error,
+                        "Invalid model",
+                        format!("Model {id} does not exist"),
+                    )
+                    .to_html(false)
+                })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if entries.is_empty() {
+        return Err(BoxedError::small(
+            BasicKind::Error,
+            "No models selected",
+            "Select at least one model to export",
+        )
+        .to_html(false));
+    }
+    let file = BufWriter::new(std::fs::File::create(path).map_err(|err| {
+        BoxedError::new(
+            BasicKind::Error,
+            "Could not create model file",
+            err.to_string(),
+            Context::show(path).to_owned(),
+        )
+        .to_html(false)
+    })?);
+    serde_json::to_writer_pretty(file, &entries).map_err(|err| {
+        BoxedError::new(
+            BasicKind::Error,
+            "Could not write models to file",
+            err.to_string(),
+            Context::show(path).to_owned(),
+        )
+        .to_html(false)
+    })
+}
+
+/// Import all models from a model file as custom models. All models are validated before any is
+/// added, so a file with an invalid model does not partially import. If the name of a model is
+/// already in use (by a built in or custom model, ignoring case) a number is appended to the
+/// name. Returns the index and final name of all imported models.
+#[tauri::command]
+pub async fn import_custom_models(
+    path: &str,
+    app: tauri::AppHandle,
+) -> Result<Vec<(usize, String)>, String> {
+    let file = std::fs::File::open(path).map_err(|err| {
+        BoxedError::new(
+            BasicKind::Error,
+            "Could not open model file",
+            err.to_string(),
+            Context::show(path).to_owned(),
+        )
+        .to_html(false)
+    })?;
+    let entries = match serde_json::from_reader(std::io::BufReader::new(file)).map_err(|err| {
+        BoxedError::new(
+            BasicKind::Error,
+            "Invalid model file",
+            err.to_string(),
+            Context::show(path).to_owned(),
+        )
+        .to_html(false)
+    })? {
+        ModelFile::Single(entry) => vec![entry],
+        ModelFile::Multiple(entries) => entries,
+    };
+    let models = entries
+        .into_iter()
+        .map(|entry| {
+            FragmentationModel::try_from(entry.model)
+                .map(|model| (entry.name.trim().to_string(), model))
+                .map_err(|err| {
+                    BoxedError::small(
+                        BasicKind::Error,
+                        "Invalid model",
+                        format!("The model '{}' is not valid", entry.name),
+                    )
+                    .add_underlying_errors(vec![err])
+                    .to_html(false)
+                })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let handle = app.state::<Mutex<State>>();
+    let mut state = handle.lock().await;
+    let mut imported = Vec::with_capacity(models.len());
+    for (name, model) in models {
+        let base = if name.is_empty() {
+            "Imported model".to_string()
+        } else {
+            name
+        };
+        let mut name = base.clone();
+        let mut counter = 1;
+        while get_model_index(&state.custom_models, &name).is_some()
+            || get_models(&state)
+                .1
+                .iter()
+                .any(|(_, n, _)| n.eq_ignore_ascii_case(&name))
+        {
+            counter += 1;
+            name = format!("{base} ({counter})");
+        }
+        state.custom_models.push((name.clone(), model));
+        imported.push((get_models(&state).1.len() - 1, name));
+    }
+    store_custom_models(&app, &state)?;
+    Ok(imported)
+}
+
 #[derive(Debug, Deserialize, PartialEq, Serialize)]
 pub struct ModelParameters {
     pub a: PrimarySeriesParameters,
@@ -722,3 +872,12 @@ pub fn parameters(
     dbg!(&parameters);
     Ok(parameters)
 }
+
+pub fn mass_mode(mass_mode: &str) -> Result<MassMode, BoxedError<'static, BasicKind>> {
+    match mass_mode {
+        "monoisotopic" => Ok(MassMode::Monoisotopic),
+        "average_weight" => Ok(MassMode::Average),
+        "most_abundant" => Ok(MassMode::MostAbundant),
+        _ => Err(BoxedError::small(BasicKind::Error, "Invalid mass mode", "")),
+    }
+}
@@ -0,0 +1,143 @@
+use std::fmt::Write;
+
+use context_error::{BasicKind, BoxedError, Context, CreateError, FullErrorContent};
+use itertools::Itertools;
+use mzannotate::{annotation::Score, prelude::*};
+use mzcore::{
+    prelude::*,
+    system::{e, isize::Charge},
+};
+use mzdata::prelude::SpectrumLike;
+
+use crate::{ModifiableState, html_builder, spectra::NoiseFilter};
+
+/// The scores of the annotation of the selected spectrum with a single model
+struct ModelScore {
+    index: usize,
+    name: String,
+    built_in: bool,
+    intensity: f64,
+    peaks: f64,
+    /// The found and total number of theoretical fragments
+    fragments: String,
+    fragments_fraction: f64,
+    positions: f64,
+    unexplained: usize,
+}
+
+/// Annotate the selected spectrum with every built-in and custom model and rank the models on
+/// the fraction of the intensity that is explained, with ties broken on the fraction of the
+/// theoretical fragments that is found. Clicking on a model selects it for annotation.
+#[tauri::command]
+pub async fn rank_models(
+    peptide: &str,
+    tolerance: (f64, &str),
+    charge: Option<isize>,
+    noise_filter: (NoiseFilter, f32),
+    mass_mode: &str,
+    mz_range: (Option<f64>, Option<f64>),
+    isotopes: bool,
+    isotope_filter: f64,
+    state: ModifiableState<'_>,
+) -> Result<String, String> {
+    let mut state = state.lock().await;
+    let (_, spectrum) = crate::spectra::create_selected_spectrum(&mut state, noise_filter)
+        .map_err(|err| err.to_html(false))?;
+    let parameters = crate::model::parameters(tolerance, mz_range, isotopes, isotope_filter)
+        .map_err(|err| err.to_html(false))?;
+    let mass_mode = crate::model::mass_mode(mass_mode).map_err(|err| err.to_html(false))?;
+    let (peptide, _) =
+        PeptidoformIonSet::pro_forma(peptide, &state.ontologies).map_err(|errs| {
+            BoxedError::new(
+                BasicKind::Error,
+                "Invalid ProForma definition",
+                "The string could not be parsed as a ProForma definition",
+                Context::full_line(0, peptide),
+            )
+            .add_underlying_errors(errs)
+            .to_html(false)
+        })?;
+    let charge = Charge::new::<e>(
+        charge
+            .or_else(|| {
+                spectrum
+                    .precursor()
+                    .and_then(|p| p.ions.first().and_then(|i| i.charge.map(|c| c as isize)))
+            })
+            .unwrap_or(1),
+    );
+
+    let scores = crate::model::get_models(&state)
+        .1
+        .into_iter()
+        .enumerate()
+        .map(|(index, (built_in, name, model))| {
+            let fragments = peptide.generate_theoretical_fragments(charge, model);
+            let annotated =
+                spectrum
+                    .clone()
+                    .annotate(peptide.clone(), &fragments, &parameters, mass_mode);
+            let (scores, _) = annotated.scores(&fragments, &parameters, mass_mode);
+            let (fragments, peaks, intensity, positions) = match scores.score {
+                Score::Position {
+                    fragments,
+                    peaks,
+                    intensity,
+                    expected_positions,
+                    ..
+                } => (fragments, peaks, intensity, expected_positions),
+                Score::UniqueFormulas {
+                    fragments,
+                    peaks,
+                    intensity,
+                    unique_formulas,
+                } => (fragments, peaks, intensity, unique_formulas),
+            };
+            ModelScore {
+                index,
+                name: name.to_string(),
+                built_in: built_in.is_some(),
+                intensity: intensity.fraction(),
+                peaks: peaks.fraction(),
+                fragments: format!("{}/{}", fragments.found, fragments.total),
+                fragments_fraction: fragments.fraction(),
+                positions: positions.fraction(),
+                unexplained: annotated
+                    .peaks
+                    .iter()
+                    .filter(|p| p.annotations.is_empty())
+                    .count(),
+            }
+        })
+        .sorted_by(|a, b| {
+            b.intensity
+                .total_cmp(&a.intensity)
+                .then(b.fragments_fraction.total_cmp(&a.fragments_fraction))
+        })
+        .collect_vec();
+
+    let mut output = String::new();
+    write!(
+        output,
+        "<table class='model-ranking'><thead><tr><th>Rank</th><th>Model</th><th>Explained intensity</th><th>Annotated peaks</th><th>Fragments found</th><th>Positions covered</th><th>Unexplained peaks</th></tr></thead><tbody>"
+    )
+    .unwrap();
+    for (rank, score) in scores.iter().enumerate() {
+        write!(
+            output,
+            "<tr data-model='{}' title='Select this model'><td>{}</td><td>{}{}</td><td>{:.2}%</td><td>{:.2}%</td><td>{}</td><td>{:.2}%</td><td>{}</td></tr>",
+            score.index,
+            rank + 1,
+            html_builder::escape(&score.name),
+            if score.built_in { "" } else { " (custom)" },
+            score.intensity * 100.0,
+            score.peaks * 100.0,
+            score.fragments,
+            score.positions * 100.0,
+            score.unexplained,
+        )
+        .unwrap();
+    }
+    write!(output, "</tbody></table>").unwrap();
+    Ok(output)
+}
@@ -0,0 +1,140 @@
+use std::io::Write;
+
+use itertools::Itertools;
+use mzannotate::prelude::*;
+use mzdata::prelude::SpectrumLike;
+use mzident::PSMMetaData;
+
+/// Write an annotated spectrum as a single NIST MSP library entry. The peptidoform is written
+/// as ProForma in the name and comment, every peak gets the mzPAF strings of its annotations.
+pub fn write_msp(
+    writer: &mut impl Write,
+    spectrum: &AnnotatedSpectrum,
+    sequence: &str,
+    model: Option<&str>,
+) -> std::io::Result<()> {
+    let peptidoform_ion_set = spectrum.peptidoform_ion_set();
+    let pro_forma = if sequence.is_empty() {
+        peptidoform_ion_set
+            .as_ref()
+            .map(|p| p.to_string())
+            .unwrap_or_default()
+    } else {
+        sequence.to_string()
+    };
+    let ion = spectrum
+        .description()
+        .precursor
+        .first()
+        .and_then(|p| p.ions.first());
+    let charge = ion.and_then(|i| i.charge);
+    let mass = peptidoform_ion_set
+        .as_ref()
+        .and_then(|p| p.formulas().first().map(|f| f.monoisotopic_mass().value))
+        .or_else(|| ion.map(|i| i.neutral_mass()));
+
+    writeln!(
+        writer,
+        "Name: {pro_forma}/{}",
+        charge.map_or("-".to_string(), |c| c.to_string())
+    )?;
+    if let Some(mass) = mass {
+        writeln!(writer, "MW: {mass:.4}")?;
+    }
+    writeln!(
+        writer,
+        "Comment: ProForma=\"{pro_forma}\" Charge={} Parent={} Model=\"{}\" NativeID=\"{}\"",
+        charge.map_or("-".to_string(), |c| c.to_string()),
+        ion.map_or("-".to_string(), |i| format!("{:.4}", i.mz)),
+        model.unwrap_or("-"),
+        spectrum.description().id,
+    )?;
+    writeln!(writer, "Num peaks: {}", spectrum.peaks.len())?;
+    for peak in &spectrum.peaks {
+        writeln!(
+            writer,
+            "{:.5}\t{}\t\"{}\"",
+            peak.mz.value,
+            peak.intensity,
+            if peak.annotations.is_empty() {
+                "?".to_string()
+            } else {
+                peak.annotations
+                    .iter()
+                    .map(|a| a.to_mz_paf_string())
+                    .join(",")
+            }
+        )?;
+    }
+    writeln!(writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use mzannotate::{fragment::FragmentType, prelude::*};
+    use mzcore::{
+        ontology::Ontologies,
+        prelude::*,
+        system::{e, isize::Charge},
+    };
+    use mzdata::spectrum::{MultiLayerSpectrum, Precursor, SelectedIon, SpectrumDescription};
+    use mzpeaks::{CentroidPeak, peak_set::PeakSetVec};
+
+    use super::write_msp;
+
+    #[test]
+    fn write_entry() {
+        let (peptidoform_ion_set, _) =
+            PeptidoformIonSet::pro_forma("AG", &Ontologies::empty()).unwrap();
+        let fragments = peptidoform_ion_set
+            .generate_theoretical_fragments(Charge::new::<e>(1), FragmentationModel::all());
+        let y1 = fragments
+            .iter()
+            .find(|f| {
+                matches!(f.ion, FragmentType::y(..))
+                    && f.charge.value == 1
+                    && f.neutral_loss.is_empty()
+            })
+            .and_then(|f| f.mz(MassMode::Monoisotopic))
+            .unwrap()
+            .value;
+        let description = SpectrumDescription {
+            id: "index=1".to_string(),
+            precursor: vec![Precursor {
+                ions: vec![SelectedIon {
+                    mz: 147.076,
+                    charge: Some(1),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let spectrum = MultiLayerSpectrum::new(
+            description,
+            None,
+            Some(PeakSetVec::new(vec![
+                CentroidPeak::new(50.0, 10.0, 0),
+                CentroidPeak::new(y1, 100.0, 1),
+            ])),
+            None,
+        );
+        let parameters = crate::model::parameters((20.0, "ppm"), (None, None), false, 0.0).unwrap();
+        let annotated = spectrum.annotate(
+            peptidoform_ion_set,
+            &fragments,
+            &parameters,
+            MassMode::Monoisotopic,
+        );
+
+        let mut output = Vec::new();
+        write_msp(&mut output, &annotated, "", Some("All")).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "Name: AG/1");
+        assert!(lines[1].starts_with("MW: "));
+        assert_eq!(lines[3], "Num peaks: 2");
+        assert_eq!(lines[4], "50.00000\t10\t\"?\"");
+        assert!(lines[5].contains("\t\"y1"));
+    }
+}
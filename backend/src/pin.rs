@@ -0,0 +1,361 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+};
+
+use context_error::{BasicKind, BoxedError, Context, CreateError, FullErrorContent};
+use itertools::Itertools;
+use mzannotate::{
+    annotation::{Fdr, Score},
+    prelude::*,
+};
+use mzcore::{
+    prelude::*,
+    sequence::Linked,
+    system::{e, isize::Charge},
+};
+use mzdata::prelude::SpectrumLike;
+use mzident::{MaybePeptidoform, PSM, PSMData, PSMMetaData};
+
+use crate::{ModifiableState, psms::select_scans, spectra::NoiseFilter};
+
+/// The names of the annotation derived features, in the order given by [`annotation_features`]
+const ANNOTATION_FEATURES: &[&str] = &[
+    "fragments_found",
+    "peaks_annotated",
+    "intensity_annotated",
+    "positions_covered",
+    "peaks_sigma",
+    "intensity_sigma",
+];
+
+fn annotation_features(score: &Score, fdr: Option<&Fdr>) -> [f64; 6] {
+    let (fragments, peaks, intensity, positions) = match score {
+        Score::Position {
+            fragments,
+            peaks,
+            intensity,
+            expected_positions,
+            ..
+        } => (fragments, peaks, intensity, expected_positions),
+        Score::UniqueFormulas {
+            fragments,
+            peaks,
+            intensity,
+            unique_formulas,
+        } => (fragments, peaks, intensity, unique_formulas),
+    };
+    [
+        fragments.fraction(),
+        peaks.fraction(),
+        intensity.fraction(),
+        positions.fraction(),
+        fdr.map_or(0.0, Fdr::peaks_sigma),
+        fdr.map_or(0.0, Fdr::intensity_sigma),
+    ]
+}
+
+/// The numeric scores reported by the search engine, for the formats where these are known
+fn engine_features(psm: &PSM<Linked, MaybePeptidoform>) -> Vec<(&'static str, f64)> {
+    match &psm.data {
+        PSMData::Sage(data) => vec![
+            ("sage_hyperscore", data.hyperscore),
+            ("sage_delta_next", data.delta_next),
+            ("sage_delta_best", data.delta_best),
+            ("sage_poisson", data.poisson),
+            (
+                "sage_discriminant_score",
+                data.sage_discriminant_score as f64,
+            ),
+            ("sage_posterior_error", data.posterior_error as f64),
+            ("sage_matched_peaks", data.matched_peaks as f64),
+            ("sage_longest_b", data.longest_b as f64),
+            ("sage_longest_y", data.longest_y as f64),
+            ("sage_missed_cleavages", data.missed_cleavages as f64),
+            ("sage_isotope_error", data.isotope_error as f64),
+        ],
+        PSMData::MSFragger(data) => vec![
+            ("msfragger_expectation_score", data.expectation_score),
+            ("msfragger_next_score", data.next_score),
+            (
+                "msfragger_peptide_prophet_probability",
+                data.peptide_prophet_probability.unwrap_or_default(),
+            ),
+            ("msfragger_missed_cleavages", data.missed_cleavages as f64),
+        ],
+        PSMData::MaxQuant(data) => vec![
+            ("maxquant_pep", data.pep),
+            ("maxquant_delta_score", data.delta_score.unwrap_or_default()),
+            ("maxquant_score_diff", data.score_diff.unwrap_or_default()),
+            (
+                "maxquant_number_of_matches",
+                data.number_of_matches.map_or(0.0, |n| n as f64),
+            ),
+        ],
+        _ => Vec::new(),
+    }
+}
+
+/// The Percolator label for every PSM, decoys can only be labelled if the FDR is computed.
+fn labels(decoys: Option<&[bool]>) -> Result<Vec<i8>, &'static str> {
+    let decoys = decoys.ok_or(
+        "Compute the FDR for this PSM file first so that the decoys are known, Percolator needs both targets and decoys",
+    )?;
+    if !decoys.iter().any(|d| *d) {
+        return Err(
+            "This PSM file does not contain any decoys, Percolator needs both targets and decoys",
+        );
+    }
+    Ok(decoys.iter().map(|d| if *d { -1 } else { 1 }).collect())
+}
+
+/// The peptidoform in PIN notation, without charge and with unknown flanking residues
+fn pin_peptide(peptide: &PeptidoformIonSet<Linked>) -> String {
+    format!(
+        "X.{}.X",
+        peptide
+            .peptidoform_ions()
+            .iter()
+            .map(|ion| ion.peptidoforms().iter().map(|p| p.to_string()).join("//"))
+            .join("+")
+    )
+}
+
+/// A single PSM in the PIN file
+struct PinLine {
+    id: String,
+    label: i8,
+    scan: usize,
+    mass: f64,
+    charge: isize,
+    features: Vec<f64>,
+    engine: Vec<(&'static str, f64)>,
+    peptide: String,
+    proteins: String,
+}
+
+/// Annotate every PSM of a PSM file with its spectrum from the open raw files and write a
+/// Percolator PIN file with the search engine scores and the annotation derived features.
+/// PSMs without a spectrum in the open raw files are skipped. The spectra are read while holding
+/// the state, after which the selection of the user is restored and the PSMs are annotated in the
+/// background. Returns a summary of the export.
+#[tauri::command]
+pub async fn export_pin(
+    file: usize,
+    path: String,
+    tolerance: (f64, &str),
+    noise_filter: (NoiseFilter, f32),
+    model: usize,
+    mass_mode: &str,
+    mz_range: (Option<f64>, Option<f64>),
+    isotopes: bool,
+    isotope_filter: f64,
+    state: ModifiableState<'_>,
+) -> Result<String, String> {
+    let (peptides, labels, psm_path) = {
+        let state = state.lock().await;
+        let files = state.psm_files();
+        let file = files
+            .iter()
+            .find(|f| f.id == file)
+            .ok_or("PSM file index not valid")?;
+        (
+            file.peptides.clone(),
+            labels(file.fdr().map(|fdr| fdr.decoy.as_slice()))?,
+            file.path.clone(),
+        )
+    };
+    let open_message = crate::raw_discovery::open_referenced_raw_files(
+        &state,
+        peptides
+            .iter()
+            .flat_map(|psm| crate::raw_discovery::referenced_raw_files(&psm.scans()))
+            .collect(),
+        std::path::Path::new(&psm_path)
+            .parent()
+            .map(std::path::Path::to_path_buf),
+    )
+    .await;
+    let mut state = state.lock().await;
+    let model = crate::model::get_models(&state)
+        .1
+        .get(model)
+        .map(|m| m.2.clone())
+        .ok_or_else(|| {
+            BoxedError::small(BasicKind::Error, "Invalid model", "Model does not exist")
+                .to_html(false)
+        })?;
+    let parameters = crate::model::parameters(tolerance, mz_range, isotopes, isotope_filter)
+        .map_err(|err| err.to_html(false))?;
+    let mass_mode = crate::model::mass_mode(mass_mode).map_err(|err| err.to_html(false))?;
+
+    let selection = state
+        .spectra
+        .iter()
+        .map(|file| (file.id(), file.selected()))
+        .collect_vec();
+    let mut spectra = Vec::new();
+    let mut skipped = 0;
+    for (index, psm) in peptides.into_iter().enumerate() {
+        if psm.peptidoform_ion_set().is_none() {
+            skipped += 1;
+            continue;
+        }
+        for file in &mut state.spectra {
+            file.clear_selected();
+        }
+        let (selected, _) = select_scans(&mut state, psm.scans());
+        match selected
+            .then(|| crate::spectra::create_selected_spectrum(&mut state, noise_filter).ok())
+            .flatten()
+        {
+            Some((_, spectrum)) => spectra.push((index, psm, spectrum)),
+            None => skipped += 1,
+        }
+    }
+    for file in &mut state.spectra {
+        let id = file.id();
+        file.set_selected(
+            selection
+                .iter()
+                .find(|(i, _)| *i == id)
+                .map(|(_, s)| s.clone())
+                .unwrap_or_default(),
+        );
+    }
+    drop(state);
+
+    let lines = tauri::async_runtime::spawn_blocking(move || {
+        spectra
+            .into_iter()
+            .filter_map(|(index, psm, spectrum)| {
+                let peptide = psm.peptidoform_ion_set()?.into_owned();
+                let charge = psm
+                    .charge()
+                    .map(|c| c.value)
+                    .or_else(|| {
+                        spectrum
+                            .precursor()
+                            .and_then(|p| p.ions.first().and_then(|i| i.charge.map(|c| c as isize)))
+                    })
+                    .unwrap_or(1);
+                let fragments =
+                    peptide.generate_theoretical_fragments(Charge::new::<e>(charge), &model);
+                let annotated =
+                    spectrum.annotate(peptide.clone(), &fragments, &parameters, mass_mode);
+                let (scores, _) = annotated.scores(&fragments, &parameters, mass_mode);
+                let fdr = (!annotated.peaks.is_empty())
+                    .then(|| annotated.fdr(&fragments, &parameters, mass_mode).0);
+                Some(PinLine {
+                    id: format!("{}_{index}", psm.format()),
+                    label: labels[index],
+                    scan: spectrum.index(),
+                    mass: psm.experimental_mass().map_or_else(
+                        || {
+                            peptide
+                                .formulas()
+                                .first()
+                                .map_or(0.0, |f| f.monoisotopic_mass().value)
+                        },
+                        |m| m.value,
+                    ),
+                    charge,
+                    features: [
+                        psm.score.unwrap_or_default(),
+                        psm.original_confidence()
+                            .map(|(score, _)| score)
+                            .unwrap_or_default(),
+                        psm.ppm_error()
+                            .map(|ppm| ppm.value.abs() * 1e6)
+                            .unwrap_or_default(),
+                        peptide.singular_peptidoform_ref().map_or(0, |p| p.len()) as f64,
+                    ]
+                    .into_iter()
+                    .chain(annotation_features(&scores.score, fdr.as_ref()))
+                    .collect(),
+                    engine: engine_features(&psm),
+                    peptide: pin_peptide(&peptide),
+                    proteins: psm
+                        .proteins()
+                        .iter()
+                        .map(|p| p.id().name().to_string())
+                        .join("\t"),
+                })
+            })
+            .collect_vec()
+    })
+    .await
+    .map_err(|err| err.to_string())?;
+
+    let max_charge = lines.iter().map(|l| l.charge).max().unwrap_or(1).max(1);
+    let engine_columns = lines
+        .iter()
+        .flat_map(|l| l.engine.iter().map(|(name, _)| *name))
+        .unique()
+        .collect_vec();
+    let write = || -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create(&path)?);
+        writeln!(
+            writer,
+            "SpecId\tLabel\tScanNr\tExpMass\tscore\toriginal_score\tabs_ppm_error\tlength\t{}\t{}{}\tPeptide\tProteins",
+            ANNOTATION_FEATURES.join("\t"),
+            (1..=max_charge).map(|c| format!("Charge{c}")).join("\t"),
+            engine_columns.iter().map(|c| format!("\t{c}")).join(""),
+        )?;
+        for line in &lines {
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}\t{}\t{}{}\t{}\t{}",
+                line.id,
+                line.label,
+                line.scan,
+                line.mass,
+                line.features.iter().join("\t"),
+                (1..=max_charge)
+                    .map(|c| if c == line.charge { "1" } else { "0" })
+                    .join("\t"),
+                engine_columns
+                    .iter()
+                    .map(|c| format!(
+                        "\t{}",
+                        line.engine
+                            .iter()
+                            .find(|(n, _)| n == c)
+                            .map_or(0.0, |(_, v)| *v)
+                    ))
+                    .join(""),
+                line.peptide,
+                line.proteins,
+            )?;
+        }
+        writer.flush()
+    };
+    write().map_err(|err| {
+        BoxedError::new(
+            BasicKind::Error,
+            "Could not export PIN file",
+            err.to_string(),
+            Context::show(path.clone()).to_owned(),
+        )
+        .to_html(false)
+    })?;
+    Ok(format!(
+        "Written {} PSMs, skipped {skipped} PSMs without a peptidoform or spectrum{}",
+        lines.len(),
+        open_message
+            .map(|message| format!(", not all raw files could be opened: {message}"))
+            .unwrap_or_default()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::labels;
+
+    #[test]
+    fn labels_need_fdr() {
+        assert!(labels(None).is_err());
+        assert!(labels(Some(&[false, false])).is_err());
+        assert_eq!(labels(Some(&[false, true, false])), Ok(vec![1, -1, 1]));
+    }
+}
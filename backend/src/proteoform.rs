@@ -0,0 +1,296 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+};
+
+use context_error::{BasicKind, BoxedError, CreateError, FullErrorContent};
+use itertools::Itertools;
+use mzannotate::{fragment::FragmentType, prelude::*};
+use mzcore::{
+    prelude::*,
+    system::{e, isize::Charge},
+};
+use mzdata::prelude::SpectrumLike;
+
+use crate::{ModifiableState, compare::parse_linear, spectra::NoiseFilter};
+
+/// The number of residues on a single row of the map
+const ROW_LENGTH: usize = 50;
+/// The number of residues in a block within a row
+const BLOCK_LENGTH: usize = 10;
+
+/// The observed fragments for a single bond, as label with all observed charges
+#[derive(Default)]
+struct Cleavage {
+    n_terminal: BTreeMap<String, BTreeSet<isize>>,
+    c_terminal: BTreeMap<String, BTreeSet<isize>>,
+}
+
+impl Cleavage {
+    fn is_empty(&self) -> bool {
+        self.n_terminal.is_empty() && self.c_terminal.is_empty()
+    }
+
+    fn title(&self) -> String {
+        self.n_terminal
+            .iter()
+            .chain(self.c_terminal.iter())
+            .map(|(label, charges)| {
+                format!(
+                    "{label} ({})",
+                    charges.iter().map(|c| format!("{c}+")).join(", ")
+                )
+            })
+            .join(", ")
+    }
+}
+
+/// Render a fragment map of a (long) proteoform on the selected spectrum. The sequence is wrapped
+/// in rows, between every two residues the N and C terminal cleavages are marked with the
+/// observed fragments and charges. For every modification the region it is localised to by the
+/// flanking cleavages is given. The characterisation score is the fraction of cleaved bonds
+/// multiplied by the average localisation (one over the number of residues in the region) of all
+/// modifications, terminal modifications are localised as well. For deconvolved spectra the
+/// fragments are only matched at charge 1.
+#[tauri::command]
+pub async fn proteoform_map(
+    peptide: &str,
+    deconvolved: bool,
+    tolerance: (f64, &str),
+    charge: Option<isize>,
+    noise_filter: (NoiseFilter, f32),
+    model: usize,
+    mass_mode: &str,
+    mz_range: (Option<f64>, Option<f64>),
+    isotopes: bool,
+    isotope_filter: f64,
+    state: ModifiableState<'_>,
+) -> Result<String, String> {
+    let mut state = state.lock().await;
+    let peptidoform = parse_linear(peptide, &state.ontologies)?;
+    let (_, spectrum) = crate::spectra::create_selected_spectrum(&mut state, noise_filter)
+        .map_err(|err| err.to_html(false))?;
+    let model = crate::model::get_models(&state)
+        .1
+        .get(model)
+        .map(|m| m.2.clone())
+        .ok_or_else(|| {
+            BoxedError::small(BasicKind::Error, "Invalid model", "Model does not exist")
+                .to_html(false)
+        })?;
+    let parameters = crate::model::parameters(tolerance, mz_range, isotopes, isotope_filter)
+        .map_err(|err| err.to_html(false))?;
+    let mass_mode = crate::model::mass_mode(mass_mode).map_err(|err| err.to_html(false))?;
+    // All peaks of a deconvolved spectrum are at charge 1
+    let deconvolved = deconvolved || spectrum.deconvoluted_peaks.is_some();
+    let charge = Charge::new::<e>(if deconvolved {
+        1
+    } else {
+        charge
+            .or_else(|| {
+                spectrum
+                    .precursor()
+                    .and_then(|p| p.ions.first().and_then(|i| i.charge.map(|c| c as isize)))
+            })
+            .unwrap_or(1)
+    });
+    let (peptidoform_ion_set, _) =
+        PeptidoformIonSet::pro_forma(&peptidoform.to_string(), &state.ontologies).map_err(
+            |errs| {
+                BoxedError::small(
+                    BasicKind::Error,
+                    "Could not create proteoform",
+                    peptidoform.to_string(),
+                )
+                .add_underlying_errors(errs)
+                .to_html(false)
+            },
+        )?;
+    let fragments = peptidoform_ion_set.generate_theoretical_fragments(charge, &model);
+    let annotated = spectrum.annotate(peptidoform_ion_set, &fragments, &parameters, mass_mode);
+
+    // Find the observed cleavages, bond `i` is between residue `i` and `i + 1`
+    let length = peptidoform.len();
+    let mut cleavages: Vec<Cleavage> = (0..length.saturating_sub(1))
+        .map(|_| Cleavage::default())
+        .collect();
+    for annotation in annotated.peaks.iter().flat_map(|p| p.annotations.iter()) {
+        let Some(position) = annotation.ion.position() else {
+            continue;
+        };
+        let (bond, n_terminal) = match annotation.ion {
+            FragmentType::a(..)
+            | FragmentType::b(..)
+            | FragmentType::c(..)
+            | FragmentType::d(..) => (position.series_number.checked_sub(1), true),
+            FragmentType::v(..)
+            | FragmentType::w(..)
+            | FragmentType::x(..)
+            | FragmentType::y(..)
+            | FragmentType::z(..) => (length.checked_sub(position.series_number + 1), false),
+            _ => continue,
+        };
+        let label = annotation.ion.label().1.to_string();
+        let Some(cleavage) = bond.and_then(|b| cleavages.get_mut(b)) else {
+            continue;
+        };
+        let list = if n_terminal {
+            &mut cleavage.n_terminal
+        } else {
+            &mut cleavage.c_terminal
+        };
+        list.entry(format!("{label}{}", position.series_number))
+            .or_default()
+            .insert(annotation.charge.value);
+    }
+    let cleaved = cleavages.iter().filter(|c| !c.is_empty()).count();
+    let coverage = cleaved as f64 / cleavages.len().max(1) as f64;
+
+    // Localise every modification to the region between the flanking cleavages, terminal
+    // modifications are localised to the region up to the first cleavage from that terminus
+    let first_cleaved = (0..cleavages.len()).find(|b| !cleavages[*b].is_empty());
+    let last_cleaved = (0..cleavages.len())
+        .rev()
+        .find(|b| !cleavages[*b].is_empty());
+    let mut regions = Vec::new();
+    if !peptidoform.get_n_term().is_empty() {
+        regions.push((
+            "N-term".to_string(),
+            peptidoform.get_n_term().iter().join(", "),
+            0,
+            first_cleaved.unwrap_or(length.saturating_sub(1)),
+        ));
+    }
+    regions.extend(
+        peptidoform
+            .sequence()
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| !s.modifications.is_empty())
+            .map(|(index, s)| {
+                let start = (0..index)
+                    .rev()
+                    .find(|b| !cleavages[*b].is_empty())
+                    .map_or(0, |b| b + 1);
+                let end = (index..cleavages.len())
+                    .find(|b| !cleavages[*b].is_empty())
+                    .unwrap_or(length - 1);
+                (
+                    format!("{}{}", s.aminoacid.pro_forma_definition(), index + 1),
+                    s.modifications.iter().join(", "),
+                    start,
+                    end,
+                )
+            }),
+    );
+    if !peptidoform.get_c_term().is_empty() {
+        regions.push((
+            "C-term".to_string(),
+            peptidoform.get_c_term().iter().join(", "),
+            last_cleaved.map_or(0, |b| b + 1),
+            length.saturating_sub(1),
+        ));
+    }
+    let localisation = if regions.is_empty() {
+        1.0
+    } else {
+        regions
+            .iter()
+            .map(|(_, _, start, end)| 1.0 / (end - start + 1) as f64)
+            .sum::<f64>()
+            / regions.len() as f64
+    };
+
+    let mut output = String::new();
+    write!(
+        output,
+        "<div class='proteoform-map'><p>{cleaved} of {} bonds cleaved ({:.2}%), characterisation score {:.3}{}</p><div class='rows'>",
+        cleavages.len(),
+        coverage * 100.0,
+        coverage * localisation,
+        if deconvolved {
+            ", deconvolved spectrum so fragments are matched at charge 1"
+        } else {
+            ""
+        },
+    )
+    .unwrap();
+    for (row, residues) in peptidoform
+        .sequence()
+        .iter()
+        .enumerate()
+        .chunks(ROW_LENGTH)
+        .into_iter()
+        .enumerate()
+    {
+        write!(
+            output,
+            "<div class='row'><span class='index'>{}</span>",
+            row * ROW_LENGTH + 1
+        )
+        .unwrap();
+        for (index, residue) in residues {
+            if index % ROW_LENGTH != 0 && index % BLOCK_LENGTH == 0 {
+                write!(output, "<span class='block'></span>").unwrap();
+            }
+            write!(
+                output,
+                "<span class='residue{}' title='{}{}{}'>{}</span>",
+                if residue.modifications.is_empty() {
+                    ""
+                } else {
+                    " modified"
+                },
+                index + 1,
+                if residue.modifications.is_empty() {
+                    ""
+                } else {
+                    ", "
+                },
+                residue.modifications.iter().join(", "),
+                residue.aminoacid.pro_forma_definition(),
+            )
+            .unwrap();
+            if let Some(cleavage) = cleavages.get(index) {
+                write!(
+                    output,
+                    "<span class='cleavage{}{}' title='{}'></span>",
+                    if cleavage.n_terminal.is_empty() {
+                        ""
+                    } else {
+                        " n"
+                    },
+                    if cleavage.c_terminal.is_empty() {
+                        ""
+                    } else {
+                        " c"
+                    },
+                    cleavage.title(),
+                )
+                .unwrap();
+            }
+        }
+        write!(output, "</div>").unwrap();
+    }
+    write!(output, "</div>").unwrap();
+    if !regions.is_empty() {
+        write!(
+            output,
+            "<table><thead><tr><th>Modification</th><th>Position</th><th>Localised to</th><th>Residues</th></tr></thead><tbody>"
+        )
+        .unwrap();
+        for (position, modifications, start, end) in &regions {
+            write!(
+                output,
+                "<tr><td>{modifications}</td><td>{position}</td><td>{}–{}</td><td>{}</td></tr>",
+                start + 1,
+                end + 1,
+                end - start + 1,
+            )
+            .unwrap();
+        }
+        write!(output, "</tbody></table>").unwrap();
+    }
+    write!(output, "</div>").unwrap();
+    Ok(output)
+}
@@ -1,17 +1,91 @@
-use std::sync::{Arc, OnceLock, atomic::AtomicUsize};
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    io::{BufRead, BufReader, BufWriter},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock, atomic::AtomicUsize},
+    time::SystemTime,
+};
 
 use mzalign::AlignIndex;
 use mzcore::{prelude::MassMode, sequence::Linked};
 use mzident::{MaybePeptidoform, PSM, PSMMetaData};
 use ordered_float::OrderedFloat;
+use serde::{Deserialize, Serialize};
 
-use crate::state::IndexSequence;
+use crate::{
+    fdr::FdrResult,
+    psm_table::{PSMRow, PSMSortColumn},
+    state::IndexSequence,
+};
 
 pub struct PSMFile {
     pub id: usize,
     pub path: String,
     pub peptides: Vec<PSM<Linked, MaybePeptidoform>>,
     pub index: OnceLock<AlignIndex<4, IndexSequence>>,
+    fdr: Option<FdrResult>,
+    /// The rows for the PSM table, built on first use
+    table: OnceLock<Vec<PSMRow>>,
+    /// The order of the table rows for every sort column and direction that was requested
+    table_order: Mutex<HashMap<(PSMSortColumn, bool), Arc<Vec<usize>>>>,
+    /// The location to store the alignment index and the key of the PSM file
+    index_cache: Option<(PathBuf, IndexKey)>,
+}
+
+/// Identifies the version of a PSM file an index was built for, based on the path, size, and a
+/// hash of the full contents so that a rewritten file never reuses a stale index
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+struct IndexKey {
+    path: PathBuf,
+    size: u64,
+    content: u64,
+}
+
+impl IndexKey {
+    fn new(path: &Path) -> Option<Self> {
+        let mut reader = BufReader::new(std::fs::File::open(path).ok()?);
+        let mut hasher = DefaultHasher::new();
+        let mut size = 0;
+        loop {
+            let buffer = reader.fill_buf().ok()?;
+            if buffer.is_empty() {
+                break;
+            }
+            hasher.write(buffer);
+            let length = buffer.len();
+            size += length as u64;
+            reader.consume(length);
+        }
+        Some(Self {
+            path: path.canonicalize().unwrap_or_else(|_| path.to_path_buf()),
+            size,
+            content: hasher.finish(),
+        })
+    }
+
+    /// The name of the file the index is stored in, this only depends on the path so that a
+    /// changed PSM file overwrites the index of the previous version. Note that the hash is not
+    /// guaranteed to be stable between Rust versions, a different hash only leads to rebuilding
+    /// the index.
+    fn file_name(&self) -> String {
+        let mut hasher = DefaultHasher::new();
+        self.path.hash(&mut hasher);
+        format!("{:016x}.{INDEX_EXTENSION}", hasher.finish())
+    }
+}
+
+/// The extension of stored indices, used to recognise them when pruning the cache
+const INDEX_EXTENSION: &str = "annotator-index";
+/// The maximal number of stored indices, the least recently used indices are removed first
+const MAXIMAL_STORED_INDICES: usize = 32;
+/// Stored indices not used for this long are removed
+const MAXIMAL_INDEX_AGE: std::time::Duration = std::time::Duration::from_secs(60 * 60 * 24 * 30);
+
+#[derive(Deserialize, Serialize)]
+struct StoredIndex<I> {
+    key: IndexKey,
+    index: I,
 }
 
 impl PSMFile {
@@ -29,13 +103,88 @@ impl PSMFile {
             path,
             peptides,
             index: OnceLock::default(),
+            fdr: None,
+            table: OnceLock::default(),
+            table_order: Mutex::default(),
+            index_cache: None,
         }
     }
 
+    pub fn fdr(&self) -> Option<&FdrResult> {
+        self.fdr.as_ref()
+    }
+
+    /// Set the FDR, this invalidates the cached PSM table as the q-values are part of the rows.
+    pub fn set_fdr(&mut self, fdr: FdrResult) {
+        self.fdr = Some(fdr);
+        self.table = OnceLock::default();
+        self.table_order.get_mut().unwrap().clear();
+    }
+
+    /// The rows for the PSM table for all PSMs in this file, in file order.
+    pub fn table_rows(&self) -> &[PSMRow] {
+        self.table.get_or_init(|| {
+            self.peptides
+                .iter()
+                .enumerate()
+                .map(|(index, psm)| {
+                    let mut row = PSMRow::new(self.id, index, psm);
+                    if let Some(fdr) = &self.fdr {
+                        row.q_value = fdr.q_values.get(index).copied().flatten();
+                        row.decoy = fdr.decoy.get(index).copied().unwrap_or_default();
+                    }
+                    row
+                })
+                .collect()
+        })
+    }
+
+    /// The indices of the table rows in the given order, the order is sorted once and then cached.
+    pub fn table_order(&self, sort: PSMSortColumn, descending: bool) -> Arc<Vec<usize>> {
+        let rows = self.table_rows();
+        self.table_order
+            .lock()
+            .unwrap()
+            .entry((sort, descending))
+            .or_insert_with(|| {
+                let mut order = (0..rows.len()).collect::<Vec<_>>();
+                order.sort_by(|a, b| {
+                    let ordering = sort.compare(&rows[*a], &rows[*b]);
+                    if descending {
+                        ordering.reverse()
+                    } else {
+                        ordering
+                    }
+                });
+                Arc::new(order)
+            })
+            .clone()
+    }
+
+    /// Store the alignment index in the cache directory once it is built. If a stored index for
+    /// this version of the PSM file exists it is loaded on the first search instead of rebuilt.
+    /// This reads the whole PSM file to hash its contents.
+    pub fn with_index_cache(mut self, cache_dir: Option<&Path>) -> Self {
+        self.index_cache = cache_dir.and_then(|dir| {
+            IndexKey::new(Path::new(&self.path))
+                .map(|key| (dir.join("indices").join(key.file_name()), key))
+        });
+        self
+    }
+
+    /// Check if the PSM passes the given FDR, if no FDR is computed for this file all PSMs pass.
+    /// Otherwise only targets with a q-value at or below the given FDR pass.
+    pub fn passes_fdr(&self, index: usize, maximal_fdr: Option<f64>) -> bool {
+        maximal_fdr.is_none_or(|fdr| self.fdr().is_none_or(|r| r.passes(index, fdr)))
+    }
+
+    /// Get the alignment index, this loads the stored index or builds it on first use so this
+    /// should not be called on the main thread.
     pub fn index(&self) -> &AlignIndex<4, IndexSequence> {
-        if let Some(index) = self.index.get() {
-            index
-        } else {
+        self.index.get_or_init(|| {
+            if let Some(index) = self.load_index() {
+                return index;
+            }
             let index = AlignIndex::new(
                 self.peptides.iter().enumerate().filter_map(|(index, psm)| {
                     psm.peptidoform_ion_set()
@@ -44,14 +193,89 @@ impl PSMFile {
                         .map(|p| IndexSequence {
                             sequence: Arc::new(p),
                             score: psm.score.map(OrderedFloat),
-                            id: self.id,
                             index,
                         })
                 }),
                 MassMode::Monoisotopic,
             );
-            let _ = self.index.set(index);
-            self.index.get().unwrap()
+            self.store_index(&index);
+            index
+        })
+    }
+
+    /// Load the stored index if it was built for this version of the PSM file.
+    fn load_index(&self) -> Option<AlignIndex<4, IndexSequence>> {
+        let (location, key) = self.index_cache.as_ref()?;
+        let file = std::fs::File::open(location).ok()?;
+        let stored: StoredIndex<AlignIndex<4, IndexSequence>> =
+            bincode::serde::decode_from_std_read(
+                &mut BufReader::new(file),
+                bincode::config::standard(),
+            )
+            .ok()?;
+        if stored.key != *key {
+            return None;
+        }
+        // Mark the index as recently used so it is not pruned
+        let _ = std::fs::File::options()
+            .append(true)
+            .open(location)
+            .and_then(|file| file.set_modified(SystemTime::now()));
+        Some(stored.index)
+    }
+
+    /// Store the index in the cache directory and prune old indices, failing to store the index
+    /// only means it has to be rebuilt next time.
+    fn store_index(&self, index: &AlignIndex<4, IndexSequence>) {
+        let Some((location, key)) = &self.index_cache else {
+            return;
+        };
+        let Some(dir) = location.parent() else {
+            return;
+        };
+        let _ = std::fs::create_dir_all(dir);
+        let stored = StoredIndex {
+            key: key.clone(),
+            index,
+        };
+        if let Ok(file) = std::fs::File::create(location)
+            && bincode::serde::encode_into_std_write(
+                &stored,
+                &mut BufWriter::new(file),
+                bincode::config::standard(),
+            )
+            .is_err()
+        {
+            let _ = std::fs::remove_file(location);
+        }
+        prune_indices(dir);
+    }
+}
+
+/// Remove stored indices that were not used recently and if there are too many stored indices
+/// remove the least recently used.
+fn prune_indices(dir: &Path) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    let now = SystemTime::now();
+    let mut indices = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let path = entry.path();
+            (path.extension()? == INDEX_EXTENSION)
+                .then(|| Some((entry.metadata().ok()?.modified().ok()?, path)))
+                .flatten()
+        })
+        .collect::<Vec<_>>();
+    indices.sort_by(|a, b| b.0.cmp(&a.0));
+    for (index, (modified, path)) in indices.into_iter().enumerate() {
+        if index >= MAXIMAL_STORED_INDICES
+            || now
+                .duration_since(modified)
+                .is_ok_and(|age| age > MAXIMAL_INDEX_AGE)
+        {
+            let _ = std::fs::remove_file(path);
         }
     }
 }
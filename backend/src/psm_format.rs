@@ -0,0 +1,385 @@
+use std::{
+    fmt::Write as _,
+    io::{BufRead, BufReader, BufWriter},
+    path::Path,
+};
+
+use context_error::{BasicKind, BoxedError, Context, CreateError, FullErrorContent};
+use itertools::Itertools;
+use mzcore::sequence::Linked;
+use mzident::{MaybePeptidoform, PSM, PSMSource, SpectrumSequenceListData};
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+use tokio::sync::Mutex;
+
+use crate::{CUSTOM_PSM_FORMATS_FILE, ModifiableState, state::State};
+
+/// A user defined mapping from the columns of a CSV/TSV file to the information needed for a PSM.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CustomPSMFormat {
+    pub name: String,
+    /// The column separator, for example `,` or `\t`
+    pub separator: char,
+    pub sequence: String,
+    pub charge: String,
+    pub scan: String,
+    pub raw_file: String,
+    pub score: Option<String>,
+    /// The range of the score, used to normalise the score to 0..=1
+    pub score_range: Option<(f64, f64)>,
+    #[serde(default)]
+    pub retention_time: Option<String>,
+    /// If the retention time column is in seconds instead of minutes
+    #[serde(default)]
+    pub retention_time_in_seconds: bool,
+    /// If the scan column contains one based scan numbers instead of zero based indices
+    pub one_based_scans: bool,
+    pub modifications: ModificationSyntax,
+    /// Remove flanking residues as in `K.PEPTIDE.R`
+    pub strip_flanking: bool,
+}
+
+/// The syntax used to write modifications in the sequence column
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub enum ModificationSyntax {
+    /// Already valid ProForma, eg `PEM[U:Oxidation]TIDE` or `PEM[+15.995]TIDE`
+    #[default]
+    ProForma,
+    /// Round brackets, eg `PEM(Oxidation)TIDE` or `PEM(15.995)TIDE`
+    RoundBrackets,
+    /// Square brackets with unsigned mass shifts, eg `PEM[15.995]TIDE`
+    UnsignedMass,
+}
+
+impl CustomPSMFormat {
+    /// Convert a sequence as written in the file to ProForma
+    fn to_pro_forma(&self, sequence: &str) -> String {
+        let mut sequence = sequence.trim();
+        if self.strip_flanking {
+            let parts = sequence.split('.').collect_vec();
+            if parts.len() == 3 && parts[0].len() <= 1 && parts[2].len() <= 1 {
+                sequence = parts[1];
+            }
+        }
+        let sequence = match self.modifications {
+            ModificationSyntax::ProForma => return sequence.to_string(),
+            ModificationSyntax::RoundBrackets => sequence.replace('(', "[").replace(')', "]"),
+            ModificationSyntax::UnsignedMass => sequence.to_string(),
+        };
+        // Add a sign to all unsigned mass shifts
+        sequence
+            .split('[')
+            .enumerate()
+            .map(|(index, part)| {
+                if index > 0 && part.starts_with(|c: char| c.is_ascii_digit()) {
+                    format!("[+{part}")
+                } else if index > 0 {
+                    format!("[{part}")
+                } else {
+                    part.to_string()
+                }
+            })
+            .join("")
+    }
+
+    fn normalise_score(&self, score: f64) -> f64 {
+        self.score_range.map_or(score, |(min, max)| {
+            ((score - min) / (max - min)).clamp(0.0, 1.0)
+        })
+    }
+}
+
+/// Split a line on the separator, respecting double quoted fields, where a double quote inside a
+/// quoted field is written as two double quotes
+fn split_line(line: &str, separator: char) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '"' && quoted && chars.peek() == Some(&'"') {
+            field.push(chars.next().unwrap());
+        } else if c == '"' {
+            quoted = !quoted;
+        } else if c == separator && !quoted {
+            fields.push(std::mem::take(&mut field));
+        } else {
+            field.push(c);
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+/// Quote a field if it contains the separator, a double quote, or a line break
+fn quote(field: &str, separator: char) -> String {
+    if field.contains([separator, '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Open a PSM file with the first custom format whose columns are all present in the header.
+/// Returns `None` if no custom format matches the file. The file is converted in memory to a
+/// spectrum sequence list (SSL), which is parsed explicitly as that format, and the scores are set
+/// afterwards.
+pub fn open_custom_psm_file(
+    path: &Path,
+    state: &State,
+) -> Option<
+    Result<Vec<Result<PSM<Linked, MaybePeptidoform>, BoxedError<'static, BasicKind>>>, String>,
+> {
+    let file = std::fs::File::open(path).ok()?;
+    let mut lines = BufReader::new(file).lines().map_while(Result::ok);
+    let header = lines.next()?;
+    let (format, columns) = state.custom_psm_formats.iter().find_map(|format| {
+        let header = split_line(&header, format.separator)
+            .into_iter()
+            .map(|h| h.trim().to_lowercase())
+            .collect_vec();
+        let column = |name: &str| header.iter().position(|h| *h == name.trim().to_lowercase());
+        let optional =
+            |name: &Option<String>| name.as_ref().map_or(Some(None), |n| column(n).map(Some));
+        Some((
+            format,
+            (
+                column(&format.sequence)?,
+                column(&format.charge)?,
+                column(&format.scan)?,
+                column(&format.raw_file)?,
+                optional(&format.score)?,
+                optional(&format.retention_time)?,
+            ),
+        ))
+    })?;
+    let (sequence, charge, scan, raw_file, score, retention_time) = columns;
+
+    let mut scores = Vec::new();
+    let mut ssl = String::from("file\tscan\tcharge\tsequence\tstart-time\tend-time\n");
+    for line in lines.filter(|l| !l.trim().is_empty()) {
+        let fields = split_line(&line, format.separator);
+        let get = |index: usize| fields.get(index).map_or("", |f| f.trim());
+        // SSL files use one based scan numbers
+        let scan_number = if format.one_based_scans {
+            get(scan).to_string()
+        } else {
+            get(scan)
+                .parse::<usize>()
+                .map_or_else(|_| get(scan).to_string(), |s| (s + 1).to_string())
+        };
+        // SSL files use minutes for the retention time
+        let retention_time = retention_time
+            .and_then(|rt| get(rt).parse::<f64>().ok())
+            .map(|rt| {
+                if format.retention_time_in_seconds {
+                    rt / 60.0
+                } else {
+                    rt
+                }
+            })
+            .map_or(String::new(), |rt| rt.to_string());
+        writeln!(
+            ssl,
+            "{}\t{}\t{}\t{}\t{retention_time}\t{retention_time}",
+            quote(get(raw_file), '\t'),
+            quote(&scan_number, '\t'),
+            quote(get(charge), '\t'),
+            quote(&format.to_pro_forma(get(sequence)), '\t'),
+        )
+        .unwrap();
+        scores.push(
+            score
+                .and_then(|s| get(s).parse::<f64>().ok())
+                .map(|s| format.normalise_score(s)),
+        );
+    }
+
+    Some(
+        SpectrumSequenceListData::parse_reader(
+            std::io::Cursor::new(ssl),
+            &state.ontologies,
+            false,
+            None,
+        )
+        .map(|peptides| {
+            peptides
+                .zip(scores)
+                .map(|(psm, score)| {
+                    psm.map(|data| {
+                        let mut psm = PSM::from(data).cast();
+                        if score.is_some() {
+                            psm.score = score;
+                        }
+                        psm
+                    })
+                })
+                .collect_vec()
+        })
+        .map_err(|err| {
+            BoxedError::new(
+                BasicKind::Error,
+                "Could not open PSM file",
+                format!("The file was recognised as custom format '{}'", format.name),
+                Context::show(path.to_string_lossy()).to_owned(),
+            )
+            .add_underlying_errors(vec![err])
+            .to_html(false)
+        }),
+    )
+}
+
+#[tauri::command]
+pub async fn get_custom_psm_formats(
+    state: ModifiableState<'_>,
+) -> Result<Vec<CustomPSMFormat>, ()> {
+    Ok(state.lock().await.custom_psm_formats.clone())
+}
+
+/// Add a new custom PSM format (`original_name` is `None`) or update the existing format with the
+/// given name, and store all custom PSM formats in the configuration file.
+#[tauri::command]
+pub async fn update_custom_psm_format(
+    original_name: Option<String>,
+    format: CustomPSMFormat,
+    app: tauri::AppHandle,
+) -> Result<(), String> {
+    if format.name.trim().is_empty() {
+        return Err("The name of a custom PSM format cannot be empty".to_string());
+    }
+    let handle = app.state::<Mutex<State>>();
+    let mut state = handle.lock().await;
+    if original_name.as_ref() != Some(&format.name)
+        && state
+            .custom_psm_formats
+            .iter()
+            .any(|f| f.name == format.name)
+    {
+        return Err(format!(
+            "A custom PSM format with the name '{}' already exists",
+            format.name
+        ));
+    }
+    if let Some(original_name) = original_name {
+        *state
+            .custom_psm_formats
+            .iter_mut()
+            .find(|f| f.name == original_name)
+            .ok_or("Given format does not exist")? = format;
+    } else {
+        state.custom_psm_formats.push(format);
+    }
+    save_custom_psm_formats(&app, &state.custom_psm_formats)
+}
+
+#[tauri::command]
+pub async fn delete_custom_psm_format(name: String, app: tauri::AppHandle) -> Result<(), String> {
+    let handle = app.state::<Mutex<State>>();
+    let mut state = handle.lock().await;
+    let length = state.custom_psm_formats.len();
+    state.custom_psm_formats.retain(|f| f.name != name);
+    if state.custom_psm_formats.len() < length {
+        save_custom_psm_formats(&app, &state.custom_psm_formats)
+    } else {
+        Err("Given format does not exist".to_string())
+    }
+}
+
+fn save_custom_psm_formats(
+    app: &tauri::AppHandle,
+    formats: &[CustomPSMFormat],
+) -> Result<(), String> {
+    let path = app
+        .path()
+        .app_config_dir()
+        .map(|dir| dir.join(CUSTOM_PSM_FORMATS_FILE))
+        .map_err(|e| {
+            BoxedError::small(
+                BasicKind::Error,
+                "Cannot find app data directory",
+                e.to_string(),
+            )
+            .to_html(false)
+        })?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|err| {
+            BoxedError::new(
+                BasicKind::Error,
+                "Could not create parent directories for custom PSM formats configuration file",
+                err.to_string(),
+                Context::show(parent.to_string_lossy()).to_owned(),
+            )
+            .to_html(false)
+        })?;
+    }
+    let file = BufWriter::new(std::fs::File::create(&path).map_err(|err| {
+        BoxedError::new(
+            BasicKind::Error,
+            "Could not open custom PSM formats configuration file",
+            err.to_string(),
+            Context::show(path.to_string_lossy()).to_owned(),
+        )
+        .to_html(false)
+    })?);
+    serde_json::to_writer_pretty(file, formats).map_err(|err| {
+        BoxedError::small(
+            BasicKind::Error,
+            "Could not write custom PSM formats to configuration file",
+            err.to_string(),
+        )
+        .to_html(false)
+    })
+}
+
+/// Load the custom PSM formats from the configuration directory. If the file cannot be parsed it
+/// is renamed to prevent it from being overwritten and the parse error is stored next to it.
+pub fn load_custom_psm_formats(path: &Path) -> Result<Vec<CustomPSMFormat>, String> {
+    let path = path.join(CUSTOM_PSM_FORMATS_FILE);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let file = std::fs::File::open(&path).map_err(|err| err.to_string())?;
+    serde_json::from_reader(BufReader::new(file)).map_err(|err| {
+        let now = chrono::Local::now().format("%Y%m%d%H%M%S%.3f");
+        let mut error = BoxedError::new(
+            BasicKind::Error,
+            "Could not parse custom PSM formats",
+            err.to_string(),
+            Context::show(path.to_string_lossy()).to_owned(),
+        );
+        let backup = path.with_file_name(format!("{CUSTOM_PSM_FORMATS_FILE}_backup_{now}.json"));
+        if let Err(err) = std::fs::rename(&path, &backup) {
+            error = error.add_underlying_errors(vec![BoxedError::small(
+                BasicKind::Error,
+                "Could not rename custom PSM formats file",
+                err.to_string(),
+            )]);
+        }
+        if let Err(err) = std::fs::write(
+            path.with_file_name(format!("error_{now}.txt")),
+            error.to_string().as_bytes(),
+        ) {
+            error = error.add_underlying_errors(vec![BoxedError::small(
+                BasicKind::Error,
+                "Could not save custom PSM formats parse error file",
+                err.to_string(),
+            )]);
+        }
+        error.to_html(false)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{quote, split_line};
+
+    #[test]
+    fn quote_round_trip() {
+        for field in ["plain", "a,b", "say \"hi\"", "\"", "tab\there"] {
+            for separator in [',', '\t'] {
+                let line = format!("{}{separator}end", quote(field, separator));
+                assert_eq!(split_line(&line, separator), [field, "end"]);
+            }
+        }
+    }
+}
@@ -0,0 +1,324 @@
+use std::{
+    cmp::Ordering,
+    fs::File,
+    io::{BufWriter, Write},
+};
+
+use context_error::{BasicKind, BoxedError, Context, CreateError, FullErrorContent};
+use itertools::Itertools;
+use mzcore::sequence::Linked;
+use mzident::{MaybePeptidoform, PSM, PSMMetaData, SpectrumIds};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ModifiableState, Theme,
+    metadata_render::{OptionalString, RenderToTable},
+    psm_file::PSMFile,
+};
+
+/// A single row in the PSM table, with the general metadata that is available for all PSM formats.
+#[derive(Clone, Debug, Serialize)]
+pub struct PSMRow {
+    pub file: usize,
+    pub index: usize,
+    pub peptide: String,
+    pub score: Option<f64>,
+    pub charge: Option<isize>,
+    /// m/z in Thomson
+    pub mz: Option<f64>,
+    /// Precursor mass error in ppm
+    pub mass_error: Option<f64>,
+    pub length: Option<usize>,
+    pub modifications: Vec<String>,
+    pub proteins: Vec<String>,
+    pub raw_files: Vec<String>,
+    pub scans: String,
+    /// Retention time in minutes
+    pub rt: Option<f64>,
+    pub format: String,
+    /// The q-value if the FDR is computed for this file
+    pub q_value: Option<f64>,
+    pub decoy: bool,
+    /// The format specific columns, only filled in for the rows on a page
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub data: Vec<(&'static str, String)>,
+}
+
+impl PSMRow {
+    pub fn new(file: usize, index: usize, psm: &PSM<Linked, MaybePeptidoform>) -> Self {
+        let peptidoform_ion_set = psm.peptidoform_ion_set();
+        let (raw_files, scans) = match psm.scans() {
+            SpectrumIds::None => (Vec::new(), String::new()),
+            SpectrumIds::FileNotKnown(scans) => (Vec::new(), scans.iter().join(";")),
+            SpectrumIds::FileKnown(scans) => (
+                scans
+                    .iter()
+                    .map(|(file, _)| {
+                        file.file_name()
+                            .map_or(String::new(), |s| s.to_string_lossy().to_string())
+                    })
+                    .collect(),
+                scans.iter().flat_map(|(_, scans)| scans.iter()).join(";"),
+            ),
+        };
+        Self {
+            file,
+            index,
+            peptide: peptidoform_ion_set
+                .as_ref()
+                .map(|p| p.to_string())
+                .unwrap_or_default(),
+            score: psm.score,
+            charge: psm.charge().map(|c| c.value),
+            mz: psm.experimental_mz().map(|mz| mz.value),
+            mass_error: psm.ppm_error().map(|ppm| ppm.value * 1e6),
+            length: peptidoform_ion_set
+                .as_ref()
+                .and_then(|p| p.singular_peptidoform_ref().map(|p| p.len())),
+            modifications: peptidoform_ion_set
+                .as_ref()
+                .map(|p| {
+                    p.peptidoform_ions()
+                        .iter()
+                        .flat_map(|p| p.peptidoforms())
+                        .flat_map(|p| {
+                            p.get_n_term()
+                                .iter()
+                                .chain(p.sequence().iter().flat_map(|s| s.modifications.iter()))
+                                .chain(p.get_c_term().iter())
+                                .map(|m| m.to_string())
+                                .collect_vec()
+                        })
+                        .unique()
+                        .collect()
+                })
+                .unwrap_or_default(),
+            proteins: psm
+                .proteins()
+                .iter()
+                .map(|p| p.id().name().to_string())
+                .collect(),
+            raw_files,
+            scans,
+            rt: psm
+                .retention_time()
+                .map(|t| t.get::<mzcore::system::time::min>()),
+            format: psm.format().to_string(),
+            q_value: None,
+            decoy: false,
+            data: Vec::new(),
+        }
+    }
+}
+
+/// The filters for the PSM table, all bounds are inclusive and all text filters are case insensitive substring matches.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct PSMFilter {
+    pub score: (Option<f64>, Option<f64>),
+    pub charge: (Option<isize>, Option<isize>),
+    /// Absolute precursor mass error in ppm
+    pub mass_error: Option<f64>,
+    pub length: (Option<usize>, Option<usize>),
+    pub modification: Option<String>,
+    pub protein: Option<String>,
+    pub raw_file: Option<String>,
+    /// Only show target PSMs with a q-value at or below this value, only applied if the FDR is
+    /// computed for this file
+    pub maximal_fdr: Option<f64>,
+}
+
+impl PSMFilter {
+    /// Check if the row passes the filter, the FDR filter is applied in the same way as everywhere
+    /// else (see [`PSMFile::passes_fdr`]).
+    pub fn matches(&self, file: &PSMFile, row: &PSMRow) -> bool {
+        fn in_range<T: PartialOrd>(value: Option<T>, (min, max): &(Option<T>, Option<T>)) -> bool {
+            (min.is_none() && max.is_none())
+                || value.is_some_and(|v| {
+                    min.as_ref().is_none_or(|min| v >= *min)
+                        && max.as_ref().is_none_or(|max| v <= *max)
+                })
+        }
+        fn contains(values: &[String], filter: &Option<String>) -> bool {
+            filter
+                .as_ref()
+                .filter(|f| !f.trim().is_empty())
+                .is_none_or(|filter| {
+                    let filter = filter.trim().to_lowercase();
+                    values.iter().any(|v| v.to_lowercase().contains(&filter))
+                })
+        }
+
+        in_range(row.score, &self.score)
+            && in_range(row.charge, &self.charge)
+            && in_range(row.length, &self.length)
+            && self
+                .mass_error
+                .is_none_or(|max| row.mass_error.is_some_and(|e| e.abs() <= max))
+            && contains(&row.modifications, &self.modification)
+            && contains(&row.proteins, &self.protein)
+            && contains(&row.raw_files, &self.raw_file)
+            && file.passes_fdr(row.index, self.maximal_fdr)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Hash, Serialize)]
+pub enum PSMSortColumn {
+    #[default]
+    Index,
+    Peptide,
+    Score,
+    Charge,
+    MassError,
+    Length,
+    Modifications,
+    Protein,
+    RawFile,
+    RetentionTime,
+    QValue,
+}
+
+impl PSMSortColumn {
+    pub(crate) fn compare(self, a: &PSMRow, b: &PSMRow) -> Ordering {
+        /// Sort missing values last
+        fn optional<T: PartialOrd>(a: Option<T>, b: Option<T>) -> Ordering {
+            match (a, b) {
+                (Some(a), Some(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            }
+        }
+        match self {
+            Self::Index => (a.file, a.index).cmp(&(b.file, b.index)),
+            Self::Peptide => a.peptide.cmp(&b.peptide),
+            Self::Score => optional(a.score, b.score),
+            Self::Charge => optional(a.charge, b.charge),
+            Self::MassError => optional(a.mass_error.map(f64::abs), b.mass_error.map(f64::abs)),
+            Self::Length => optional(a.length, b.length),
+            Self::Modifications => a.modifications.len().cmp(&b.modifications.len()),
+            Self::Protein => a.proteins.cmp(&b.proteins),
+            Self::RawFile => a.raw_files.cmp(&b.raw_files),
+            Self::RetentionTime => optional(a.rt, b.rt),
+            Self::QValue => optional(a.q_value, b.q_value),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PSMTablePage {
+    /// The total number of PSMs passing the filter
+    pub total: usize,
+    pub rows: Vec<PSMRow>,
+}
+
+/// Get all rows of a PSM file that pass the filter, in the given order. The rows and the order are
+/// cached on the file so only the filter is applied on every call.
+pub fn filtered_rows<'a>(
+    file: &'a PSMFile,
+    filter: &PSMFilter,
+    sort: PSMSortColumn,
+    descending: bool,
+) -> Vec<&'a PSMRow> {
+    let rows = file.table_rows();
+    file.table_order(sort, descending)
+        .iter()
+        .map(|index| &rows[*index])
+        .filter(|row| filter.matches(file, row))
+        .collect_vec()
+}
+
+/// Get a single page of the filtered and sorted PSMs of an open PSM file.
+#[tauri::command]
+pub async fn get_psm_table(
+    file: usize,
+    filter: PSMFilter,
+    sort: PSMSortColumn,
+    descending: bool,
+    page: usize,
+    page_size: usize,
+    theme: Theme,
+    state: ModifiableState<'_>,
+) -> Result<PSMTablePage, &'static str> {
+    let state = state.lock().await;
+    let files = state.psm_files();
+    let file = files
+        .iter()
+        .find(|f| f.id == file)
+        .ok_or("PSM file index not valid")?;
+    let rows = file.table_rows();
+    let mut total = 0;
+    let mut page_rows = Vec::with_capacity(page_size);
+    for index in file.table_order(sort, descending).iter() {
+        let row = &rows[*index];
+        if filter.matches(file, row) {
+            if total >= page * page_size && page_rows.len() < page_size {
+                let mut row = row.clone();
+                row.data = file.peptides[row.index].data.to_table(theme);
+                page_rows.push(row);
+            }
+            total += 1;
+        }
+    }
+    Ok(PSMTablePage {
+        total,
+        rows: page_rows,
+    })
+}
+
+/// Export all filtered and sorted PSMs of an open PSM file as a TSV file.
+#[tauri::command]
+pub async fn export_psm_table(
+    file: usize,
+    filter: PSMFilter,
+    sort: PSMSortColumn,
+    descending: bool,
+    path: &str,
+    state: ModifiableState<'_>,
+) -> Result<(), String> {
+    let state = state.lock().await;
+    let files = state.psm_files();
+    let file = files
+        .iter()
+        .find(|f| f.id == file)
+        .ok_or("PSM file index not valid")?;
+    let rows = filtered_rows(file, &filter, sort, descending);
+    let write = || -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(
+            writer,
+            "file\tindex\tpeptide\tscore\tcharge\tmz\tmass_error_ppm\tlength\tmodifications\tproteins\traw_files\tscans\trt_min\tformat\tq_value\tdecoy"
+        )?;
+        for row in rows {
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                row.file,
+                row.index,
+                row.peptide,
+                row.score.to_optional_string(),
+                row.charge.to_optional_string(),
+                row.mz.to_optional_string(),
+                row.mass_error.to_optional_string(),
+                row.length.to_optional_string(),
+                row.modifications.join(";"),
+                row.proteins.join(";"),
+                row.raw_files.join(";"),
+                row.scans,
+                row.rt.to_optional_string(),
+                row.format,
+                row.q_value.to_optional_string(),
+                row.decoy,
+            )?;
+        }
+        writer.flush()
+    };
+    write().map_err(|err| {
+        BoxedError::new(
+            BasicKind::Error,
+            "Could not export PSMs",
+            err.to_string(),
+            Context::show(path).to_owned(),
+        )
+        .to_html(false)
+    })
+}
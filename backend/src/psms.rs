@@ -1,12 +1,19 @@
 use context_error::{BasicKind, BoxedError, Context, CreateError, FullErrorContent, combine_error};
 use itertools::Itertools;
 use mzalign::{AlignScoring, AlignType, Alignment};
-use mzcore::{prelude::*, sequence::Linked};
+use mzcore::{
+    molecular_formula,
+    ontology::Ontologies,
+    prelude::*,
+    sequence::{Linked, ReturnModification, SimpleModification, SimpleModificationInner},
+};
 use mzident::*;
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 
-use crate::{ModifiableState, html_builder, psm_file::PSMFile, state::State};
+use crate::{
+    ModifiableState, html_builder, psm_file::PSMFile, raw_discovery::raw_file_stem, state::State,
+};
 
 /// Open a file and get all individual peptide errors.
 /// # Errors
@@ -25,10 +32,16 @@ pub fn annotator_open_psm_file(
     state: &mut tokio::sync::MutexGuard<'_, State>,
 ) -> Result<Option<String>, String> {
     let mut peptide_errors = Vec::new();
-    let peptides = open_psm_file(path, &state.ontologies, false).map_err(|e| e.to_html(false))?;
-    state.psm_files_mut().push(PSMFile::new(
+    // Tables that are not recognised as any supported format are tried with the custom formats
+    let peptides: Vec<_> = match open_psm_file(path, &state.ontologies, false) {
+        Ok(peptides) => peptides.collect(),
+        Err(error) => crate::psm_format::open_custom_psm_file(path, state)
+            .unwrap_or_else(|| Err(error.to_html(false)))?,
+    };
+    let file = PSMFile::new(
         path.to_string_lossy().to_string(),
         peptides
+            .into_iter()
             .filter_map(|p| match p {
                 Ok(p) => Some(p),
                 Err(e) => {
@@ -37,7 +50,9 @@ pub fn annotator_open_psm_file(
                 }
             })
             .collect(),
-    ));
+    )
+    .with_index_cache(state.cache_dir.as_deref());
+    state.psm_files_mut().push(file);
     if peptide_errors.is_empty() {
         Ok(None)
     } else {
@@ -83,11 +98,17 @@ pub async fn get_identified_peptides_files(
         .collect_vec())
 }
 
+/// Search all open PSM files for a peptidoform with mass based alignment, optionally restricted to
+/// PSMs that match the metadata query. If no peptidoform is given all PSMs matching the query are
+/// returned sorted on score. If a maximal FDR is given only target PSMs that pass this FDR are
+/// returned for files where the FDR is computed.
 #[tauri::command]
 pub async fn search_peptide<'a>(
     text: &'a str,
+    query: Option<PSMQuery>,
     minimal_match_score: f64,
     minimal_peptide_score: f64,
+    maximal_fdr: Option<f64>,
     amount: usize,
     state: ModifiableState<'a>,
 ) -> Result<String, String> {
@@ -98,6 +119,23 @@ pub async fn search_peptide<'a>(
         );
     }
     let state = state.lock().await;
+    let filter = query
+        .map(|query| PSMQueryFilter::new(query, &state.ontologies))
+        .transpose()?;
+    if text.trim().is_empty() {
+        return filter.map_or_else(
+            || Ok("Give a peptidoform or metadata to search for.".to_string()),
+            |filter| {
+                Ok(search_peptide_metadata(
+                    &state.psm_files(),
+                    &filter,
+                    minimal_peptide_score,
+                    maximal_fdr,
+                    amount,
+                ))
+            },
+        );
+    }
     let query = std::sync::Arc::new(
         Peptidoform::<Linked>::pro_forma(text, &state.ontologies)
             .map_err(|errs| {
@@ -129,24 +167,31 @@ pub async fn search_peptide<'a>(
             file.index()
                 .par_align_one_filtered(
                     query.clone(),
-                    |p| p.score.is_none_or(|score| score.0 >= minimal_peptide_score),
+                    |p| {
+                        p.score.is_none_or(|score| score.0 >= minimal_peptide_score)
+                            && file.passes_fdr(p.index, maximal_fdr)
+                            && filter
+                                .as_ref()
+                                .is_none_or(|filter| filter.matches(&file.peptides[p.index]))
+                    },
                     AlignScoring::default(),
                     AlignType::GLOBAL_B,
                 )
                 .filter(|alignment| alignment.normalised_score() >= minimal_match_score)
+                .map(|alignment| (alignment, file.id))
         })
         .fold(
             || Vec::with_capacity(amount),
             |mut acc, x| {
                 if acc.len() < amount {
                     let index = acc
-                        .binary_search_by(|a: &Alignment<_, _>| a.cmp(&x).reverse())
+                        .binary_search_by(|a: &(Alignment<_, _>, usize)| a.cmp(&x).reverse())
                         .unwrap_or_else(|v| v);
                     acc.insert(index, x);
                 } else if acc.last().is_some_and(|v| *v < x) {
                     acc.pop();
                     let index = acc
-                        .binary_search_by(|a: &Alignment<_, _>| a.cmp(&x).reverse())
+                        .binary_search_by(|a: &(Alignment<_, _>, usize)| a.cmp(&x).reverse())
                         .unwrap_or_else(|v| v);
                     acc.insert(index, x);
                 }
@@ -157,13 +202,13 @@ pub async fn search_peptide<'a>(
             for x in h2 {
                 if acc.len() < amount {
                     let index = acc
-                        .binary_search_by(|a: &Alignment<_, _>| a.cmp(&x).reverse())
+                        .binary_search_by(|a: &(Alignment<_, _>, usize)| a.cmp(&x).reverse())
                         .unwrap_or_else(|v| v);
                     acc.insert(index, x);
                 } else if acc.last().is_some_and(|v| *v < x) {
                     acc.pop();
                     let index = acc
-                        .binary_search_by(|a: &Alignment<_, _>| a.cmp(&x).reverse())
+                        .binary_search_by(|a: &(Alignment<_, _>, usize)| a.cmp(&x).reverse())
                         .unwrap_or_else(|v| v);
                     acc.insert(index, x);
                 }
@@ -171,7 +216,7 @@ pub async fn search_peptide<'a>(
             acc
         })
         .into_iter()
-        .map(|alignment| {
+        .map(|(alignment, file)| {
             let start = alignment.start_a();
             let end = alignment.start_a() + alignment.len_a();
             let sequence = alignment.seq_a().peptidoform();
@@ -196,9 +241,9 @@ pub async fn search_peptide<'a>(
             vec![
                 format!(
                     "<a onclick=\"load_peptide({0}, {1})\">F{2}:{1}</a>",
-                    alignment.seq_a().id,
+                    file,
                     alignment.seq_a().index,
-                    alignment.seq_a().id + 1
+                    file + 1
                 ),
                 highlighted_match,
                 format!("{:.3}", alignment.normalised_score()),
@@ -222,6 +267,242 @@ pub async fn search_peptide<'a>(
     .to_string())
 }
 
+/// The metadata based query for PSMs, all given parts have to match. Ranges are inclusive.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct PSMQuery {
+    /// Precursor mass in Da with the tolerance in ppm, the experimental mass is used if
+    /// available, otherwise the theoretical mass of the peptidoform
+    pub mass: Option<(f64, f64)>,
+    /// Precursor m/z in Th with the tolerance in ppm, the experimental m/z is used if available,
+    /// otherwise the theoretical m/z of the peptidoform at the charge of the PSM
+    pub mz: Option<(f64, f64)>,
+    /// Modification in ProForma notation, either a named modification (eg `U:Oxidation`) or a
+    /// mass shift (eg `+15.995`)
+    pub modification: Option<String>,
+    /// The tolerance in Da for matching mass shift modifications
+    pub modification_tolerance: f64,
+    /// Case insensitive substring match on the protein accession or name
+    pub protein: Option<String>,
+    /// Retention time range in minutes
+    pub retention_time: (Option<f64>, Option<f64>),
+}
+
+/// A parsed and validated [`PSMQuery`]
+struct PSMQueryFilter {
+    query: PSMQuery,
+    modification: Option<SimpleModification>,
+    protein: Option<String>,
+    proton: f64,
+}
+
+/// Check if the value is within the given ppm tolerance of the target, the target has to be positive.
+fn within_ppm(value: f64, (target, tolerance): (f64, f64)) -> bool {
+    target > 0.0 && (value - target).abs() / target * 1e6 <= tolerance
+}
+
+impl PSMQueryFilter {
+    fn new(query: PSMQuery, ontologies: &Ontologies) -> Result<Self, String> {
+        for (name, value) in [("mass", query.mass), ("m/z", query.mz)] {
+            if let Some((target, _)) = value.filter(|(target, _)| *target <= 0.0) {
+                return Err(BoxedError::new(
+                    BasicKind::Error,
+                    "Invalid query",
+                    format!("The precursor {name} has to be positive"),
+                    Context::show(target.to_string()),
+                )
+                .to_html(false));
+            }
+        }
+        let modification = query
+            .modification
+            .as_deref()
+            .map(str::trim)
+            .filter(|m| !m.is_empty())
+            .map(|text| {
+                SimpleModificationInner::pro_forma(
+                    text,
+                    &mut Vec::new(),
+                    &mut Vec::new(),
+                    ontologies,
+                )
+                .map_err(|err| {
+                    BoxedError::new(
+                        BasicKind::Error,
+                        "Invalid modification",
+                        "Could not parse the modification",
+                        Context::full_line(0, text),
+                    )
+                    .add_underlying_errors(err)
+                    .to_html(false)
+                })
+                .and_then(|((m, _), _)| match m {
+                    ReturnModification::Defined(d) => Ok(d),
+                    _ => Err(BoxedError::new(
+                        BasicKind::Error,
+                        "Invalid modification",
+                        "Can not search for ambiguous modifications",
+                        Context::full_line(0, text),
+                    )
+                    .to_html(false)),
+                })
+            })
+            .transpose()?;
+        let protein = query
+            .protein
+            .as_deref()
+            .map(|p| p.trim().to_lowercase())
+            .filter(|p| !p.is_empty());
+        Ok(Self {
+            query,
+            modification,
+            protein,
+            proton: molecular_formula!(H 1 Electron -1)
+                .monoisotopic_mass()
+                .value,
+        })
+    }
+
+    fn matches(&self, psm: &PSM<Linked, MaybePeptidoform>) -> bool {
+        let theoretical_masses = || {
+            psm.peptidoform_ion_set()
+                .map(|p| {
+                    p.formulas()
+                        .iter()
+                        .map(|f| f.monoisotopic_mass().value)
+                        .collect_vec()
+                })
+                .unwrap_or_default()
+        };
+        self.query.mass.is_none_or(|mass| {
+            psm.experimental_mass().map_or_else(
+                || {
+                    theoretical_masses()
+                        .into_iter()
+                        .any(|m| within_ppm(m, mass))
+                },
+                |m| within_ppm(m.value, mass),
+            )
+        }) && self.query.mz.is_none_or(|mz| {
+            psm.experimental_mz().map_or_else(
+                || {
+                    psm.charge().filter(|c| c.value != 0).is_some_and(|charge| {
+                        let z = charge.value as f64;
+                        theoretical_masses()
+                            .into_iter()
+                            .any(|m| within_ppm((m + z * self.proton) / z.abs(), mz))
+                    })
+                },
+                |m| within_ppm(m.value, mz),
+            )
+        }) && {
+            let (min, max) = self.query.retention_time;
+            (min.is_none() && max.is_none())
+                || psm.retention_time().is_some_and(|rt| {
+                    let rt = rt.get::<mzcore::system::time::min>();
+                    min.is_none_or(|min| rt >= min) && max.is_none_or(|max| rt <= max)
+                })
+        } && self.protein.as_ref().is_none_or(|protein| {
+            psm.proteins().iter().any(|p| {
+                p.id().name().to_lowercase().contains(protein)
+                    || p.numerical_id()
+                        .is_some_and(|id| id.to_string().to_lowercase().contains(protein))
+            })
+        }) && self.modification.as_ref().is_none_or(|modification| {
+            psm.peptidoform_ion_set().is_some_and(|p| {
+                p.peptidoform_ions()
+                    .iter()
+                    .flat_map(|p| p.peptidoforms())
+                    .any(|p| {
+                        p.get_n_term()
+                            .iter()
+                            .chain(p.sequence().iter().flat_map(|s| s.modifications.iter()))
+                            .chain(p.get_c_term().iter())
+                            .filter_map(|m| m.simple())
+                            .any(|m| match &**modification {
+                                SimpleModificationInner::Mass(_, mass, _) => {
+                                    (m.formula().monoisotopic_mass().value
+                                        - mass.into_inner().value)
+                                        .abs()
+                                        <= self.query.modification_tolerance
+                                }
+                                _ => m == modification,
+                            })
+                    })
+            })
+        })
+    }
+}
+
+/// Find all PSMs matching the metadata query, sorted on score.
+fn search_peptide_metadata(
+    files: &[PSMFile],
+    filter: &PSMQueryFilter,
+    minimal_peptide_score: f64,
+    maximal_fdr: Option<f64>,
+    amount: usize,
+) -> String {
+    let data = files
+        .par_iter()
+        .flat_map(|file| {
+            file.peptides
+                .par_iter()
+                .enumerate()
+                .filter(move |(index, _)| file.passes_fdr(*index, maximal_fdr))
+                .map(move |(index, psm)| (file.id, index, psm))
+        })
+        .filter(|(_, _, psm)| psm.score.is_none_or(|score| score >= minimal_peptide_score))
+        .filter(|(_, _, psm)| filter.matches(psm))
+        .collect::<Vec<_>>()
+        .into_iter()
+        .sorted_by(|a, b| {
+            b.2.score
+                .unwrap_or(f64::MIN)
+                .total_cmp(&a.2.score.unwrap_or(f64::MIN))
+        })
+        .take(amount)
+        .map(|(file, index, psm)| {
+            vec![
+                format!(
+                    "<a onclick=\"load_peptide({file}, {index})\">F{}:{index}</a>",
+                    file + 1
+                ),
+                psm.peptidoform_ion_set()
+                    .map(|p| p.to_string())
+                    .unwrap_or_default(),
+                psm.experimental_mz()
+                    .map(|mz| format!("{:.3}", mz.value))
+                    .unwrap_or_default(),
+                psm.charge()
+                    .map(|c| c.value.to_string())
+                    .unwrap_or_default(),
+                psm.retention_time()
+                    .map(|rt| format!("{:.3}", rt.get::<mzcore::system::time::min>()))
+                    .unwrap_or_default(),
+                psm.proteins()
+                    .iter()
+                    .map(|p| p.id().name().to_string())
+                    .join(";"),
+                psm.score
+                    .map(|score| format!("{score:.3}"))
+                    .unwrap_or_default(),
+            ]
+        })
+        .collect::<Vec<_>>();
+    html_builder::HtmlElement::table(
+        Some(&[
+            "Index".to_string(),
+            "Sequence".to_string(),
+            "m/z (Th)".to_string(),
+            "Charge".to_string(),
+            "RT (min)".to_string(),
+            "Proteins".to_string(),
+            "Peptide Score".to_string(),
+        ]),
+        data,
+    )
+    .to_string()
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct PSMSettings {
     pub peptide: String,
@@ -231,7 +512,7 @@ pub struct PSMSettings {
 }
 
 impl PSMSettings {
-    fn from_peptide<C, A>(state: &State, peptide: &PSM<C, A>, warning: Option<String>) -> Self {
+    pub fn from_peptide<C, A>(state: &State, peptide: &PSM<C, A>, warning: Option<String>) -> Self {
         let mut str_peptide = String::new();
         if let Some(peptide) = peptide.peptidoform_ion_set() {
             peptide.display(&mut str_peptide, true).unwrap()
@@ -256,92 +537,126 @@ pub async fn load_identified_peptide(
     index: usize,
     state: ModifiableState<'_>,
 ) -> Result<PSMSettings, &'static str> {
-    let mut state = state.lock().await;
-    let res = state
+    let (filename, path, peptide) = state
+        .lock()
+        .await
         .psm_files()
         .iter()
         .find(|f| f.id == file)
         .and_then(|file| {
             file.peptides
                 .get(index)
-                .map(|p| (file.file_name(), p.clone()))
-        });
-    res
-        .map(|(filename, peptide)| {
-            let mut message = None;
-            let mut selected = false;
-            match peptide.scans() {
-                SpectrumIds::None => (),
-                SpectrumIds::FileNotKnown(scans) => {
-                    if let Some(index) = state.spectra.first().map(|r| r.id()) {
-                        for scan in scans {
-                            let _ = match scan {
-                                SpectrumId::Index(i) => state.spectra[index].select_index(i),
-                                SpectrumId::Number(i) => state.spectra[index].select_index(i-1),
-                                SpectrumId::Native(n) => state.spectra[index].select_native_id(n),
-                                SpectrumId::RetentionTime(rt) => state.spectra[index].select_retention_time(rt),
-                            };
-                            selected = true;
-                        }
-                    } else {
-                        message = Some("No raw files are loaded".to_string());
-                    }
-                },
-                SpectrumIds::FileKnown(scans) => {
-                    for (raw_file, scans) in scans {
-                        let mut name_matching = None;
-                        let mut stem_matching = None;
+                .map(|p| (file.file_name(), file.path.clone(), p.clone()))
+        })
+        .ok_or("The PSM could not be found")?;
+    let open_message = crate::raw_discovery::open_referenced_raw_files(
+        &state,
+        crate::raw_discovery::referenced_raw_files(&peptide.scans()),
+        std::path::Path::new(&path)
+            .parent()
+            .map(std::path::Path::to_path_buf),
+    )
+    .await;
+    let mut state = state.lock().await;
+    let (selected, message) = select_scans(&mut state, peptide.scans());
 
-                        let name = raw_file.file_name().unwrap_or_default().to_string_lossy().to_lowercase();
-                        let stem = raw_file.file_stem().unwrap_or_default().to_string_lossy().to_lowercase();
+    if let Some(annotated) = peptide.annotated_spectrum() {
+        state.spectra.push(crate::raw_file::RawFile::Single {
+            id: 0,
+            spectrum: annotated.into_owned().into(),
+            selected: !selected,
+            title: format!(
+                "F{file}:{index} - {filename} - {}",
+                peptide.peptidoform_ion_set().unwrap_or_default()
+            ),
+        })
+    }
 
-                            // Search for a rawfile with the same name (stem+ext) or same stem, prefer the one with the same name
-                        for (index, file) in state.spectra.iter_mut().enumerate() {
-                            let path = file.details().path;
-                            let path = std::path::Path::new(&path);
-                            if name_matching.is_none()
-                                && path
-                                    .file_name()
-                                    .map(|n| n.to_string_lossy().to_lowercase())
-                                    .unwrap_or_default()
-                                    == name
-                            {
-                                name_matching = Some(index);
-                            }
-                            if stem_matching.is_none()
-                                && path
-                                    .file_stem()
-                                    .map(|n| n.to_string_lossy().to_lowercase())
-                                    .unwrap_or_default()
-                                    == stem
-                            {
-                                stem_matching = Some(index);
-                            }
-                            file.clear_selected();
-                        }
+    Ok(PSMSettings::from_peptide(
+        &state,
+        &peptide,
+        open_message.or(message),
+    ))
+}
 
-                        if let Some(index) = name_matching.or(stem_matching) {
-                            for scan in scans {
-                                let _ = match scan {
-                                    SpectrumId::Index(i) => state.spectra[index].select_index(i),
-                                    SpectrumId::Number(i) => state.spectra[index].select_index(i-1),
-                                    SpectrumId::Native(n) => state.spectra[index].select_native_id(n),
-                                    SpectrumId::RetentionTime(rt) => state.spectra[index].select_retention_time(rt),
-                                };
-                                selected = true;
-                            }
-                        } else {
-                            message = Some(format!("Could not find a raw file with name '{stem}' either load the correct raw file or manually load the spectra '{}' from the correct raw file", scans.iter().join(";")))
+/// Select the given scans in the open raw files. Raw files that are not open can be searched for
+/// and opened beforehand with [`crate::raw_discovery::open_referenced_raw_files`]. Returns if any
+/// spectrum was selected and a message if not all scans could be selected.
+pub fn select_scans(state: &mut State, scans: SpectrumIds) -> (bool, Option<String>) {
+    let mut message = None;
+    let mut selected = false;
+    match scans {
+        SpectrumIds::None => (),
+        SpectrumIds::FileNotKnown(scans) => {
+            if let Some(index) = state.spectra.first().map(|r| r.id()) {
+                for scan in scans {
+                    let _ = match scan {
+                        SpectrumId::Index(i) => state.spectra[index].select_index(i),
+                        SpectrumId::Number(i) => state.spectra[index].select_index(i - 1),
+                        SpectrumId::Native(n) => state.spectra[index].select_native_id(n),
+                        SpectrumId::RetentionTime(rt) => {
+                            state.spectra[index].select_retention_time(rt)
                         }
-                    }
-                },
+                    };
+                    selected = true;
+                }
+            } else {
+                message = Some("No raw files are loaded".to_string());
             }
+        }
+        SpectrumIds::FileKnown(scans) => {
+            for (raw_file, scans) in scans {
+                let mut name_matching = None;
+                let mut stem_matching = None;
 
-            if let Some(annotated) = peptide.annotated_spectrum() {
-                state.spectra.push(crate::raw_file::RawFile::Single { id: 0, spectrum: annotated.into_owned().into(), selected: !selected, title: format!("F{file}:{index} - {filename} - {}", peptide.peptidoform_ion_set().unwrap_or_default()) })
-            }
+                let name = raw_file
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_lowercase();
+                let stem = raw_file_stem(raw_file);
 
-            PSMSettings::from_peptide(&state, &peptide, message)
-        })
-        .ok_or("The PSM could not be found")
+                // Search for a rawfile with the same name (stem+ext) or same stem, prefer the one with the same name
+                for (index, file) in state.spectra.iter_mut().enumerate() {
+                    let path = file.details().path;
+                    let path = std::path::Path::new(&path);
+                    if name_matching.is_none()
+                        && path
+                            .file_name()
+                            .map(|n| n.to_string_lossy().to_lowercase())
+                            .unwrap_or_default()
+                            == name
+                    {
+                        name_matching = Some(index);
+                    }
+                    if stem_matching.is_none() && raw_file_stem(path) == stem {
+                        stem_matching = Some(index);
+                    }
+                    file.clear_selected();
+                }
+
+                let index = name_matching.or(stem_matching);
+
+                if let Some(index) = index {
+                    for scan in scans {
+                        let _ = match scan {
+                            SpectrumId::Index(i) => state.spectra[index].select_index(i),
+                            SpectrumId::Number(i) => state.spectra[index].select_index(i - 1),
+                            SpectrumId::Native(n) => state.spectra[index].select_native_id(n),
+                            SpectrumId::RetentionTime(rt) => {
+                                state.spectra[index].select_retention_time(rt)
+                            }
+                        };
+                        selected = true;
+                    }
+                } else if message.is_none() {
+                    message = Some(format!(
+                        "Could not find a raw file with name '{stem}' either load the correct raw file, add its folder to the raw file search roots, or manually load the spectra '{}' from the correct raw file",
+                        scans.iter().join(";")
+                    ))
+                }
+            }
+        }
+    }
+    (selected, message)
 }
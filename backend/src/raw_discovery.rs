@@ -0,0 +1,220 @@
+use std::path::{Path, PathBuf};
+
+use itertools::Itertools;
+use mzident::{PSMMetaData, SpectrumIds};
+
+use crate::{ModifiableState, RAW_EXTENSIONS, file_extension, state::State};
+
+/// How many directories deep the search for raw files goes
+const SEARCH_DEPTH: usize = 3;
+
+/// Get the lowercase stem of a raw file, used to match raw files named in PSM files to raw files
+/// on disk. A final `.gz` extension and a known raw file extension are removed, any other dots
+/// are kept as part of the name.
+pub fn raw_file_stem(path: &Path) -> String {
+    let name = path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase();
+    let name = name.strip_suffix(".gz").unwrap_or(&name);
+    name.rsplit_once('.')
+        .filter(|(_, extension)| RAW_EXTENSIONS.contains(extension))
+        .map_or(name, |(stem, _)| stem)
+        .to_string()
+}
+
+/// Find a raw file on disk that matches the raw file name as given in a PSM file. First the path
+/// is tried as is, where relative paths are taken relative to the directory of the PSM file, then
+/// the directory of the PSM file and all search roots are searched for a file with the same stem
+/// and any supported raw file extension. This walks the file system so do not call this while
+/// holding the state.
+pub fn find_raw_file(
+    roots: &[PathBuf],
+    raw_file: &Path,
+    directory: Option<&Path>,
+) -> Option<PathBuf> {
+    let as_given = if raw_file.is_absolute() {
+        Some(raw_file.to_path_buf())
+    } else {
+        directory.map(|directory| directory.join(raw_file))
+    };
+    if let Some(path) = as_given.filter(|path| path.is_file()) {
+        return Some(path);
+    }
+    let stem = raw_file_stem(raw_file);
+    directory
+        .into_iter()
+        .chain(roots.iter().map(PathBuf::as_path))
+        .find_map(|directory| search_directory(directory, &stem, SEARCH_DEPTH))
+}
+
+/// Get all raw files that are named in the given scans.
+pub fn referenced_raw_files(scans: &SpectrumIds) -> Vec<PathBuf> {
+    match scans {
+        SpectrumIds::FileKnown(scans) => scans.iter().map(|(raw, _)| raw.clone()).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Search for the given raw files that are not open yet and open all that are found. The
+/// directories are searched without holding the state. Returns an error message if any of the
+/// found raw files could not be opened.
+pub async fn open_referenced_raw_files(
+    state: &ModifiableState<'_>,
+    raw_files: Vec<PathBuf>,
+    directory: Option<PathBuf>,
+) -> Option<String> {
+    let (open, roots) = {
+        let state = state.lock().await;
+        (
+            open_raw_file_stems(&state),
+            state.raw_file_search_roots.clone(),
+        )
+    };
+    let missing = raw_files
+        .into_iter()
+        .filter(|raw| !open.contains(&raw_file_stem(raw)))
+        .unique()
+        .collect_vec();
+    if missing.is_empty() {
+        return None;
+    }
+    let found = tauri::async_runtime::spawn_blocking(move || {
+        missing
+            .iter()
+            .filter_map(|raw| find_raw_file(&roots, raw, directory.as_deref()))
+            .collect_vec()
+    })
+    .await
+    .unwrap_or_default();
+    let mut state = state.lock().await;
+    let mut message = None;
+    for path in found {
+        // Another command could have opened the file in the meantime
+        if open_raw_file_stems(&state).contains(&raw_file_stem(&path)) {
+            continue;
+        }
+        if let Err(error) = crate::spectra::annotator_open_raw_file(&path, &mut state) {
+            message = Some(error);
+        }
+    }
+    message
+}
+
+fn open_raw_file_stems(state: &State) -> Vec<String> {
+    state
+        .spectra
+        .iter()
+        .map(|f| raw_file_stem(Path::new(&f.details().path)))
+        .collect_vec()
+}
+
+fn search_directory(directory: &Path, stem: &str, depth: usize) -> Option<PathBuf> {
+    let entries = std::fs::read_dir(directory)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .collect_vec();
+    entries
+        .iter()
+        .find(|path| {
+            path.is_file()
+                && raw_file_stem(path) == stem
+                && file_extension(path).is_some_and(|ext| RAW_EXTENSIONS.contains(&ext.as_str()))
+        })
+        .cloned()
+        .or_else(|| {
+            (depth > 0)
+                .then(|| {
+                    entries
+                        .iter()
+                        .filter(|path| path.is_dir())
+                        .find_map(|path| search_directory(path, stem, depth - 1))
+                })
+                .flatten()
+        })
+}
+
+/// Set the additional directories that are searched for raw files referenced in PSM files.
+#[tauri::command]
+pub async fn set_raw_file_search_roots(
+    roots: Vec<String>,
+    state: ModifiableState<'_>,
+) -> Result<(), String> {
+    let roots = roots
+        .iter()
+        .map(|r| r.trim())
+        .filter(|r| !r.is_empty())
+        .map(PathBuf::from)
+        .collect_vec();
+    if let Some(invalid) = roots.iter().find(|r| !r.is_dir()) {
+        return Err(format!(
+            "The search root '{}' is not a directory",
+            invalid.to_string_lossy()
+        ));
+    }
+    state.lock().await.raw_file_search_roots = roots;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_raw_file_search_roots(state: ModifiableState<'_>) -> Result<Vec<String>, ()> {
+    Ok(state
+        .lock()
+        .await
+        .raw_file_search_roots
+        .iter()
+        .map(|r| r.to_string_lossy().to_string())
+        .collect())
+}
+
+/// Get all raw files referenced in a PSM file that are not open and could not be found on disk.
+#[tauri::command]
+pub async fn get_missing_raw_files(
+    file: usize,
+    state: ModifiableState<'_>,
+) -> Result<Vec<String>, &'static str> {
+    let (raw_files, directory, roots) = {
+        let state = state.lock().await;
+        let files = state.psm_files();
+        let file = files
+            .iter()
+            .find(|f| f.id == file)
+            .ok_or("PSM file index not valid")?;
+        let open = open_raw_file_stems(&state);
+        (
+            file.peptides
+                .iter()
+                .flat_map(|psm| referenced_raw_files(&psm.scans()))
+                .unique()
+                .filter(|raw| !open.contains(&raw_file_stem(raw)))
+                .collect_vec(),
+            Path::new(&file.path).parent().map(Path::to_path_buf),
+            state.raw_file_search_roots.clone(),
+        )
+    };
+    tauri::async_runtime::spawn_blocking(move || {
+        raw_files
+            .into_iter()
+            .filter(|raw| find_raw_file(&roots, raw, directory.as_deref()).is_none())
+            .map(|raw| raw.to_string_lossy().to_string())
+            .collect()
+    })
+    .await
+    .map_err(|_| "Could not search for the raw files")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::raw_file_stem;
+
+    #[test]
+    fn stems() {
+        assert_eq!(raw_file_stem(Path::new("x.mzML.gz")), "x");
+        assert_eq!(raw_file_stem(Path::new("x.raw")), "x");
+        assert_eq!(raw_file_stem(Path::new("a.b")), "a.b");
+        assert_eq!(raw_file_stem(Path::new("folder/Run.v2.mzML")), "run.v2");
+    }
+}
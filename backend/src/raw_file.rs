@@ -8,12 +8,15 @@ use mzdata::{
 };
 use serde::{Deserialize, Serialize};
 
+use crate::chromatogram::Chromatogram;
+
 pub enum RawFile {
     File {
         id: usize,
         rawfile: MZReaderType<File>,
         selected_spectra: Vec<usize>,
         path: String,
+        chromatogram: Option<Chromatogram>,
     },
     Single {
         id: usize,
@@ -48,6 +51,41 @@ impl RawFile {
         }
     }
 
+    pub fn is_selected(&self) -> bool {
+        match self {
+            Self::File {
+                selected_spectra, ..
+            } => !selected_spectra.is_empty(),
+            Self::Single { selected, .. } => *selected,
+        }
+    }
+
+    /// The indices of all selected spectra
+    pub fn selected(&self) -> Vec<usize> {
+        match self {
+            Self::File {
+                selected_spectra, ..
+            } => selected_spectra.clone(),
+            Self::Single { selected, .. } => {
+                if *selected {
+                    vec![0]
+                } else {
+                    Vec::new()
+                }
+            }
+        }
+    }
+
+    /// Restore a selection as given by [`Self::selected`]
+    pub fn set_selected(&mut self, selection: Vec<usize>) {
+        match self {
+            Self::File {
+                selected_spectra, ..
+            } => *selected_spectra = selection,
+            Self::Single { selected, .. } => *selected = selection.contains(&0),
+        }
+    }
+
     pub fn unselect_index(&mut self, index: usize) {
         match self {
             Self::File {
@@ -95,6 +133,14 @@ impl RawFile {
         }
     }
 
+    /// Get the spectrum index for a native ID using the offset index, without reading the spectrum.
+    pub fn index_of_native_id(&self, native_id: &str) -> Option<usize> {
+        match self {
+            Self::File { rawfile, .. } => rawfile.get_index().index_of(native_id),
+            Self::Single { spectrum, .. } => (spectrum.description.id == native_id).then_some(0),
+        }
+    }
+
     pub fn select_native_id(&mut self, native_id: String) -> Result<(), &'static str> {
         match self {
             Self::File {
@@ -127,20 +173,34 @@ impl RawFile {
         &mut self,
         rt: RangeInclusive<OrderedTime>,
     ) -> Result<(), &'static str> {
+        self.select_retention_time_range(rt.start().value, rt.end().value)
+    }
+
+    /// Select the MS2 spectrum closest to the given retention time, using the chromatogram to find it.
+    pub fn select_closest_retention_time(&mut self, rt: f64) -> Result<(), &'static str> {
+        let closest = self
+            .chromatogram()?
+            .closest_ms2(rt)
+            .map(|point| point.rt)
+            .ok_or("There are no MS2 spectra in this file")?;
+        self.select_retention_time_range(closest, closest)
+    }
+
+    fn select_retention_time_range(&mut self, start: f64, end: f64) -> Result<(), &'static str> {
         match self {
             Self::File {
                 rawfile,
                 selected_spectra,
                 ..
             } => rawfile
-                .start_from_time(rt.start().value)
+                .start_from_time(start)
                 .map(|iter| {
                     iter.take_while(|s| {
                         s.description
                             .acquisition
                             .scans
                             .first()
-                            .is_some_and(|s| s.start_time <= rt.end().value)
+                            .is_some_and(|s| s.start_time <= end)
                     })
                     .for_each(|s| {
                         if s.ms_level() == 2 && !selected_spectra.contains(&s.index()) {
@@ -162,6 +222,7 @@ impl RawFile {
             rawfile: file,
             selected_spectra: Vec::new(),
             path,
+            chromatogram: None,
         }
     }
 
@@ -200,6 +261,18 @@ impl RawFile {
         }
     }
 
+    /// Get the TIC and base peak chromatograms, these are calculated on first use and cached afterwards.
+    pub fn chromatogram(&mut self) -> Result<&Chromatogram, &'static str> {
+        match self {
+            Self::File {
+                rawfile,
+                chromatogram,
+                ..
+            } => Ok(chromatogram.get_or_insert_with(|| Chromatogram::new(rawfile.iter()))),
+            Self::Single { .. } => Err("Cannot create a chromatogram for a single spectrum"),
+        }
+    }
+
     pub fn get_selected_spectra(&mut self) -> Box<dyn Iterator<Item = MultiLayerSpectrum> + '_> {
         match self {
             Self::File {
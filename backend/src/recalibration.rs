@@ -0,0 +1,389 @@
+use std::{collections::HashMap, fmt::Write};
+
+use context_error::{BasicKind, BoxedError, CreateError, FullErrorContent};
+use itertools::Itertools;
+use mzannotate::prelude::*;
+use mzcore::{
+    prelude::*,
+    system::{e, isize::Charge},
+};
+use mzdata::{prelude::SpectrumLike, spectrum::MultiLayerSpectrum};
+use mzident::PSMMetaData;
+use serde::{Deserialize, Serialize};
+
+use crate::{ModifiableState, html_builder, psms::select_scans, spectra::NoiseFilter};
+
+/// The minimal number of annotated peaks needed to estimate a recalibration
+const MINIMAL_POINTS: usize = 25;
+/// The maximal number of points shown in the recalibration graph
+const MAXIMAL_GRAPH_POINTS: usize = 2000;
+
+/// A linear model of the systematic fragment m/z error of a single raw file, the error in ppm is
+/// `a + b * mz + c * rt` with the retention time in minutes.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Recalibration {
+    pub coefficients: [f64; 3],
+    /// The number of annotated peaks used to fit the model
+    pub points: usize,
+    /// The root mean square error in ppm before and after recalibration
+    pub rms: (f64, f64),
+}
+
+impl Recalibration {
+    /// Fit the model on `(mz, rt, ppm)` points, if the retention time or m/z do not vary the
+    /// respective coefficients are zero.
+    fn fit(points: &[(f64, f64, f64)]) -> Option<Self> {
+        if points.len() < MINIMAL_POINTS {
+            return None;
+        }
+        let coefficients = solve::<3>(points, |(mz, rt, _)| [1.0, *mz, *rt])
+            .or_else(|| solve::<2>(points, |(mz, _, _)| [1.0, *mz]).map(|[a, b]| [a, b, 0.0]))
+            .or_else(|| solve::<1>(points, |_| [1.0]).map(|[a]| [a, 0.0, 0.0]))?;
+        let mut recalibration = Self {
+            coefficients,
+            points: points.len(),
+            rms: (0.0, 0.0),
+        };
+        let len = points.len() as f64;
+        recalibration.rms = (
+            (points.iter().map(|p| p.2.powi(2)).sum::<f64>() / len).sqrt(),
+            (points
+                .iter()
+                .map(|p| (p.2 - recalibration.error(p.0, p.1)).powi(2))
+                .sum::<f64>()
+                / len)
+                .sqrt(),
+        );
+        Some(recalibration)
+    }
+
+    /// The expected error in ppm at this m/z and retention time
+    pub fn error(&self, mz: f64, rt: f64) -> f64 {
+        self.coefficients[0] + self.coefficients[1] * mz + self.coefficients[2] * rt
+    }
+
+    /// Correct all peaks of a spectrum, both the centroided peaks and the raw m/z array (if
+    /// present) so that profile spectra can be peak picked and averaged after recalibration.
+    pub fn apply(&self, spectrum: &mut MultiLayerSpectrum) {
+        let rt = spectrum.start_time();
+        if let Some(peaks) = spectrum.peaks.as_mut() {
+            for peak in peaks.peaks.iter_mut() {
+                peak.mz /= 1.0 + self.error(peak.mz, rt) * 1e-6;
+            }
+        }
+        if let Some(mzs) = spectrum
+            .arrays
+            .as_mut()
+            .and_then(|arrays| arrays.mzs_mut().ok())
+        {
+            for mz in mzs.iter_mut() {
+                *mz /= 1.0 + self.error(*mz, rt) * 1e-6;
+            }
+        }
+    }
+}
+
+/// Least squares fit of a linear model with `N` features using the normal equations
+fn solve<const N: usize>(
+    points: &[(f64, f64, f64)],
+    features: impl Fn(&(f64, f64, f64)) -> [f64; N],
+) -> Option<[f64; N]> {
+    let mut matrix = [[0.0; N]; N];
+    let mut vector = [0.0; N];
+    for point in points {
+        let x = features(point);
+        for i in 0..N {
+            vector[i] += x[i] * point.2;
+            for j in 0..N {
+                matrix[i][j] += x[i] * x[j];
+            }
+        }
+    }
+    // Gaussian elimination with partial pivoting
+    for column in 0..N {
+        let pivot = (column..N).max_by(|a, b| {
+            matrix[*a][column]
+                .abs()
+                .total_cmp(&matrix[*b][column].abs())
+        })?;
+        if matrix[pivot][column].abs() < 1e-9 * matrix[column][column].abs().max(1.0) {
+            return None;
+        }
+        matrix.swap(column, pivot);
+        vector.swap(column, pivot);
+        for row in column + 1..N {
+            let factor = matrix[row][column] / matrix[column][column];
+            for k in column..N {
+                matrix[row][k] -= factor * matrix[column][k];
+            }
+            vector[row] -= factor * vector[column];
+        }
+    }
+    let mut result = [0.0; N];
+    for row in (0..N).rev() {
+        let sum: f64 = (row + 1..N).map(|k| matrix[row][k] * result[k]).sum();
+        result[row] = (vector[row] - sum) / matrix[row][row];
+    }
+    result.iter().all(|v| v.is_finite()).then_some(result)
+}
+
+/// Render the errors against m/z with the fitted error at the median retention time
+fn render_fit(output: &mut String, points: &[(f64, f64, f64)], recalibration: &Recalibration) {
+    let (min_mz, max_mz) = points
+        .iter()
+        .map(|p| p.0)
+        .minmax()
+        .into_option()
+        .unwrap_or((0.0, 1.0));
+    let max_ppm = points.iter().map(|p| p.2.abs()).fold(1.0_f64, f64::max);
+    let rt = points
+        .iter()
+        .map(|p| p.1)
+        .sorted_by(f64::total_cmp)
+        .nth(points.len() / 2)
+        .unwrap_or_default();
+    let x = |mz: f64| (mz - min_mz) / (max_mz - min_mz).max(f64::EPSILON) * 100.0;
+    let y = |ppm: f64| (max_ppm - ppm) / (2.0 * max_ppm) * 100.0;
+    write!(
+        output,
+        "<div class='recalibration-graph'><span class='max'>{max_ppm:.2} ppm</span><svg viewBox='0 0 100 100' preserveAspectRatio='none'><line class='zero' x1='0' y1='50' x2='100' y2='50'></line>"
+    )
+    .unwrap();
+    let step = points.len().div_ceil(MAXIMAL_GRAPH_POINTS);
+    for point in points.iter().step_by(step) {
+        write!(
+            output,
+            "<circle cx='{}' cy='{}' r='0.4'></circle>",
+            x(point.0),
+            y(point.2)
+        )
+        .unwrap();
+    }
+    write!(
+        output,
+        "<line class='fit' x1='0' y1='{}' x2='100' y2='{}'></line></svg><span class='min'>-{max_ppm:.2} ppm</span><div class='x-axis'><span>{min_mz:.2}</span><span>m/z</span><span>{max_mz:.2}</span></div></div>",
+        y(recalibration.error(min_mz, rt)),
+        y(recalibration.error(max_mz, rt)),
+    )
+    .unwrap();
+}
+
+/// Estimate the systematic fragment m/z error for every raw file with spectra for the PSMs in
+/// the given PSM file. All PSMs passing the score and FDR thresholds are annotated and the errors
+/// of all annotated peaks are used to fit the model. The recalibration is applied to all spectra
+/// from these raw files from now on. Returns the fitted models with graphs.
+#[tauri::command]
+pub async fn estimate_recalibration(
+    file: usize,
+    minimal_peptide_score: f64,
+    maximal_fdr: Option<f64>,
+    tolerance: (f64, &str),
+    noise_filter: (NoiseFilter, f32),
+    model: usize,
+    mass_mode: &str,
+    mz_range: (Option<f64>, Option<f64>),
+    isotopes: bool,
+    isotope_filter: f64,
+    state: ModifiableState<'_>,
+) -> Result<String, String> {
+    let (peptides, psm_path) = {
+        let state = state.lock().await;
+        let files = state.psm_files();
+        let file = files
+            .iter()
+            .find(|f| f.id == file)
+            .ok_or("PSM file index not valid")?;
+        (
+            file.peptides
+                .iter()
+                .enumerate()
+                .filter(|(index, psm)| {
+                    psm.score.is_none_or(|s| s >= minimal_peptide_score)
+                        && file.passes_fdr(*index, maximal_fdr)
+                })
+                .map(|(_, psm)| psm.clone())
+                .collect_vec(),
+            file.path.clone(),
+        )
+    };
+    // Failing to open a raw file only means these PSMs cannot be used
+    let _ = crate::raw_discovery::open_referenced_raw_files(
+        &state,
+        peptides
+            .iter()
+            .flat_map(|psm| crate::raw_discovery::referenced_raw_files(&psm.scans()))
+            .collect(),
+        std::path::Path::new(&psm_path)
+            .parent()
+            .map(std::path::Path::to_path_buf),
+    )
+    .await;
+    let (model, parameters, mass_mode, spectra, raw_files) = {
+        let mut state = state.lock().await;
+        let model = crate::model::get_models(&state)
+            .1
+            .get(model)
+            .map(|m| m.2.clone())
+            .ok_or_else(|| {
+                BoxedError::small(BasicKind::Error, "Invalid model", "Model does not exist")
+                    .to_html(false)
+            })?;
+        let parameters = crate::model::parameters(tolerance, mz_range, isotopes, isotope_filter)
+            .map_err(|err| err.to_html(false))?;
+        let mass_mode = crate::model::mass_mode(mass_mode).map_err(|err| err.to_html(false))?;
+
+        // Estimate on the uncorrected spectra
+        let recalibrations = std::mem::take(&mut state.recalibrations);
+        let selection = state
+            .spectra
+            .iter()
+            .map(|file| (file.id(), file.selected()))
+            .collect_vec();
+        let mut spectra = Vec::new();
+        for psm in peptides {
+            if psm.peptidoform_ion_set().is_none() {
+                continue;
+            }
+            for file in &mut state.spectra {
+                file.clear_selected();
+            }
+            let (selected, _) = select_scans(&mut state, psm.scans());
+            let Some(raw_file) = state
+                .spectra
+                .iter()
+                .find(|f| f.is_selected())
+                .map(|f| f.id())
+            else {
+                continue;
+            };
+            if let Some((_, spectrum)) = selected
+                .then(|| crate::spectra::create_selected_spectrum(&mut state, noise_filter).ok())
+                .flatten()
+            {
+                spectra.push((raw_file, psm, spectrum));
+            }
+        }
+        for file in &mut state.spectra {
+            let id = file.id();
+            file.set_selected(
+                selection
+                    .iter()
+                    .find(|(i, _)| *i == id)
+                    .map(|(_, s)| s.clone())
+                    .unwrap_or_default(),
+            );
+        }
+        state.recalibrations = recalibrations;
+        let raw_files = state
+            .spectra
+            .iter()
+            .map(|file| (file.id(), file.details().path.clone()))
+            .collect_vec();
+        (model, parameters, mass_mode, spectra, raw_files)
+    };
+
+    let points = tauri::async_runtime::spawn_blocking(move || {
+        let mut points: HashMap<usize, Vec<(f64, f64, f64)>> = HashMap::new();
+        for (raw_file, psm, spectrum) in spectra {
+            let Some(peptide) = psm.peptidoform_ion_set().map(|p| p.into_owned()) else {
+                continue;
+            };
+            let charge = psm
+                .charge()
+                .map(|c| c.value)
+                .or_else(|| {
+                    spectrum
+                        .precursor()
+                        .and_then(|p| p.ions.first().and_then(|i| i.charge.map(|c| c as isize)))
+                })
+                .unwrap_or(1);
+            let rt = spectrum.start_time();
+            let fragments =
+                peptide.generate_theoretical_fragments(Charge::new::<e>(charge), &model);
+            let annotated = spectrum.annotate(peptide, &fragments, &parameters, mass_mode);
+            let file_points = points.entry(raw_file).or_default();
+            for peak in &annotated.peaks {
+                if let Some(ppm) = peak
+                    .annotations
+                    .iter()
+                    .filter_map(|f| f.mz(mass_mode))
+                    .map(|mz| (peak.mz.value - mz.value) / mz.value * 1e6)
+                    .min_by(|a, b| a.abs().total_cmp(&b.abs()))
+                {
+                    file_points.push((peak.mz.value, rt, ppm));
+                }
+            }
+        }
+        points
+    })
+    .await
+    .map_err(|err| err.to_string())?;
+
+    let mut output = String::new();
+    let mut fitted = Vec::new();
+    for (raw_file, path) in &raw_files {
+        let Some(points) = points.get(raw_file) else {
+            continue;
+        };
+        write!(
+            output,
+            "<div class='recalibration'><h3>{}</h3>",
+            html_builder::escape(path)
+        )
+        .unwrap();
+        if let Some(recalibration) = Recalibration::fit(points) {
+            write!(
+                output,
+                "<p>Error (ppm) = {:.3} + {:.3e} × m/z + {:.3e} × RT (min), fitted on {} annotated peaks. RMS error before {:.3} ppm, after {:.3} ppm.</p>",
+                recalibration.coefficients[0],
+                recalibration.coefficients[1],
+                recalibration.coefficients[2],
+                recalibration.points,
+                recalibration.rms.0,
+                recalibration.rms.1,
+            )
+            .unwrap();
+            render_fit(&mut output, points, &recalibration);
+            write!(
+                output,
+                "<button class='clear-recalibration secondary' type='button' data-file='{raw_file}'>Clear recalibration</button>",
+            )
+            .unwrap();
+            fitted.push((*raw_file, recalibration));
+        } else {
+            write!(
+                output,
+                "<p>Not enough annotated peaks to estimate the error, found {} but at least {MINIMAL_POINTS} are needed.</p>",
+                points.len()
+            )
+            .unwrap();
+        }
+        write!(output, "</div>").unwrap();
+    }
+    state.lock().await.recalibrations.extend(fitted);
+    if output.is_empty() {
+        Err(BoxedError::small(
+            BasicKind::Error,
+            "No spectra found",
+            "None of the PSMs passing the thresholds have a spectrum in the open raw files",
+        )
+        .to_html(false))
+    } else {
+        Ok(output)
+    }
+}
+
+/// Remove the recalibration for the given raw file, or for all raw files if no file is given.
+#[tauri::command]
+pub async fn clear_recalibration(
+    file: Option<usize>,
+    state: ModifiableState<'_>,
+) -> Result<(), ()> {
+    let mut state = state.lock().await;
+    if let Some(file) = file {
+        state.recalibrations.remove(&file);
+    } else {
+        state.recalibrations.clear();
+    }
+    Ok(())
+}
@@ -27,7 +27,11 @@ use crate::{
 };
 use ordered_float::OrderedFloat;
 
-use super::{classes::get_classes, label::get_label};
+use super::{
+    classes::get_classes,
+    discrimination::{discriminating_fragments, render_discriminating_fragments},
+    label::get_label,
+};
 
 pub fn annotated_spectrum(
     spectrum: &AnnotatedSpectrum,
@@ -742,11 +746,15 @@ fn general_stats(
     let mut positions_details_row = String::new();
     let mut fdr_peaks_row = String::new();
     let mut fdr_intensity_row = String::new();
+    let mut discriminating_row = String::new();
+    let mut discriminating_details_row = String::new();
 
     let (combined_scores, separate_peptide_scores) =
         spectrum.scores(fragments, parameters, mass_mode);
     let fdr = (spectrum.peaks.len() != 0).then(|| spectrum.fdr(fragments, parameters, mass_mode));
     let peptidoform_ion_set = spectrum.peptidoform_ion_set().unwrap_or_default();
+    let discriminating = (multiple_peptidoform_ions || multiple_peptidoforms)
+        .then(|| discriminating_fragments(spectrum, fragments, parameters, mass_mode));
 
     for (peptidoform_ion_index, peptidoform_ion_scores) in
         separate_peptide_scores.iter().enumerate()
@@ -768,6 +776,26 @@ fn general_stats(
                     }
                 });
             write!(mass_row, "<td>{precursor}</td>").unwrap();
+            if let Some(discriminating) = discriminating
+                .as_ref()
+                .and_then(|d| d.get(peptidoform_ion_index)?.get(peptidoform_index))
+            {
+                let observed = discriminating.iter().filter(|f| f.observed).count();
+                write!(
+                    discriminating_row,
+                    "<td>{:.2}% ({observed}/{})</td>",
+                    if discriminating.is_empty() {
+                        0.0
+                    } else {
+                        observed as f64 / discriminating.len() as f64 * 100.0
+                    },
+                    discriminating.len()
+                )
+                .unwrap();
+                write!(discriminating_details_row, "<td>").unwrap();
+                render_discriminating_fragments(&mut discriminating_details_row, discriminating);
+                write!(discriminating_details_row, "</td>").unwrap();
+            }
             match peptidoform_score.score {
                 Score::Position {
                     fragments,
@@ -951,6 +979,8 @@ fn general_stats(
         write!(output, "<td>Combined</td></tr>").unwrap();
         // Add a combined stats column
         write!(mass_row, "<td>-</td>").unwrap();
+        write!(discriminating_row, "<td>-</td>").unwrap();
+        write!(discriminating_details_row, "<td>-</td>").unwrap();
         match combined_scores.score {
             Score::Position {
                 fragments,
@@ -1106,10 +1136,18 @@ fn general_stats(
         <tr><td>Sequence positions covered</td>{positions_row}</tr>
         <tr class='fragments-detail'><td>Positions detailed</td>{positions_details_row}</tr>
         <tr><td title='FDR estimation by permutation; Tests how many matches are found when the spectrum is shifted from -25 to +25 Da plus π (to have non integer offsets). The percentage is the number found for the actual matches divided by the average found number for the shifted spectra. The number between brackets denotes the number of standard deviations the actual matches is from the shifted matches.'>Peaks false match chance</td>{fdr_peaks_row}</tr>
-        <tr><td title='FDR estimation by permutation; Same procedure as Peaks FDR, but this time counts the fraction of intensity annotated instead of the number of peaks. '>Intensity false match chance</td>{fdr_intensity_row}</tr>
-    </table>"
+        <tr><td title='FDR estimation by permutation; Same procedure as Peaks FDR, but this time counts the fraction of intensity annotated instead of the number of peaks. '>Intensity false match chance</td>{fdr_intensity_row}</tr>"
     )
     .unwrap();
+    if discriminating.is_some() {
+        write!(
+            output,
+            "<tr><td title='The theoretical fragments that can only be explained by this peptidoform, as no fragment of any other peptidoform is within the tolerance. The percentage indicates how many of these were observed.'>Discriminating fragments found</td>{discriminating_row}</tr>
+        <tr class='fragments-detail'><td>Discriminating fragments</td>{discriminating_details_row}</tr>"
+        )
+        .unwrap();
+    }
+    write!(output, "</table>").unwrap();
 }
 
 fn density_estimation<const STEPS: usize>(mut data: Vec<f64>) -> ([f64; STEPS], f64, f64) {
@@ -1130,6 +1168,21 @@ fn density_estimation<const STEPS: usize>(mut data: Vec<f64>) -> ([f64; STEPS],
         (data.iter().map(|p| (mean - p).powi(2)).sum::<f64>() / len).sqrt();
     let iqr: f64 = last_half_sum / half - first_half_sum / half;
     let h = 0.25 * standard_deviation.min(iqr / 1.34) * len.powf(-0.2);
+    // If (most of) the values are identical the bandwidth is zero, use a minimal bandwidth
+    // instead so that the density is a narrow spike instead of NaN
+    let h = if h.is_finite() && h > 0.0 {
+        h
+    } else {
+        ((max_value - min_value) / STEPS as f64)
+            .max(mean.abs() * 1e-3)
+            .max(1e-3)
+    };
+    // Show the spike if all values are identical
+    let (min_value, max_value) = if max_value > min_value {
+        (min_value, max_value)
+    } else {
+        (min_value - 3.0 * h, max_value + 3.0 * h)
+    };
 
     let gaussian_kernel =
         |x: f64| 1.0 / (2.0 * std::f64::consts::PI).sqrt() * (-1.0 / 2.0 * x.powi(2)).exp();
@@ -1155,6 +1208,27 @@ pub async fn density_graph(data: Vec<f64>) -> Result<String, ()> {
     Ok(line_graph_y(&densities, min, max))
 }
 
+/// Render a horizontal density plot of the data, with the minimal and maximal value on the x axis
+pub fn density_plot(data: Vec<f64>) -> String {
+    const STEPS: usize = 128;
+    let (densities, min, max) = density_estimation::<STEPS>(data);
+    let max_y = densities.iter().copied().reduce(f64::max).unwrap_or(1.0);
+    let path = densities
+        .iter()
+        .enumerate()
+        .map(|(i, density)| {
+            format!(
+                "{} {}",
+                i as f64 / (STEPS - 1) as f64 * 100.0,
+                max_y - density
+            )
+        })
+        .join(" L ");
+    format!(
+        "<div class='density'><svg viewBox='0 0 100 {max_y}' preserveAspectRatio='none'><path class='line' d='M {path}'></path><path class='volume' d='M 0 {max_y} L {path} L 100 {max_y} Z'></path></svg><div class='x-axis'><span>{min:.3}</span><span>{max:.3}</span></div></div>",
+    )
+}
+
 fn line_graph_y(points: &[f64], min_y: f64, max_y: f64) -> String {
     let max_x = points.iter().copied().reduce(f64::max).unwrap_or(f64::MAX);
     let mut path = String::new();
@@ -1518,3 +1592,18 @@ pub fn render_glycan_fragment(
             }
         })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::density_estimation;
+
+    #[test]
+    fn density_identical_values() {
+        for data in [vec![5.0; 10], vec![0.0], vec![1.0, 1.0, 1.0, 1.0, 2.0]] {
+            let (densities, min, max) = density_estimation::<16>(data);
+            assert!(densities.iter().all(|d| d.is_finite()));
+            assert!(densities.iter().any(|d| *d > 0.0));
+            assert!(min < max);
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use std::fmt::Write;
+
+use mzannotate::{prelude::*, spectrum::AnnotatedSpectrum};
+use mzcore::{prelude::*, quantities::WithinTolerance, system::MassOverCharge};
+use mzident::PSMMetaData;
+
+/// A theoretical fragment that can only be explained by a single peptidoform in the set
+pub struct DiscriminatingFragment<'a> {
+    pub fragment: &'a Fragment,
+    pub mz: MassOverCharge,
+    pub observed: bool,
+}
+
+/// Get the theoretical fragments that are unique to each peptidoform, indexed as
+/// `[peptidoform ion][peptidoform]`. A fragment is unique if no fragment of any other
+/// peptidoform in the set falls within the tolerance. The fragments are sorted on m/z so only the
+/// fragments within the tolerance window have to be compared, and the peaks are found with a
+/// binary search.
+pub fn discriminating_fragments<'a>(
+    spectrum: &AnnotatedSpectrum,
+    fragments: &'a [Fragment],
+    parameters: &MatchingParameters,
+    mass_mode: MassMode,
+) -> Vec<Vec<Vec<DiscriminatingFragment<'a>>>> {
+    let peptidoform_ion_set = spectrum.peptidoform_ion_set().unwrap_or_default();
+    let mut output: Vec<Vec<Vec<DiscriminatingFragment<'a>>>> = peptidoform_ion_set
+        .peptidoform_ions()
+        .iter()
+        .map(|p| p.peptidoforms().iter().map(|_| Vec::new()).collect())
+        .collect();
+    let mut located = fragments
+        .iter()
+        .filter_map(|f| {
+            Some((
+                (f.peptidoform_ion_index?, f.peptidoform_index?),
+                f.mz(mass_mode)?,
+                f,
+            ))
+        })
+        .collect::<Vec<_>>();
+    located.sort_unstable_by(|a, b| a.1.value.total_cmp(&b.1.value));
+    let within = |other: &(_, MassOverCharge, _), mz| parameters.tolerance.within(&other.1, mz);
+    for (position, (index, mz, fragment)) in located.iter().enumerate() {
+        if located[..position]
+            .iter()
+            .rev()
+            .take_while(|other| within(other, mz))
+            .chain(
+                located[position + 1..]
+                    .iter()
+                    .take_while(|other| within(other, mz)),
+            )
+            .any(|(other, _, _)| other != index)
+        {
+            continue;
+        }
+        let start = spectrum
+            .peaks
+            .partition_point(|p| p.mz.value < mz.value && !parameters.tolerance.within(&p.mz, mz));
+        let observed = spectrum.peaks[start..]
+            .iter()
+            .take_while(|p| p.mz.value <= mz.value || parameters.tolerance.within(&p.mz, mz))
+            .any(|p| p.annotations.iter().any(|a| a == *fragment));
+        if let Some(list) = output.get_mut(index.0).and_then(|p| p.get_mut(index.1)) {
+            list.push(DiscriminatingFragment {
+                fragment,
+                mz: *mz,
+                observed,
+            });
+        }
+    }
+    output
+}
+
+/// Render a list of discriminating fragments, with the observed fragments highlighted
+pub fn render_discriminating_fragments(
+    output: &mut String,
+    fragments: &[DiscriminatingFragment<'_>],
+) {
+    write!(output, "<span class='discriminating-fragments'>").unwrap();
+    for fragment in fragments {
+        let label = fragment.fragment.ion.label().1.to_string();
+        write!(
+            output,
+            "<span class='ion{}' title='m/z {:.4}{}'>{}</span>",
+            if fragment.observed { " observed" } else { "" },
+            fragment.mz.value,
+            if fragment.observed {
+                ", observed"
+            } else {
+                ", not observed"
+            },
+            fragment
+                .fragment
+                .ion
+                .position()
+                .map_or(label.clone(), |p| format!("{label}{}", p.series_number)),
+        )
+        .unwrap();
+    }
+    write!(output, "</span>").unwrap();
+}
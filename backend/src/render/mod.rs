@@ -1,8 +1,10 @@
 mod all;
 mod classes;
+mod discrimination;
 mod label;
 mod peptide;
 mod table;
 pub use all::*;
+pub use label::display_sequence_index;
 pub use peptide::*;
 pub use table::spectrum_table;
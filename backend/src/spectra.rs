@@ -1,5 +1,5 @@
 use std::{
-    io::{BufRead, ErrorKind},
+    io::{BufRead, BufWriter, ErrorKind, Write},
     ops::RangeInclusive,
     path::Path,
     str::FromStr,
@@ -211,7 +211,7 @@ pub async fn load_raw<'a>(
 
 pub fn annotator_open_raw_file(
     path: &std::path::Path,
-    state: &mut tokio::sync::MutexGuard<'_, crate::State>,
+    state: &mut crate::State,
 ) -> Result<RawFileDetails, String> {
     if path
         .extension()
@@ -798,10 +798,24 @@ pub fn create_selected_spectrum(
     filter: (NoiseFilter, f32),
 ) -> Result<(Vec<CentroidPeak>, MultiLayerSpectrum), BoxedError<'_, BasicKind>> {
     let mut spectra = Vec::new();
+    // Every spectrum is recalibrated with the model of its own raw file before merging
+    let mut recalibrations = Vec::new();
     for file in state.spectra.iter_mut() {
-        spectra.extend(file.get_selected_spectra());
+        let recalibration = state.recalibrations.get(&file.id()).copied();
+        let before = spectra.len();
+        spectra.extend(file.get_selected_spectra().map(|mut spectrum| {
+            if let Some(recalibration) = recalibration {
+                recalibration.apply(&mut spectrum);
+            }
+            spectrum
+        }));
+        if let Some(recalibration) = recalibration
+            && spectra.len() > before
+        {
+            recalibrations.push(recalibration);
+        }
     }
-    let spectrum = if spectra.is_empty() {
+    let mut spectrum = if spectra.is_empty() {
         return Err(BoxedError::new(
             BasicKind::Error,
             "No selected spectra",
@@ -866,6 +880,18 @@ pub fn create_selected_spectrum(
         )
     };
 
+    for recalibration in recalibrations {
+        spectrum.description.params.push(Param::new_key_value(
+            "recalibration",
+            Value::String(format!(
+                "{} + {} * mz + {} * rt ppm",
+                recalibration.coefficients[0],
+                recalibration.coefficients[1],
+                recalibration.coefficients[2]
+            )),
+        ));
+    }
+
     Ok(filter.0.filter(filter.1, spectrum))
 }
 
@@ -907,6 +933,10 @@ pub fn save_spectrum(
             mzdata::params::Value::String(sequence.to_string()),
         ));
     }
+    let model_name = crate::model::get_models(&state)
+        .1
+        .get(model)
+        .map(|(_, name, _)| name.to_string());
     if let Some(built_in) = crate::model::get_models(&state)
         .1
         .get(model)
@@ -983,10 +1013,24 @@ pub fn save_spectrum(
                 .to_html(false)
             })
         }
+        Some("msp") => {
+            let mut writer = BufWriter::new(file);
+            crate::msp::write_msp(&mut writer, &spectrum, sequence, model_name.as_deref())
+                .and_then(|()| writer.flush())
+                .map_err(|e| {
+                    BoxedError::new(
+                        BasicKind::Error,
+                        "Could not write file",
+                        "Could not write MSP",
+                        Context::show(e.to_string()),
+                    )
+                    .to_html(false)
+                })
+        }
         _ => Err(BoxedError::new(
             BasicKind::Error,
             "Could not write file",
-            "Invalid path, use mgf, mzml, or mzspeclib.txt as extension",
+            "Invalid path, use mgf, msp, mzml, or mzspeclib.txt as extension",
             Context::show(path.to_string_lossy()).to_owned(),
         )
         .to_html(false)),
@@ -1,14 +1,20 @@
 use std::{
     cell::{Ref, RefCell, RefMut},
-    sync::Arc,
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, atomic::AtomicBool},
 };
 
 use mzannotate::prelude::*;
 use mzcore::{ontology::Ontologies, prelude::*, sequence::Linear};
 use mzpeaks::CentroidPeak;
 use ordered_float::OrderedFloat;
+use serde::{Deserialize, Serialize};
 
-use crate::{psm_file::PSMFile, raw_file::RawFile};
+use crate::{
+    coverage::FastaDatabase, psm_file::PSMFile, psm_format::CustomPSMFormat, raw_file::RawFile,
+    recalibration::Recalibration,
+};
 
 pub struct State {
     pub spectra: Vec<RawFile>,
@@ -18,7 +24,15 @@ pub struct State {
     pub custom_modifications_error: Option<(String, Vec<String>)>,
     pub custom_models: Vec<(String, FragmentationModel)>,
     pub custom_models_error: Option<(String, Vec<String>)>,
+    pub custom_psm_formats: Vec<CustomPSMFormat>,
     pub auto_open_errors: Vec<String>,
+    pub watched_folder: Option<(PathBuf, Arc<AtomicBool>)>,
+    pub fasta: FastaDatabase,
+    pub raw_file_search_roots: Vec<PathBuf>,
+    /// The app cache directory, used to store the alignment indices of PSM files
+    pub cache_dir: Option<PathBuf>,
+    /// The fragment m/z recalibration per raw file id
+    pub recalibrations: HashMap<usize, Recalibration>,
 }
 
 impl State {
@@ -30,11 +44,12 @@ impl State {
     }
 }
 
-#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+/// A peptidoform in the alignment index of a PSM file, this does not store the id of the PSM file
+/// so that the index can be stored on disk and reused in later sessions.
+#[derive(Clone, Deserialize, PartialEq, Eq, PartialOrd, Ord, Serialize)]
 pub struct IndexSequence {
     pub sequence: Arc<Peptidoform<Linear>>,
     pub score: Option<OrderedFloat<f64>>,
-    pub id: usize,
     pub index: usize,
 }
 
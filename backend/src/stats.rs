@@ -0,0 +1,215 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+};
+
+use itertools::Itertools;
+use mzident::PSMMetaData;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ModifiableState,
+    psm_table::{PSMFilter, PSMSortColumn, filtered_rows},
+    render::density_plot,
+};
+
+/// The protease used to count missed cleavages
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub enum Protease {
+    /// Cleaves after K and R, but not before P
+    #[default]
+    Trypsin,
+    /// Cleaves after K and R, also before P
+    TrypsinP,
+    /// Cleaves after K
+    LysC,
+    /// Cleaves before K
+    LysN,
+    /// Cleaves after R, but not before P
+    ArgC,
+    /// Cleaves before D
+    AspN,
+    /// Cleaves after E, but not before P
+    GluC,
+    /// Cleaves after F, W, and Y, but not before P
+    Chymotrypsin,
+}
+
+impl Protease {
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Trypsin => "trypsin",
+            Self::TrypsinP => "trypsin/P",
+            Self::LysC => "Lys-C",
+            Self::LysN => "Lys-N",
+            Self::ArgC => "Arg-C",
+            Self::AspN => "Asp-N",
+            Self::GluC => "Glu-C",
+            Self::Chymotrypsin => "chymotrypsin",
+        }
+    }
+
+    /// Check if this protease cleaves the bond between these two residues
+    fn cleaves(self, before: char, after: char) -> bool {
+        match self {
+            Self::Trypsin => matches!(before, 'K' | 'R') && after != 'P',
+            Self::TrypsinP => matches!(before, 'K' | 'R'),
+            Self::LysC => before == 'K',
+            Self::LysN => after == 'K',
+            Self::ArgC => before == 'R' && after != 'P',
+            Self::AspN => after == 'D',
+            Self::GluC => before == 'E' && after != 'P',
+            Self::Chymotrypsin => matches!(before, 'F' | 'W' | 'Y') && after != 'P',
+        }
+    }
+
+    /// Count the missed cleavages, all cleavage sites within the sequence
+    fn missed_cleavages(self, sequence: &[char]) -> usize {
+        sequence
+            .iter()
+            .tuple_windows()
+            .filter(|(a, b)| self.cleaves(**a, **b))
+            .count()
+    }
+}
+
+/// Render a bar for every value with the number of PSMs
+fn histogram<T: std::fmt::Display>(output: &mut String, title: &str, counts: &BTreeMap<T, usize>) {
+    let max = counts.values().copied().max().unwrap_or(1) as f64;
+    write!(
+        output,
+        "<div class='graph'><h3>{title}</h3><div class='histogram'>"
+    )
+    .unwrap();
+    for (value, count) in counts {
+        write!(
+            output,
+            "<div class='bar' title='{value}: {count} PSMs'><span class='count'>{count}</span><span class='fill' style='--fraction:{}'></span><span class='value'>{value}</span></div>",
+            *count as f64 / max
+        )
+        .unwrap();
+    }
+    write!(output, "</div></div>").unwrap();
+}
+
+/// Get the distributions of score, charge, length, mass error, missed cleavages (for the given
+/// protease), and modifications of all PSMs in a PSM file that pass the filter.
+#[tauri::command]
+pub async fn get_psm_statistics(
+    file: usize,
+    filter: PSMFilter,
+    protease: Protease,
+    state: ModifiableState<'_>,
+) -> Result<String, &'static str> {
+    let state = state.lock().await;
+    let files = state.psm_files();
+    let file = files
+        .iter()
+        .find(|f| f.id == file)
+        .ok_or("PSM file index not valid")?;
+    let rows = filtered_rows(file, &filter, PSMSortColumn::Index, false);
+
+    let mut charges = BTreeMap::new();
+    let mut lengths = BTreeMap::new();
+    let mut cleavages = BTreeMap::new();
+    let mut modifications: HashMap<&str, usize> = HashMap::new();
+    let mut unmodified = 0;
+    let mut original_scores = Vec::new();
+    for row in &rows {
+        if let Some(charge) = row.charge {
+            *charges.entry(charge).or_insert(0) += 1;
+        }
+        if let Some(length) = row.length {
+            *lengths.entry(length).or_insert(0) += 1;
+        }
+        let psm = &file.peptides[row.index];
+        if let Some(peptidoform) = psm
+            .peptidoform_ion_set()
+            .and_then(|p| p.singular_peptidoform_ref().cloned())
+        {
+            let sequence = peptidoform
+                .sequence()
+                .iter()
+                .flat_map(|s| s.aminoacid.pro_forma_definition().chars())
+                .collect_vec();
+            *cleavages
+                .entry(protease.missed_cleavages(&sequence))
+                .or_insert(0) += 1;
+        }
+        if let Some((score, _)) = psm.original_confidence() {
+            original_scores.push(score);
+        }
+        if row.modifications.is_empty() {
+            unmodified += 1;
+        }
+        for modification in &row.modifications {
+            *modifications.entry(modification.as_str()).or_insert(0) += 1;
+        }
+    }
+
+    let mut output = String::new();
+    write!(
+        output,
+        "<div class='psm-statistics'><p>{} PSMs, {} unique peptidoforms, {} unmodified PSMs</p>",
+        rows.len(),
+        rows.iter().map(|r| &r.peptide).unique().count(),
+        unmodified,
+    )
+    .unwrap();
+    for (title, data) in [
+        ("Score", rows.iter().filter_map(|r| r.score).collect_vec()),
+        ("Original score", original_scores),
+        (
+            "Precursor mass error (ppm)",
+            rows.iter().filter_map(|r| r.mass_error).collect_vec(),
+        ),
+    ] {
+        write!(output, "<div class='graph'><h3>{title}</h3>").unwrap();
+        if data.is_empty() {
+            write!(output, "<p>Not available</p>").unwrap();
+        } else {
+            output.push_str(&density_plot(data));
+        }
+        write!(output, "</div>").unwrap();
+    }
+    histogram(&mut output, "Charge", &charges);
+    histogram(&mut output, "Peptide length", &lengths);
+    histogram(
+        &mut output,
+        &format!("Missed cleavages ({})", protease.name()),
+        &cleavages,
+    );
+    write!(
+        output,
+        "<div class='graph'><h3>Modifications</h3><table><thead><tr><th>Modification</th><th>PSMs</th><th>Fraction</th></tr></thead><tbody>"
+    )
+    .unwrap();
+    for (modification, count) in modifications
+        .into_iter()
+        .sorted_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)))
+    {
+        write!(
+            output,
+            "<tr><td>{modification}</td><td>{count}</td><td>{:.2}%</td></tr>",
+            count as f64 / rows.len() as f64 * 100.0
+        )
+        .unwrap();
+    }
+    write!(output, "</tbody></table></div></div>").unwrap();
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Protease;
+
+    #[test]
+    fn missed_cleavages() {
+        let sequence = "AKPDRKEFGK".chars().collect::<Vec<_>>();
+        assert_eq!(Protease::Trypsin.missed_cleavages(&sequence), 2);
+        assert_eq!(Protease::TrypsinP.missed_cleavages(&sequence), 3);
+        assert_eq!(Protease::AspN.missed_cleavages(&sequence), 1);
+        assert_eq!(Protease::GluC.missed_cleavages(&sequence), 1);
+        assert_eq!(Protease::Chymotrypsin.missed_cleavages(&sequence), 1);
+    }
+}
@@ -0,0 +1,204 @@
+use std::{collections::HashSet, fmt::Write};
+
+use context_error::{BasicKind, BoxedError, CreateError, FullErrorContent};
+use itertools::Itertools;
+use mzannotate::{fragment::FragmentType, prelude::*};
+use mzcore::{
+    molecular_formula,
+    prelude::*,
+    quantities::WithinTolerance,
+    system::{MassOverCharge, e, isize::Charge, thomson},
+};
+use mzdata::prelude::SpectrumLike;
+
+use crate::{ModifiableState, compare::parse_linear, spectra::NoiseFilter};
+
+/// The maximal number of explanations shown for a single peak
+const MAXIMAL_EXPLANATIONS: usize = 10;
+
+/// A theoretical ion that is not generated by the selected model
+struct Candidate {
+    category: &'static str,
+    label: String,
+    mz: f64,
+}
+
+/// For the most intense peaks that are not annotated with the selected model, find theoretical
+/// ions that could explain them: internal fragments, immonium ions, diagnostic ions, fragments
+/// with additional neutral losses, fragments of the model at other charge states, and isotope
+/// peaks of the fragments of the model. All explanations within the tolerance are reported
+/// with their error.
+#[tauri::command]
+pub async fn explain_unassigned_peaks(
+    peptide: &str,
+    amount: usize,
+    tolerance: (f64, &str),
+    charge: Option<isize>,
+    noise_filter: (NoiseFilter, f32),
+    model: usize,
+    mass_mode: &str,
+    mz_range: (Option<f64>, Option<f64>),
+    isotopes: bool,
+    isotope_filter: f64,
+    state: ModifiableState<'_>,
+) -> Result<String, String> {
+    let mut state = state.lock().await;
+    let peptidoform = parse_linear(peptide, &state.ontologies)?;
+    let (_, spectrum) = crate::spectra::create_selected_spectrum(&mut state, noise_filter)
+        .map_err(|err| err.to_html(false))?;
+    let model = crate::model::get_models(&state)
+        .1
+        .get(model)
+        .map(|m| m.2.clone())
+        .ok_or_else(|| {
+            BoxedError::small(BasicKind::Error, "Invalid model", "Model does not exist")
+                .to_html(false)
+        })?;
+    let parameters = crate::model::parameters(tolerance, mz_range, isotopes, isotope_filter)
+        .map_err(|err| err.to_html(false))?;
+    let mass_mode = crate::model::mass_mode(mass_mode).map_err(|err| err.to_html(false))?;
+    let charge = charge
+        .or_else(|| {
+            spectrum
+                .precursor()
+                .and_then(|p| p.ions.first().and_then(|i| i.charge.map(|c| c as isize)))
+        })
+        .unwrap_or(1)
+        .max(1);
+    let (peptidoform_ion_set, _) =
+        PeptidoformIonSet::pro_forma(&peptidoform.to_string(), &state.ontologies).map_err(
+            |errs| {
+                BoxedError::small(
+                    BasicKind::Error,
+                    "Could not create peptidoform",
+                    peptidoform.to_string(),
+                )
+                .add_underlying_errors(errs)
+                .to_html(false)
+            },
+        )?;
+    let fragments =
+        peptidoform_ion_set.generate_theoretical_fragments(Charge::new::<e>(charge), &model);
+    let annotated = spectrum.clone().annotate(
+        peptidoform_ion_set.clone(),
+        &fragments,
+        &parameters,
+        mass_mode,
+    );
+
+    // Gather all candidate ions
+    let key = |mz: f64| (mz * 1e4).round() as i64;
+    let known: HashSet<i64> = fragments
+        .iter()
+        .filter_map(|f| f.mz(mass_mode).map(|mz| key(mz.value)))
+        .collect();
+    let proton = molecular_formula!(H 1 Electron -1).mass(mass_mode).value;
+    let isotope_spacing = molecular_formula!([13 C 1] C -1).mass(mass_mode).value;
+    let mut candidates = Vec::new();
+    for fragment in peptidoform_ion_set
+        .generate_theoretical_fragments(Charge::new::<e>(charge), FragmentationModel::all())
+    {
+        let Some(mz) = fragment.mz(mass_mode) else {
+            continue;
+        };
+        if known.contains(&key(mz.value)) {
+            continue;
+        }
+        candidates.push(Candidate {
+            category: match fragment.ion {
+                FragmentType::Internal(..) => "Internal fragment",
+                FragmentType::Immonium(_, _) => "Immonium ion",
+                FragmentType::Diagnostic(_) => "Diagnostic ion",
+                _ if !fragment.neutral_loss.is_empty() => "Additional neutral loss",
+                _ => "Other fragment",
+            },
+            label: fragment.to_mz_paf_string(),
+            mz: mz.value,
+        });
+    }
+    for fragment in &fragments {
+        let Some(mz) = fragment.mz(mass_mode) else {
+            continue;
+        };
+        let fragment_charge = fragment.charge.value.max(1);
+        let neutral = (mz.value - proton) * fragment_charge as f64;
+        for other in (1..=charge).filter(|c| *c != fragment_charge) {
+            candidates.push(Candidate {
+                category: "Other charge state",
+                label: format!("{} at charge {other}+", fragment.to_mz_paf_string()),
+                mz: neutral / other as f64 + proton,
+            });
+        }
+        if !parameters.match_isotopes {
+            for isotope in 1..=2 {
+                candidates.push(Candidate {
+                    category: "Isotope",
+                    label: format!("{}+{isotope}i", fragment.to_mz_paf_string()),
+                    mz: mz.value + isotope as f64 * isotope_spacing / fragment_charge as f64,
+                });
+            }
+        }
+    }
+
+    // Explain the most intense unassigned peaks
+    let max_intensity = annotated
+        .peaks
+        .iter()
+        .map(|p| p.intensity)
+        .fold(0.0_f32, f32::max)
+        .max(f32::EPSILON);
+    let mut output = String::new();
+    write!(
+        output,
+        "<table class='unassigned-peaks'><thead><tr><th>m/z</th><th>Relative intensity</th><th>Category</th><th>Label</th><th>Theoretical m/z</th><th>Error (ppm)</th></tr></thead><tbody>"
+    )
+    .unwrap();
+    for peak in annotated
+        .peaks
+        .iter()
+        .filter(|p| p.annotations.is_empty())
+        .sorted_by(|a, b| b.intensity.total_cmp(&a.intensity))
+        .take(amount)
+    {
+        let explanations = candidates
+            .iter()
+            .filter(|c| {
+                parameters
+                    .tolerance
+                    .within(&peak.mz, &MassOverCharge::new::<thomson>(c.mz))
+            })
+            .map(|c| (c, (peak.mz.value - c.mz) / c.mz * 1e6))
+            .sorted_by(|a, b| a.1.abs().total_cmp(&b.1.abs()))
+            .unique_by(|(c, _)| (c.category, &c.label))
+            .take(MAXIMAL_EXPLANATIONS)
+            .collect_vec();
+        let rows = explanations.len().max(1);
+        write!(
+            output,
+            "<tr><td rowspan='{rows}'>{:.4}</td><td rowspan='{rows}'>{:.2}%</td>",
+            peak.mz.value,
+            peak.intensity / max_intensity * 100.0,
+        )
+        .unwrap();
+        if explanations.is_empty() {
+            write!(
+                output,
+                "<td colspan='4' class='none'>No explanation found</td></tr>"
+            )
+            .unwrap();
+        }
+        for (index, (candidate, ppm)) in explanations.iter().enumerate() {
+            write!(
+                output,
+                "{}<td>{}</td><td>{}</td><td>{:.4}</td><td>{ppm:.2}</td></tr>",
+                if index == 0 { "" } else { "<tr>" },
+                candidate.category,
+                candidate.label,
+                candidate.mz,
+            )
+            .unwrap();
+        }
+    }
+    write!(output, "</tbody></table>").unwrap();
+    Ok(output)
+}
@@ -0,0 +1,328 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use context_error::{BasicKind, BoxedError, Context, CreateError, FullErrorContent};
+use mzannotate::{annotation::model::BuiltInFragmentationModel, prelude::*};
+use mzcore::{
+    molecular_formula,
+    prelude::*,
+    system::{e, isize::Charge},
+};
+use mzdata::prelude::{IonProperties, SpectrumLike};
+use serde::{Deserialize, Serialize};
+use tauri::{Emitter, Manager};
+use tokio::sync::Mutex;
+
+use crate::{
+    ModifiableState, RAW_EXTENSIONS, file_extension, raw_file::RawFile, spectra::NoiseFilter,
+    state::State,
+};
+
+const PSM_EXTENSIONS: &[&str] = &[
+    "csv",
+    "tsv",
+    "txt",
+    "psmtsv",
+    "mztab",
+    "deepnovo_denovo",
+    "ssl",
+];
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// The number of spectra at the end of a new raw file that are searched for the targets
+const TARGET_SEARCH_DEPTH: usize = 500;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct WatchSettings {
+    pub path: PathBuf,
+    /// Also open PSM files
+    pub psm_files: bool,
+    /// ProForma definitions of the target peptidoforms
+    pub targets: Vec<String>,
+    /// Precursor tolerance in ppm for matching targets
+    pub tolerance: f64,
+    /// The settings used to annotate the matched targets
+    #[serde(default)]
+    pub annotation: WatchAnnotationSettings,
+}
+
+/// The annotation settings for annotating targets in a watched folder, as set in the annotate section.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct WatchAnnotationSettings {
+    pub tolerance: (f64, String),
+    pub mass_mode: String,
+    pub mz_range: (Option<f64>, Option<f64>),
+    pub isotopes: bool,
+    pub isotope_filter: f64,
+    pub noise_filter: (NoiseFilter, f32),
+}
+
+impl Default for WatchAnnotationSettings {
+    fn default() -> Self {
+        Self {
+            tolerance: (20.0, "ppm".to_string()),
+            mass_mode: "monoisotopic".to_string(),
+            mz_range: (None, None),
+            isotopes: false,
+            isotope_filter: 0.5,
+            noise_filter: (NoiseFilter::Local, 0.0),
+        }
+    }
+}
+
+/// The event sent to the frontend for every file handled in the watched folder.
+#[derive(Clone, Debug, Serialize)]
+struct WatchEvent {
+    path: String,
+    error: Option<String>,
+    annotated: Option<String>,
+}
+
+/// Start watching a folder, any previously watched folder is stopped.
+#[tauri::command]
+pub async fn watch_folder(settings: WatchSettings, app: tauri::AppHandle) -> Result<(), String> {
+    tauri::async_runtime::spawn_blocking(move || start_watching(app, settings))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+/// Get the folder that is currently watched, if any.
+#[tauri::command]
+pub async fn get_watched_folder(state: ModifiableState<'_>) -> Result<Option<String>, ()> {
+    Ok(state
+        .lock()
+        .await
+        .watched_folder
+        .as_ref()
+        .map(|(path, _)| path.to_string_lossy().to_string()))
+}
+
+#[tauri::command]
+pub async fn stop_watching_folder(state: ModifiableState<'_>) -> Result<(), ()> {
+    if let Some((_, stop)) = state.lock().await.watched_folder.take() {
+        stop.store(true, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+pub fn start_watching(app: tauri::AppHandle, settings: WatchSettings) -> Result<(), String> {
+    if !settings.path.is_dir() {
+        return Err(BoxedError::new(
+            BasicKind::Error,
+            "Could not watch folder",
+            "The path is not a folder",
+            Context::show(settings.path.to_string_lossy()).to_owned(),
+        )
+        .to_html(false));
+    }
+    let parameters = crate::model::parameters(
+        (
+            settings.annotation.tolerance.0,
+            &settings.annotation.tolerance.1,
+        ),
+        settings.annotation.mz_range,
+        settings.annotation.isotopes,
+        settings.annotation.isotope_filter,
+    )
+    .map_err(|err| err.to_html(false))?;
+    let mass_mode = crate::model::mass_mode(&settings.annotation.mass_mode)
+        .map_err(|err| err.to_html(false))?;
+    let handle = app.state::<Mutex<State>>();
+    let mut state = handle.blocking_lock();
+    let targets = settings
+        .targets
+        .iter()
+        .filter(|t| !t.trim().is_empty())
+        .map(|t| {
+            PeptidoformIonSet::pro_forma(t, &state.ontologies)
+                .map(|(p, _)| p)
+                .map_err(|errs| {
+                    BoxedError::new(
+                        BasicKind::Error,
+                        "Invalid target peptidoform",
+                        "The target could not be parsed as a ProForma definition",
+                        Context::full_line(0, t),
+                    )
+                    .add_underlying_errors(errs)
+                    .to_html(false)
+                })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let stop = Arc::new(AtomicBool::new(false));
+    if let Some((_, previous)) = state
+        .watched_folder
+        .replace((settings.path.clone(), stop.clone()))
+    {
+        previous.store(true, Ordering::Relaxed);
+    }
+    drop(state);
+
+    // All files already present are ignored, only newly acquired files are opened
+    let mut seen: HashMap<PathBuf, Option<u64>> = list_files(&settings.path)
+        .into_iter()
+        .map(|(path, _)| (path, None))
+        .collect();
+    std::thread::spawn(move || {
+        while !stop.load(Ordering::Relaxed) {
+            std::thread::sleep(POLL_INTERVAL);
+            for (path, size) in list_files(&settings.path) {
+                match seen.get(&path) {
+                    // Already opened or present from the start
+                    Some(None) => continue,
+                    // Only open the file once the size is stable, otherwise it is still being written
+                    Some(Some(previous)) if *previous == size => {
+                        seen.insert(path.clone(), None);
+                        if let Some(event) =
+                            open_file(&app, &path, &settings, &targets, &parameters, mass_mode)
+                        {
+                            let _ = app.emit("watch-folder", event);
+                        }
+                    }
+                    _ => {
+                        seen.insert(path, Some(size));
+                    }
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+fn list_files(folder: &Path) -> Vec<(PathBuf, u64)> {
+    std::fs::read_dir(folder)
+        .map(|dir| {
+            dir.filter_map(|entry| entry.ok())
+                .filter_map(|entry| {
+                    entry
+                        .metadata()
+                        .ok()
+                        .filter(|m| m.is_file())
+                        .map(|m| (entry.path(), m.len()))
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn open_file(
+    app: &tauri::AppHandle,
+    path: &Path,
+    settings: &WatchSettings,
+    targets: &[PeptidoformIonSet],
+    parameters: &MatchingParameters,
+    mass_mode: MassMode,
+) -> Option<WatchEvent> {
+    let extension = file_extension(path)?;
+    let handle = app.state::<Mutex<State>>();
+    let mut state = handle.blocking_lock();
+    let mut event = WatchEvent {
+        path: path.to_string_lossy().to_string(),
+        error: None,
+        annotated: None,
+    };
+    if RAW_EXTENSIONS.contains(&extension.as_str()) {
+        match crate::spectra::annotator_open_raw_file(path, &mut state) {
+            Ok(details) if !targets.is_empty() => {
+                match annotate_newest_target(
+                    &mut state, details.id, targets, settings, parameters, mass_mode,
+                ) {
+                    Ok(annotated) => event.annotated = annotated,
+                    Err(error) => event.error = Some(error),
+                }
+            }
+            Ok(_) => (),
+            Err(error) => event.error = Some(error),
+        }
+    } else if settings.psm_files && PSM_EXTENSIONS.contains(&extension.as_str()) {
+        match crate::psms::annotator_open_psm_file(path, &mut state) {
+            Ok(warning) => event.error = warning,
+            Err(error) => event.error = Some(error),
+        }
+    } else {
+        return None;
+    }
+    Some(event)
+}
+
+/// Find the newest MS2 spectrum with a precursor matching any of the targets, select and annotate it.
+/// Only the last spectra of the file are searched, as these are the most recently acquired.
+/// Returns the ProForma definition of the annotated target if one was found.
+fn annotate_newest_target(
+    state: &mut State,
+    file_id: usize,
+    targets: &[PeptidoformIonSet],
+    settings: &WatchSettings,
+    parameters: &MatchingParameters,
+    mass_mode: MassMode,
+) -> Result<Option<String>, String> {
+    let proton = molecular_formula!(H 1 Electron -1)
+        .monoisotopic_mass()
+        .value;
+    let matches = |spectrum: &mzdata::spectrum::MultiLayerSpectrum| {
+        let ion = spectrum.precursor().and_then(|p| p.ions.first())?;
+        let charge = ion.charge()?;
+        targets.iter().find_map(|target| {
+            target.formulas().iter().find_map(|formula| {
+                let mz = (formula.monoisotopic_mass().value + f64::from(charge) * proton)
+                    / f64::from(charge);
+                ((ion.mz - mz).abs() / mz * 1e6 <= settings.tolerance).then(|| {
+                    (
+                        spectrum.index(),
+                        target.clone(),
+                        charge,
+                        spectrum.precursor().map(|p| {
+                            crate::model::get_built_in_index(BuiltInFragmentationModel::from(
+                                p.activation.methods(),
+                            ))
+                        }),
+                    )
+                })
+            })
+        })
+    };
+
+    let Some(file) = state.spectra.iter_mut().find(|f| f.id() == file_id) else {
+        return Ok(None);
+    };
+    let found = match file {
+        RawFile::File { rawfile, .. } => (rawfile.len().saturating_sub(TARGET_SEARCH_DEPTH)
+            ..rawfile.len())
+            .rev()
+            .filter_map(|index| rawfile.get_spectrum_by_index(index))
+            .filter(|s| s.ms_level() == 2)
+            .find_map(|s| matches(&s)),
+        RawFile::Single { spectrum, .. } => matches(spectrum),
+    };
+    let Some((index, peptide, charge, model)) = found else {
+        return Ok(None);
+    };
+
+    for file in &mut state.spectra {
+        file.clear_selected();
+    }
+    state
+        .spectra
+        .iter_mut()
+        .find(|f| f.id() == file_id)
+        .map_or(Ok(()), |f| f.select_index(index))
+        .map_err(|e| e.to_string())?;
+    let (background, spectrum) =
+        crate::spectra::create_selected_spectrum(state, settings.annotation.noise_filter)
+            .map_err(|err| err.to_html(false))?;
+    let model = crate::model::get_models(state)
+        .1
+        .get(model.unwrap_or_default())
+        .map_or_else(|| FragmentationModel::all().clone(), |m| m.2.clone());
+    let fragments =
+        peptide.generate_theoretical_fragments(Charge::new::<e>(charge as isize), &model);
+    let definition = peptide.to_string();
+    let annotated = spectrum.annotate(peptide, &fragments, parameters, mass_mode);
+    state.annotated_spectrum = Some((annotated, background));
+    Ok(Some(definition))
+}
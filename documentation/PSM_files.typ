@@ -37,6 +37,10 @@ There are quite some programs that export PSMs. The annotator supports a selecti
   [π-PrimeNovo], [0.1],
 )
 
+== Custom formats <custom-psm-formats>
+
+Other CSV or TSV files can be opened by defining a custom format in the PSM file settings, use #button[Create new] to add a format and #button[Edit] or #button[Delete] to change or remove a format. A custom format has a unique name and gives the separator and the names of the columns (case insensitive) that contain the sequence, charge, scan, and raw file, and optionally the score and retention time. The modifications in the sequence can be written as ProForma, with round brackets (`PEM(Oxidation)TIDE`), or with unsigned mass shifts (`PEM[15.995]TIDE`), and flanking residues (`K.PEPTIDE.R`) can be removed. Fields that contain the separator can be quoted with double quotes, a double quote inside a quoted field is written as two double quotes. The scan column is interpreted as the zero based spectrum index unless the scans are marked as one based. If a score range is given the score is normalised to this range. The retention time is read in minutes unless it is marked as seconds. When a file is not recognised as any of the formats above the custom formats are tried in order and the first format with all columns present in the header is used. The custom formats are stored in `custom_psm_formats.json` in the configuration folder. If this file cannot be read it is renamed to a backup file so that it is not overwritten, and the error is stored next to it.
+
 #aside[If you have data from an unsupported version of a supported program please open an issue on GitHub and give an example file so that the support can be extended.]
 
 #aside[If you have data from an unsupported program that you think should be supported open an issue on GitHub to discuss.]
@@ -51,8 +55,38 @@ Below the structured metadata follows in table format all other metadata from th
 
 Use #button[Load] to select the right spectrum (if the corresponding raw file is open) and load the details for annotation. This will load the sequence, charge, and method if these are available.
 
+If the PSM names the raw file it originates from and this raw file is not open yet, the Annotator first tries the path as given, relative to the folder of the PSM file if it is a relative path, and then searches for it in the folder of the PSM file and in the raw file search roots (including up to three levels of subfolders). Any file with the same name, ignoring a supported raw file extension (optionally gzipped), and a supported raw file extension is opened automatically. The same rule is used to link the PSMs to the open raw files, so `run` and `run.mzML` in a PSM file both match `run.mzML.gz` but `sample.v2` only matches files named `sample.v2` with a raw file extension. The search roots can be set in the 'PSM file settings' or on the command line with `--raw-file-root <folder>` (can be given multiple times). For every PSM file the raw files that are referenced but could not be found are listed.
+
+== PSM table
+
+Below the PSM details the PSM table lists all PSMs of the selected PSM file, one page at a time. The PSMs can be filtered on score, charge, length, absolute precursor mass error, modification, protein, and raw file, and sorted on any of these columns. Next to the general columns the table shows the columns specific to the format of the PSM file. Click on a row to show the details of that PSM. Use #button[Export] to save all PSMs passing the filter as a TSV file.
+
+== Consensus
+
+When the same data is searched with multiple search engines, use #button[Compare PSM files] to list all spectra that are identified in more than one open PSM file. The spectra are matched on raw file name and spectrum, where scan numbers and native IDs are converted to the spectrum index (native IDs only if the raw file is open). PSMs that do not name their raw file are only compared within their own PSM file. By default only spectra where the PSM files disagree on the peptidoform are shown. Use #button[Annotate together] to select the spectrum and annotate all distinct peptidoforms as a chimeric spectrum.
+
+== PSM statistics
+
+Use #button[Show statistics] to get a quick overview of the quality of the search result of the selected PSM file. It shows the density of the normalised score, the original score, and the precursor mass error, and histograms of the charge, peptide length, and number of missed cleavages. The missed cleavages are counted for the selected protease, for example for trypsin any K or R not followed by P, except at the C terminus. Lastly all modifications are listed with the number of PSMs that contain them. The filters of the PSM table are used, for example to only show the statistics of the PSMs passing an FDR threshold (see @fdr).
+
 == Peptidoform search
 
 Once at least one PSM file is open all PSM can be searched for sequence patterns. Type the search pattern as a ProForma (see @proforma) peptidoform in the search box and hit #button[Search] to search. The search is based on mass based alignment@schulte_handle_2024 so any peptidoform matching the mass pattern of the search will come up. For example, searching for 'WNA' matches 'R#text(fill: blue)[WGGA]PG'. By default it will show the 25 best matching peptidoforms, but this number can be changed. The search can be restricted with a minimal score for the searched peptidoforms, which also makes the search faster, or with a minimal score for the alignment. Both these minimal score have to be in range 0.0 to 1.0.
 
+The first search in a PSM file builds a search index, which can take some time for large files. This index is stored in the app cache folder. When the same version of the PSM file (same location and contents) is opened again the stored index is loaded on the first search instead of rebuilt. If the PSM file is changed the index is rebuilt on the next search. Indices that have not been used for 30 days are removed, and at most 32 indices are kept.
+
 Once the search is complete all matching peptidoforms (up to the maximum) will be shown below. The index indicates from which PSM file the peptidoform originated as well as the index in that file. Clicking on the index selects this peptidoform in the peptidoform details pane. The sequence column shows the sequence of the peptidoform, with in blue the section that matched the search term. The match score (normalised mass based alignment score between 0 and 1) as well as the peptidoform score is shown in the last two columns.
+
+Under 'Filter on metadata' the search can be restricted to PSMs matching their metadata. All given criteria have to match. When the search box is left empty all PSMs matching the metadata are shown, sorted on score:
++ Precursor mass (in Da) or m/z (in Th) with a tolerance in ppm. The experimental mass or m/z is used if the PSM file contains it, otherwise the theoretical mass of the peptidoform, or the theoretical m/z at the charge of the PSM.
++ A modification, given as a named modification (for example `U:Oxidation` or `UNIMOD:35`) or as a mass shift (for example `+15.995`) with a tolerance in Da.
++ A protein accession or name, this matches any PSM with a protein containing the search text (case insensitive).
++ A retention time range in minutes.
+
+== Protein coverage
+
+Use #button[Load FASTA] to load a FASTA file, the proteins are indexed once when loaded. When a FASTA file is loaded all peptidoforms from all open PSM files are mapped to the proteins, treating I and L as equal. For every protein covered by at least one PSM the sequence is shown with the coverage percentage, the number of PSMs, and the number of distinct peptides. Every residue is coloured by the number of PSMs covering it and underlined if a modification was observed on it, hover over a residue to see the exact counts and modifications. Below the sequence all matching peptides are listed with their location, clicking on any of the PSMs selects it in the peptidoform details pane.
+
+== False discovery rate <fdr>
+
+For PSM files that contain decoys the false discovery rate can be estimated with the target–decoy approach with #button[Compute FDR]. If the search engine reports which PSMs are decoys (Sage and pLink) this is used, otherwise a PSM is a decoy when all its proteins start with one of the decoy prefixes (for example `DECOY_` or `rev_`, case insensitive). The PSMs are ranked on the selected score, which can be the normalised score, the original score from the search engine, or the absolute precursor mass error. Per PSM the q-value is calculated as the lowest FDR (decoys divided by targets) at which the PSM is accepted. PSMs with the same score are accepted together so they get the same q-value. Once calculated the number of accepted targets at 0.1%, 1%, 5%, and 10% FDR is shown. The peptide search, metadata search, PSM table, and PSM export can then be restricted to target PSMs passing a chosen FDR. PSM files where the FDR is not calculated are not filtered, in files where it is calculated PSMs without the selected score are filtered out.
@@ -66,4 +66,6 @@ Immonium ions are internal fragments that break on both sides of a single amino
 
 If there are cross-links in the peptidoform it can be controlled if these are allowed to cleave in theoretical fragmentation. This only work for cross-link modifications that have defined cleavage rules, see @custom-modifications.
 
+Custom models are stored in a separate JSON file on your computer, similar to custom modifications (see @custom-modifications). To share models with colleagues, for example a model tuned for a specific instrument, one or more models (custom or built in) can be exported to a standalone JSON file, turn on 'Export' for these models in the custom model section and use #button[Export selected]. Such a file can be imported on any other computer with #button[Import], all models in the file are checked before any is added so a file with an invalid model will not be partially imported. If a model with the same name (ignoring case) already exists a number is appended to the name of the imported model, for example 'EThcD (2)'.
+
 #aside[There are some reference sheets available and the end of this manual to help keep an overview of all fragmentation chemistry. #link("https://github.com/douweschulte/reference-sheets")[Or see douweschulte/reference-sheets on GitHub.]]
\ No newline at end of file
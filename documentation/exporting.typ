@@ -6,4 +6,7 @@ To export an annotated spectrum click the export button in the top left corner.
 
 == Data
 
-Once a spectrum is annotated the selected spectrum can be saved as a mzSpecLib, MGF, or mzML file. mzSpecLib files contain the spectrum and the peak annotations. MGF and mzML contain only the spectrum.
\ No newline at end of file
+Once a spectrum is annotated the selected spectrum can be saved as a mzSpecLib, MSP, MGF, or mzML file. mzSpecLib files contain the spectrum and the peak annotations. MSP files contain the spectrum, the ProForma definition, charge, and model, and the peak annotations as mzPAF strings. MGF and mzML contain only the spectrum.
+== Percolator
+
+A whole PSM file can be exported as a Percolator PIN file for rescoring with #button[Export PIN]. Percolator needs targets and decoys, so the FDR has to be computed for this PSM file first (see @fdr) to know which PSMs are decoys. Every PSM is annotated with its spectrum from the open raw files using the current annotation settings, the spectrum selection is restored afterwards. Next to the score, original search engine score, absolute precursor mass error, peptidoform length, and the charge (as one column per charge state) the PIN file contains the features derived from the annotation: the fraction of found fragments, the fraction of annotated peaks, the fraction of annotated intensity, the fraction of covered positions, and the peaks and intensity σ from the annotation FDR. For Sage, MSFragger, and MaxQuant the scores of the search engine are added as well. PSMs without a spectrum in the open raw files are skipped.
@@ -42,6 +42,14 @@ Once a spectrum is selected it will be listed below the respective raw file. It
 
 If multiple spectra are selected at the same time these spectra will be merged before being annotated.
 
+== Watched folder
+
+When using the Annotator next to the instrument a folder can be watched with #button[Watch folder], any raw file (and if enabled any PSM file) that appears in this folder is opened as soon as it is fully written. Files already present when the watching starts are ignored. Optionally a list of target peptidoforms can be given, for every new raw file the newest MS2 spectrum (within the last 500 spectra) with a precursor matching one of the targets is selected and annotated with the settings from the annotate section at the moment the watching was started. The watched folder is shown below the buttons to open files, where it can be stopped. The folder can also be watched from the start by starting the Annotator from the command line, in that case the default annotation settings are used.
+
+```
+annotator --watch <folder> --watch-psm-files --watch-target "PEPTIDE" --watch-target "ANOTHERPEPTIDE" --watch-tolerance 20
+```
+
 == Thermo raw files <thermo-raw>
 
 The .NET 8.0 runtime is needed to open Thermo raw files, #link("https://dotnet.microsoft.com/en-us/download/dotnet/8.0")[which can be downloaded here.] Additionally on windows you can use `winget install Microsoft.DotNet.Runtime.8` for a quick install. Once this is installed Thermo raw files can be loaded as any other file.
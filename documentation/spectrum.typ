@@ -38,6 +38,10 @@ Using the unassigned mode is most helpful to detect a series of peaks that is a
 
 #figure(image("error_graph.svg"), caption: [Example error graph in absolute error mode with the errors for unassigned peaks relative to some N terminal ion series. Visible is that four peaks match perfectly but right after that four additional peaks have a constant offset of 16 Thompson to the reference series.] ) <fig-error-graph>
 
+=== Recalibration <recalibration>
+
+The error graph only shows the errors for a single spectrum. A systematic error for a whole run can be estimated from a PSM file with #button[Estimate recalibration] in the PSM details pane by annotating all PSMs that pass the minimal score (and FDR, see @fdr) with their spectra from the open raw files, using the current annotation settings. For every raw file the errors of all annotated peaks are fitted with a linear model of m/z and retention time (error = a + b × m/z + c × RT, in ppm). At least 25 annotated peaks are needed for a raw file. The fitted model is shown with a graph of all errors against m/z and the fitted error at the median retention time, together with the root mean square error before and after recalibration. From then on the peaks of all spectra from that raw file are corrected before annotation. The recalibration is stored as a parameter in the spectrum, so recalibrated spectra can be saved as MGF or mzML. When spectra from multiple raw files are selected together every spectrum is recalibrated with the model of its own raw file before the spectra are merged. Use #button[Clear recalibration] for a single raw file or #button[Clear all] to go back to the original m/z values. The precursor m/z is not recalibrated.
+
 == Statistics overview
 
 The statistic overview gives detailed statistics about the match. Listed are:
@@ -48,9 +52,18 @@ The statistic overview gives detailed statistics about the match. Listed are:
 + The sequence positions covered by at least one matched fragment. If some positions do not generate theoretical fragments, for example with cross-linking loop links, this statistic will be split into one regarding the full peptidoform and one only taking the possible locations into account.
 + Peaks false match chance a false discovery metric at the peaks level. Estimating the FDR by permutation. The spectrum is shifted by -25 to +25 Da plus π (to have non integer offsets) and the number of matches is counted. The percentage is the number of actual matches divided by the average found for the shifted spectra. The number between brackets denotes the number of standard deviations the actual matches are from the shifted matches. This number is the average chance for a single peak annotation to be based on chance. For some rough guidelines, 10% or less is commonly seen for bottom up data while up to 50% can be seen for top or middle down data.
 + Intensity false match chance. This is based on the same calculation as the peaks false match chance, However this does not count the number of peaks matched but the sum intensity. This number should be similar or lower compared to the peaks false match chance.
++ Discriminating fragments found, only shown when multiple peptidoforms are annotated at the same time. These are the theoretical fragments of a peptidoform where no fragment of any of the other peptidoforms falls within the tolerance, so these are the only fragments that can decide between isobaric or chimeric alternatives. Listed is how many of these were observed, and the ion series specific statistics list all discriminating fragments with the observed ones highlighted.
 
 The toggle switch above the table allows to open ion series specific statistics. These are exactly the same as above but split per ion series.
 
+=== Rank fragmentation models <rank-models>
+
+When the activation method of a spectrum is not known, or to check which settings best describe an activation method, use #button[Rank models] in the spectrum analysis section below the fragment table to annotate the selected spectrum with the current peptidoform using every built-in and custom model. The models are ranked on the fraction of the intensity that is annotated, with ties broken on the fraction of theoretical fragments that are found. For each model the annotated peaks, theoretical fragments found, sequence positions covered, and number of unexplained peaks are shown as well. Note that models that generate more fragments, like 'All', will often annotate more intensity but find a lower fraction of their theoretical fragments. Click on a model to select it and annotate the spectrum with it.
+
+=== Explain unassigned peaks <unassigned-peaks>
+
+Intense peaks that are not annotated often point to something interesting, like an unexpected modification or an unusual fragmentation pathway. Give the number of peaks and use #button[Explain peaks] in the spectrum analysis section below the fragment table. For the given number of most intense unassigned peaks possible explanations are listed that are not generated by the selected model: internal fragments (written as `m3:6` in mzPAF), immonium ions, diagnostic ions, fragments with additional neutral losses, and other fragments that are generated by the 'All' model, as well as fragments of the selected model at another charge state (up to the precursor charge) and the first and second isotope of fragments of the selected model (only when isotopes are not already matched). All explanations within the tolerance are shown with their error, at most 10 per peak, sorted on the absolute error. Note that with many candidates some peaks will be explained by chance, especially with wide tolerances, so treat these as suggestions.
+
 == Fragment table
 
 This table contains all details on the spectrum in table format. It can display unassigned peaks, annotated peaks, and missing fragments. For each peak/fragment it displays all data. This whole table can be copied to other software for other analysis. Additionally, above the table is a normalised output for the ProForma definition, which removes any implementation specific notation and returns a fully specification compliant ProForma sequence.
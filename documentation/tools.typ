@@ -18,4 +18,34 @@ This can be used to find modifications in any of the ontologies (modification da
 
 == Isotopic distribution
 
-Here the isotopic distribution for any molecular formula can be generated. All weights of the formula will be displayed as well as a graph with all isotopic peaks. Hovering over the peaks gives additional details on that specific peak. For the generation of the isotopic distribution an averagine model is used that slightly overestimates the prevalence of higher weight isotopes, especially for elements with multiple isotopes. Any element with a defined monoisotopic weight, so any element that is stable and naturally occurring, can be used in the formula.
\ No newline at end of file
+Here the isotopic distribution for any molecular formula can be generated. All weights of the formula will be displayed as well as a graph with all isotopic peaks. Hovering over the peaks gives additional details on that specific peak. For the generation of the isotopic distribution an averagine model is used that slightly overestimates the prevalence of higher weight isotopes, especially for elements with multiple isotopes. Any element with a defined monoisotopic weight, so any element that is stable and naturally occurring, can be used in the formula.
+
+== Compare peptidoforms
+
+Two peptidoforms, for example a de novo read and a database hit, can be compared with mass based alignment@schulte_handle_2024. Both peptidoforms are given in ProForma notation (see @proforma) and have to be linear (no cross-links). The alignment is shown with the two peptidoforms above each other, every aligned segment is marked with the kind of match: identical, identical with a mass difference due to modifications, isobaric (for example N and GG), rotated (for example AG and GA), mismatching, or a gap. The normalised alignment score and the mass difference between the two peptidoforms are shown above the alignment.
+
+Optionally (with 'Annotate on spectrum') both peptidoforms can be annotated on the selected spectrum using the current annotation settings. All positions with at least one annotated fragment are shown underlined and bold, and for both peptidoforms the number of positions with fragments and the fraction of annotated peaks and intensity is shown. This indicates which of the sites that differ between the two peptidoforms are supported by the spectrum.
+
+== Modification localisation <localisation>
+
+When the position of a modification is not certain it can be defined as an ambiguous modification in ProForma (see @proforma), either with a group on all possible positions (`PS[Phospho#g1]T[#g1]Y`) or as a modification at an unknown position (`[Phospho]?PSTY`). The localisation tool enumerates all positional isomers, where two ambiguous modifications cannot occupy the same position, and annotates every isomer on the selected spectrum using the current annotation settings. Give the peptidoform and use #button[Localise] to score it. The site-determining ions are the theoretical fragments of an isomer that are not shared by all isomers, where two fragments are the same if they are within the tolerance. Following PhosphoRS@taus_universal_2011 every isomer is scored by the binomial probability of matching at least the observed number of site-determining ions by chance, with the chance of a random match based on the number of peaks, the tolerance, and the m/z range of the spectrum. These are normalised into a probability for each isomer and summed into a probability for every possible site. For every isomer all site-determining ions are listed with the observed ions highlighted. At most 256 isomers can be scored.
+
+== Explain precursor mass offset <mass-offset>
+
+When the mass of the annotated peptidoform does not match the precursor mass of the selected spectrum the difference can be explained with this tool, similar to an open search on a single spectrum. The mass offset is calculated from the precursor m/z and charge of the spectrum (or the given charge). Candidate explanations are all modifications in any of the ontologies with a mass within the given tolerance (in Dalton) of the offset, pairs of Unimod modifications that together fit the offset within the tolerance (where one modification can be at most 50 Da beyond the offset or below zero, and only the 100 pairs with the smallest mass error are used), and all single amino acid substitutions of the canonical amino acids. Every modification is placed on every position allowed by its placement rules (modifications without placement rules, like mass modifications, can be placed on any residue) and every placement is annotated on the selected spectrum using the current annotation settings. Give the peptidoform and the tolerance and use #button[Explain] to search. The placements are ranked on the fraction of the intensity that is explained. At most 500 placements are annotated, when there are more placements the ones with the smallest mass error are annotated, and the best 50 are shown.
+
+== Glycan inference <glycan-inference>
+
+When a peptide is glycosylated with an unknown glycan the glycan can be inferred from the selected spectrum. Give the peptide backbone in ProForma (see @proforma) without the glycan, and optionally the mass delta of the glycan, otherwise the difference between the precursor mass and the mass of the backbone is used. All GNOme compositions and topologies with a mass within the mass tolerance (in Dalton) of the delta are placed on the most likely glycosylation site: the first N in a sequon (N-X-S/T with X not P), otherwise the first S or T, otherwise the first N. This site is a guess, so all other possible sites are listed as well, and backbone fragments that contain the site depend on this choice. Use #button[Infer] to search. Every candidate is annotated on the selected spectrum using the current annotation settings, so use a model that generates glycan fragments (see @custom-model). The candidates are ranked on the fraction of the intensity that is explained by oxonium (B) and Y ions, with ties broken on the number of oxonium and Y ions found, and the structure of the best supported topology is shown above the list. Note that topologies with the same composition often share most fragments, so check if the fragments that distinguish them are observed. At most 200 candidates are annotated, when there are more candidates the ones closest in mass to the delta are annotated.
+
+== Cross-link site search <cross-link-search>
+
+When two peptides are known to be cross-linked but the exact sites are not, the sites can be searched on the selected spectrum. Give both peptides in ProForma (see @proforma) without the cross-link and the linker, for example `X:DSS` or `XLMOD:02001`. All site pairs that are allowed by the placement rules of the linker are enumerated, for asymmetric linkers both orientations are tried. Use #button[Search] to annotate every site pair on the selected spectrum using the current annotation settings. The site pairs are ranked on the number of peaks annotated with a fragment that contains the intact cross-link, as these are the fragments that localise the sites. Evidence is counted per peak, so a peak that is explained by multiple fragments only counts once. Ties are broken on the number of stub doublets and lastly on the fraction of intensity explained. For cleavable linkers (with breakages defined, see @custom-modifications) a stub doublet is the same fragment observed with two different stubs on two different peaks, which is a strong indication of the cross-link. Terminal sites are skipped when the peptide already has a modification on that terminus, all skipped site pairs are listed above the results with the reason. At most 500 site pairs can be annotated and the best 50 are shown, with the full ProForma definition so that the best one can be copied to annotate the spectrum.
+
+== Disulfide bond mapping <disulfide-bonds>
+
+To find which cysteines are bonded give one or more peptides in ProForma (see @proforma), separated by `//`, without the disulfide bonds. All unmodified cysteines are considered, so block free cysteines with a modification (for example `C[Carbamidomethyl]`) if these are known. All connectivity patterns with the given number of bonds (by default as many as possible) are enumerated, where patterns that leave a peptide unconnected are skipped as the spectrum is of a single molecule. The bonds are written as `MOD:00034`, L-cystine (cross link) from PSI-MOD, and with #button[Map] every pattern is annotated on the selected spectrum using the current annotation settings. Additionally, the S–S cleavage products that are typical for ETD and EAD are matched: when breaking a single bond separates the molecule, the separated parts are matched at the reduced mass and one hydrogen less or more, at all charges up to the precursor charge. Breaking a bond that does not separate the molecule, like an intra-chain bond or one bond of a pair between the same peptides, does not give a separate product, so these bonds are only supported by fragments with an intact disulfide. The number of these bonds is listed for every pattern. The patterns are ranked on the sum of the number of peaks annotated with a fragment with an intact disulfide (a peak explained by multiple fragments only counts once) and the number of observed cleavage products, with ties broken on the explained intensity. At most 500 patterns can be annotated and the best 25 are shown.
+
+== Proteoform fragment map <proteoform-map>
+
+For top-down spectra the normal peptidoform view gets hard to read, the proteoform fragment map gives an overview for long sequences. Give the proteoform in ProForma (see @proforma) and use #button[Map], it is annotated on the selected spectrum using the current annotation settings (for example with the 'Top-down ETD' model). The sequence is wrapped in rows of 50 residues with a space every 10 residues. Between every two residues the cleavages are marked, a mark on top for N terminal fragments (a, b, c, d) and a mark on the bottom for C terminal fragments (v, w, x, y, z). Hovering over a mark shows the fragments with all charges they were observed at. Modified residues are highlighted. The number and fraction of bonds that are cleaved is given. For every modified residue the region that the modification is localised to is listed, this is the region between the closest cleaved bonds on both sides of the residue, a region of one residue means that the modification is fully localised. Terminal modifications are localised to the region between the terminus and the first cleaved bond from that terminus. The characterisation score is the fraction of cleaved bonds multiplied by the average localisation of all modifications, where the localisation is one over the number of residues in the region. This is 1 for a proteoform where every bond is cleaved. For deconvolved spectra, where all peaks are at charge 1, turn on 'Deconvolved' so the fragments are only matched at charge 1. Spectra that are stored with deconvolved peaks are detected automatically.
@@ -7,7 +7,7 @@ const { listen } = window.__TAURI__.event;
 const { open, save } = window.__TAURI__.dialog;
 
 const RAW_EXTENSIONS = ["xy", "mgf", "mzml", "imzml", "mzmlb", "raw"];
-const RAW_WRITE_EXTENSIONS = ["mgf", "mzml", "mzspeclib.txt"];
+const RAW_WRITE_EXTENSIONS = ["mgf", "msp", "mzml", "mzspeclib.txt"];
 const PSM_EXTENSIONS = ["csv", "csv.gz", "tsv", "tsv.gz", "txt", "txt.gz", "psmtsv", "psmtsv.gz", "fasta", "fasta.gz", "fas", "fas.gz", "fa", "fa.gz", "faa", "faa.gz", "mpfa", "mpfa.gz", "mztab", "mztab.gz", "deepnovo_denovo", "deepnovo_denovo.gz", "ssl", "ssl.gz"];
 
 import { SetUpSpectrumInterface, spectrumClearDistanceLabels } from "./script.js";
@@ -49,6 +49,19 @@ listen('tauri://drag-drop', event => {
   });
 })
 
+listen('watch-folder', event => {
+  if (event.payload.error != null) {
+    showError("open-files-error", event.payload.error);
+  }
+  if (event.payload.annotated != null) {
+    document.querySelector("#peptide").innerText = event.payload.annotated;
+    refresh();
+  } else {
+    update_identified_peptide_file_select();
+    update_open_raw_files();
+  }
+})
+
 listen('tauri://drag-enter', event => {
   document.querySelector("html").classList.add("file-drop-hover");
 })
@@ -123,7 +136,7 @@ async function save_spectrum_file(e) {
   let properties = {
     title: "Save selected spectrum",
     filters: [{
-      extensions: RAW_WRITE_EXTENSIONS, name: "MGF, mzML, or mzSpecLib"
+      extensions: RAW_WRITE_EXTENSIONS, name: "MGF, MSP, mzML, or mzSpecLib"
     }]
   };
   save(properties).then((result) => {
@@ -199,6 +212,9 @@ async function select_identified_peptides_file(id) {
       document.getElementById("number-of-identified-peptides").innerText = child.dataset.length;
       child.selected = true;
       psm_details();
+      update_psm_table(0);
+      update_missing_raw_files();
+      document.querySelector("#compute-fdr-result").innerText = "";
       return;
     }
   }
@@ -355,6 +371,10 @@ async function update_open_raw_files() {
           input_native_id.addEventListener("keydown", event => { if (event.keyCode == 13) { select_on_scan() } else { } });
           spectrum_selection.appendChild(input_native_id);
           header.appendChild(spectrum_selection);
+          let chromatogram = document.createElement("button");
+          chromatogram.innerText = "Chromatogram";
+          chromatogram.addEventListener("click", () => toggle_chromatogram(file.id, chromatogram));
+          header.appendChild(chromatogram);
           rawfile.dataset.single = false;
         }
         let close = document.createElement("button");
@@ -375,6 +395,82 @@ async function update_open_raw_files() {
   });
 }
 
+/// Show the watched folder, if any
+async function update_watched_folder() {
+  invoke("get_watched_folder").then((result) => {
+    document.querySelector("#watched-folder").hidden = result == null;
+    document.querySelector("#watched-folder-path").innerText = result == null ? "" : result;
+  });
+}
+
+async function start_watching_folder() {
+  let path = document.querySelector("#watch-folder-path").dataset.path;
+  if (path == undefined) {
+    showError("watch-folder-error", "Select a folder to watch");
+    return;
+  }
+  let settings = annotation_settings();
+  document.querySelector("#watch-folder-start").classList.add("loading");
+  invoke("watch_folder", {
+    settings: {
+      path: path,
+      psm_files: document.querySelector("#watch-folder-psm-files").checked,
+      targets: document.querySelector("#watch-folder-targets").value.split("\n").map(t => t.trim()).filter(t => t != ""),
+      tolerance: Number(document.querySelector("#watch-folder-tolerance").value),
+      annotation: {
+        tolerance: settings.tolerance,
+        mass_mode: settings.massMode,
+        mz_range: settings.mzRange,
+        isotopes: settings.isotopes,
+        isotope_filter: settings.isotopeFilter,
+        noise_filter: settings.noiseFilter,
+      },
+    }
+  }).then(() => {
+    clearError("watch-folder-error");
+    document.querySelector("#watch-folder-start").classList.remove("loading");
+    document.querySelector("#watch-folder-dialog").close();
+    update_watched_folder();
+  }).catch((error) => {
+    showError("watch-folder-error", error);
+    document.querySelector("#watch-folder-start").classList.remove("loading");
+  });
+}
+
+/// Show or hide the TIC and base peak chromatograms for a raw file, clicking on the chromatogram selects the closest MS2 spectrum
+async function toggle_chromatogram(file_id, button) {
+  let existing = document.getElementById("rawfile-" + file_id + "-chromatogram");
+  if (existing != null) {
+    existing.remove();
+    return;
+  }
+  button.classList.add("loading");
+  invoke("get_chromatogram", { fileIndex: file_id }).then((result) => {
+    let wrapper = document.createElement("div");
+    wrapper.id = "rawfile-" + file_id + "-chromatogram";
+    wrapper.innerHTML = result;
+    wrapper.addEventListener("click", e => {
+      let chromatogram = wrapper.querySelector(".chromatogram");
+      let box = chromatogram.getBoundingClientRect();
+      let min = Number(chromatogram.dataset.minRt);
+      let max = Number(chromatogram.dataset.maxRt);
+      let rt = min + (e.clientX - box.left) / box.width * (max - min);
+      invoke("select_chromatogram_retention_time", { fileIndex: file_id, rt: rt }).then(() => {
+        clearError("open-files-error");
+        update_selected_spectra();
+        invoke("get_chromatogram", { fileIndex: file_id }).then((result) => wrapper.innerHTML = result);
+      }).catch((error) => {
+        showError("open-files-error", error);
+      });
+    });
+    button.parentElement.after(wrapper);
+    button.classList.remove("loading");
+  }).catch((error) => {
+    showError("open-files-error", error);
+    button.classList.remove("loading");
+  });
+}
+
 /// Refresh the selected spectra
 async function update_selected_spectra() {
   invoke("get_selected_spectra", {}).then(
@@ -441,13 +537,33 @@ async function load_annotated_spectrum() {
   }
 }
 
+function psm_query() {
+  let text = (id) => document.querySelector(id).value.trim() == "" ? null : document.querySelector(id).value;
+  let ppm = Number(document.querySelector("#search-peptide-ppm").value);
+  let mass = number_or_null("search-peptide-mass");
+  let mz = number_or_null("search-peptide-mz");
+  let query = {
+    mass: mass == null ? null : [mass, ppm],
+    mz: mz == null ? null : [mz, ppm],
+    modification: text("#search-peptide-modification"),
+    modification_tolerance: Number(document.querySelector("#search-peptide-modification-tolerance").value),
+    protein: text("#search-peptide-protein"),
+    retention_time: [number_or_null("search-peptide-rt-min"), number_or_null("search-peptide-rt-max")],
+  };
+  let empty = query.mass == null && query.mz == null && query.modification == null && query.protein == null && query.retention_time[0] == null && query.retention_time[1] == null;
+  return empty ? null : query;
+}
+
 async function search_peptide() {
-  if (document.querySelector("#search-peptide-input").value != "") {
+  let query = psm_query();
+  if (document.querySelector("#search-peptide-input").value != "" || query != null) {
     document.querySelector("#search-peptide").classList.add("loading");
     invoke("search_peptide", {
       text: document.querySelector("#search-peptide-input").value,
+      query: query,
       minimalMatchScore: Number(document.querySelector("#search-peptide-minimal-match").value),
       minimalPeptideScore: Number(document.querySelector("#search-peptide-minimal-peptide").value),
+      maximalFdr: number_or_null("search-peptide-fdr"),
       amount: Number(document.querySelector("#search-peptide-amount").value)
     }).then((result) => {
       document.querySelector("#resulting-peptides").innerHTML = result;
@@ -463,6 +579,87 @@ async function search_peptide() {
   }
 }
 
+function selected_psm_file() {
+  let select = document.querySelector("#details-identified-peptide-files");
+  if (select.children.length == 0) return null;
+  return Number(select.options[select.selectedIndex].value);
+}
+
+function psm_filter() {
+  let text = (id) => document.querySelector(id).value.trim() == "" ? null : document.querySelector(id).value;
+  return {
+    score: [number_or_null("psm-filter-score-min"), number_or_null("psm-filter-score-max")],
+    charge: [number_or_null("psm-filter-charge-min"), number_or_null("psm-filter-charge-max")],
+    mass_error: number_or_null("psm-filter-mass-error"),
+    length: [number_or_null("psm-filter-length-min"), number_or_null("psm-filter-length-max")],
+    modification: text("#psm-filter-modification"),
+    protein: text("#psm-filter-protein"),
+    raw_file: text("#psm-filter-raw-file"),
+    maximal_fdr: number_or_null("psm-filter-fdr"),
+  };
+}
+
+let psm_table_page = 0;
+async function update_psm_table(page = psm_table_page) {
+  let file = selected_psm_file();
+  if (file == null) return;
+  let page_size = Math.max(1, Number(document.querySelector("#psm-table-page-size").value));
+  document.querySelector("#psm-table-apply").classList.add("loading");
+  invoke("get_psm_table", {
+    file: file,
+    filter: psm_filter(),
+    sort: document.querySelector("#psm-table-sort").value,
+    descending: document.querySelector("#psm-table-descending").checked,
+    page: page,
+    pageSize: page_size,
+    theme: Theme,
+  }).then((result) => {
+    document.querySelector("#psm-table-apply").classList.remove("loading");
+    clearError("psm-table-error");
+    let pages = Math.max(1, Math.ceil(result.total / page_size));
+    psm_table_page = Math.min(page, pages - 1);
+    document.querySelector("#psm-table-page").innerText = `Page ${psm_table_page + 1} / ${pages} (${result.total} PSMs)`;
+    document.querySelector("#psm-table-previous").disabled = psm_table_page == 0;
+    document.querySelector("#psm-table-next").disabled = psm_table_page >= pages - 1;
+
+    let columns = [["Index", (r) => r.index], ["Peptide", (r) => r.peptide], ["Score", (r) => r.score], ["Charge", (r) => r.charge], ["m/z", (r) => r.mz?.toFixed(4)], ["Mass error (ppm)", (r) => r.mass_error?.toFixed(2)], ["Length", (r) => r.length], ["Modifications", (r) => r.modifications.join(", ")], ["Proteins", (r) => r.proteins.join(", ")], ["Raw files", (r) => r.raw_files.join(", ")], ["Scans", (r) => r.scans], ["RT (min)", (r) => r.rt?.toFixed(2)], ["q-value", (r) => r.q_value?.toExponential(2)], ["Decoy", (r) => r.decoy ? "Decoy" : ""]];
+    let data_columns = [...new Set(result.rows.flatMap((r) => (r.data ?? []).map((d) => d[0])))];
+    let table = createElement("table");
+    let header = createElement("tr");
+    for (let column of columns) header.appendChild(createElement("th", { text: column[0] }));
+    for (let column of data_columns) header.appendChild(createElement("th", { text: column }));
+    table.appendChild(header);
+    for (let row of result.rows) {
+      let tr = createElement("tr", { title: "Show the details of this PSM" });
+      for (let column of columns) tr.appendChild(createElement("td", { text: String(column[1](row) ?? "-") }));
+      for (let column of data_columns) tr.appendChild(createElement("td", { html: (row.data ?? []).find((d) => d[0] == column)?.[1] ?? "-" }));
+      tr.addEventListener("click", () => load_peptide(row.file, row.index));
+      table.appendChild(tr);
+    }
+    let output = document.querySelector("#psm-table");
+    output.innerText = "";
+    output.appendChild(table);
+  }).catch((error) => {
+    document.querySelector("#psm-table-apply").classList.remove("loading");
+    showError("psm-table-error", error);
+  })
+}
+
+async function export_psm_table() {
+  let file = selected_psm_file();
+  if (file == null) return;
+  save({ filters: [{ name: "TSV", extensions: ["tsv"] }] }).then((path) => {
+    if (path == null) return;
+    invoke("export_psm_table", {
+      file: file,
+      filter: psm_filter(),
+      sort: document.querySelector("#psm-table-sort").value,
+      descending: document.querySelector("#psm-table-descending").checked,
+      path: path,
+    }).then(() => clearError("psm-table-error")).catch((error) => showError("psm-table-error", error));
+  });
+}
+
 async function close_identified_peptide_file() {
   let select = document.querySelector("#details-identified-peptide-files");
   invoke("close_identified_peptides_file", { file: Number(select.options[select.selectedIndex].value) }).then(() => {
@@ -486,6 +683,267 @@ export async function load_peptide(file, peptide) {
 }
 window.load_peptide = load_peptide;
 
+async function update_missing_raw_files() {
+  let file = selected_psm_file();
+  if (file == null) return;
+  invoke("get_missing_raw_files", { file: file }).then((result) => {
+    let output = document.querySelector("#missing-raw-files");
+    output.classList.toggle("hidden", result.length == 0);
+    output.innerText = result.length == 0 ? "" : "Raw files that are not open and could not be found: " + result.join(", ");
+  }).catch(() => { })
+}
+
+async function update_raw_file_search_roots() {
+  invoke("get_raw_file_search_roots").then((result) => {
+    document.querySelector("#raw-file-search-roots").value = result.join("\n");
+  })
+}
+
+async function save_raw_file_search_roots() {
+  invoke("set_raw_file_search_roots", { roots: document.querySelector("#raw-file-search-roots").value.split("\n") }).then(() => {
+    clearError("raw-file-search-roots-error");
+    update_missing_raw_files();
+  }).catch((error) => showError("raw-file-search-roots-error", error));
+}
+
+async function get_psm_statistics() {
+  let file = selected_psm_file();
+  if (file == null) return;
+  document.querySelector("#psm-statistics").classList.add("loading");
+  invoke("get_psm_statistics", {
+    file: file,
+    filter: psm_filter(),
+    protease: document.querySelector("#psm-statistics-protease").value,
+  }).then((result) => {
+    document.querySelector("#psm-statistics").classList.remove("loading");
+    clearError("psm-statistics-error");
+    document.querySelector("#psm-statistics-result").innerHTML = result;
+  }).catch((error) => {
+    document.querySelector("#psm-statistics").classList.remove("loading");
+    showError("psm-statistics-error", error);
+  });
+}
+
+async function update_custom_psm_formats() {
+  invoke("get_custom_psm_formats").then((formats) => {
+    let container = document.getElementById("custom-psm-formats");
+    container.innerText = "";
+    for (let format of formats) {
+      let element = document.createElement("li");
+      element.appendChild(createElement("p", { text: format.name }));
+      let edit_button = createElement("button", { text: "Edit" });
+      edit_button.classList.add("edit");
+      edit_button.addEventListener("click", () => open_custom_psm_format_dialog(format));
+      element.appendChild(edit_button);
+      let delete_button = createElement("button", { text: "Delete" });
+      delete_button.classList.add("delete");
+      delete_button.classList.add("secondary");
+      delete_button.addEventListener("click", () =>
+        invoke("delete_custom_psm_format", { name: format.name })
+          .then(() => {
+            clearError("custom-psm-formats-error");
+            update_custom_psm_formats();
+          })
+          .catch((error) => showError("custom-psm-formats-error", error)));
+      element.appendChild(delete_button);
+      container.appendChild(element);
+    }
+  }).catch((error) => showError("custom-psm-formats-error", error));
+}
+
+function open_custom_psm_format_dialog(format) {
+  let dialog = document.getElementById("custom-psm-format-dialog");
+  let set = (id, value) => document.getElementById("custom-psm-format-" + id).value = value ?? "";
+  let check = (id, value) => document.getElementById("custom-psm-format-" + id).checked = value;
+  dialog.dataset.originalName = format == null ? "" : format.name;
+  set("name", format?.name);
+  set("separator", format == null ? "," : format.separator == "\t" ? "tab" : format.separator);
+  set("sequence", format?.sequence);
+  set("modifications", format == null ? "ProForma" : format.modifications);
+  check("strip-flanking", format?.strip_flanking ?? false);
+  set("charge", format?.charge);
+  set("scan", format?.scan);
+  check("one-based", format?.one_based_scans ?? false);
+  set("raw-file", format?.raw_file);
+  set("score", format?.score);
+  set("score-min", format?.score_range?.[0]);
+  set("score-max", format?.score_range?.[1]);
+  set("retention-time", format?.retention_time);
+  check("retention-time-seconds", format?.retention_time_in_seconds ?? false);
+  clearError("custom-psm-format-error");
+  dialog.showModal();
+}
+
+async function save_custom_psm_format() {
+  let dialog = document.getElementById("custom-psm-format-dialog");
+  let text = (id) => document.getElementById("custom-psm-format-" + id).value.trim();
+  let optional_text = (id) => text(id) == "" ? null : text(id);
+  let checked = (id) => document.getElementById("custom-psm-format-" + id).checked;
+  let score_min = number_or_null("custom-psm-format-score-min");
+  let score_max = number_or_null("custom-psm-format-score-max");
+  let separator = text("separator");
+  invoke("update_custom_psm_format", {
+    originalName: dialog.dataset.originalName == "" ? null : dialog.dataset.originalName,
+    format: {
+      name: text("name"),
+      separator: separator == "tab" ? "\t" : separator,
+      sequence: text("sequence"),
+      charge: text("charge"),
+      scan: text("scan"),
+      raw_file: text("raw-file"),
+      score: optional_text("score"),
+      score_range: score_min == null || score_max == null ? null : [score_min, score_max],
+      retention_time: optional_text("retention-time"),
+      retention_time_in_seconds: checked("retention-time-seconds"),
+      one_based_scans: checked("one-based"),
+      modifications: text("modifications"),
+      strip_flanking: checked("strip-flanking"),
+    }
+  }).then(() => {
+    dialog.close();
+    update_custom_psm_formats();
+  }).catch((error) => showError("custom-psm-format-error", error));
+}
+
+async function compute_fdr() {
+  let file = selected_psm_file();
+  if (file == null) return;
+  document.querySelector("#compute-fdr").classList.add("loading");
+  invoke("compute_fdr", {
+    file: file,
+    settings: {
+      decoy_prefixes: document.querySelector("#fdr-decoy-prefixes").value.split(",").map((p) => p.trim()).filter((p) => p != ""),
+      score: document.querySelector("#fdr-score").value,
+      lower_is_better: document.querySelector("#fdr-lower-is-better").checked,
+    },
+  }).then((result) => {
+    document.querySelector("#compute-fdr").classList.remove("loading");
+    document.querySelector("#compute-fdr-result").innerHTML = result;
+    clearError("compute-fdr-error");
+    update_psm_table(0);
+  }).catch((error) => {
+    document.querySelector("#compute-fdr").classList.remove("loading");
+    showError("compute-fdr-error", error);
+  })
+}
+
+async function estimate_recalibration() {
+  let file = selected_psm_file();
+  if (file == null) return;
+  document.querySelector("#estimate-recalibration").classList.add("loading");
+  invoke("estimate_recalibration", {
+    file: file,
+    minimalPeptideScore: Number(document.querySelector("#recalibration-minimal-score").value),
+    maximalFdr: number_or_null("recalibration-fdr"),
+    ...annotation_settings(),
+  }).then((result) => {
+    document.querySelector("#estimate-recalibration").classList.remove("loading");
+    document.querySelector("#recalibration-result").innerHTML = result;
+    clearError("recalibration-error");
+  }).catch((error) => {
+    document.querySelector("#estimate-recalibration").classList.remove("loading");
+    showError("recalibration-error", error);
+  })
+}
+
+async function clear_recalibration(file) {
+  invoke("clear_recalibration", { file: file }).then(() => {
+    let result = document.querySelector("#recalibration-result");
+    if (file == null) {
+      result.innerHTML = "";
+    } else {
+      result.querySelector(".clear-recalibration[data-file='" + file + "']")?.closest(".recalibration")?.remove();
+    }
+    clearError("recalibration-error");
+  });
+}
+
+async function export_pin() {
+  let file = selected_psm_file();
+  if (file == null) return;
+  save({ filters: [{ name: "Percolator input", extensions: ["pin"] }] }).then((path) => {
+    if (path == null) return;
+    document.querySelector("#export-pin").classList.add("loading");
+    invoke("export_pin", { ...annotation_settings(), file: file, path: path }).then((result) => {
+      document.querySelector("#export-pin").classList.remove("loading");
+      document.querySelector("#compute-fdr-result").innerText = result;
+      clearError("compute-fdr-error");
+    }).catch((error) => {
+      document.querySelector("#export-pin").classList.remove("loading");
+      showError("compute-fdr-error", error);
+    })
+  })
+}
+
+async function load_fasta() {
+  open({ directory: false, multiple: false, filters: [{ name: "FASTA", extensions: ["fasta", "fasta.gz", "fas", "fas.gz", "fa", "fa.gz", "faa", "faa.gz"] }] }).then((path) => {
+    if (path == null) return;
+    document.querySelector("#load-fasta").classList.add("loading");
+    invoke("load_fasta", { path: path }).then((result) => {
+      document.querySelector("#load-fasta").classList.remove("loading");
+      document.querySelector("#fasta-status").innerText = `${result} proteins loaded`;
+      document.querySelector("#fasta-status").title = path;
+      clearError("protein-coverage-error");
+      get_protein_coverage();
+    }).catch((error) => {
+      document.querySelector("#load-fasta").classList.remove("loading");
+      showError("protein-coverage-error", error);
+    })
+  })
+}
+
+async function get_protein_coverage() {
+  document.querySelector("#protein-coverage").classList.add("loading");
+  invoke("get_protein_coverage", {
+    filter: document.querySelector("#protein-coverage-filter").value,
+    amount: Number(document.querySelector("#protein-coverage-amount").value),
+  }).then((result) => {
+    document.querySelector("#protein-coverage").classList.remove("loading");
+    document.querySelector("#protein-coverage-result").innerHTML = result;
+    clearError("protein-coverage-error");
+  }).catch((error) => {
+    document.querySelector("#protein-coverage").classList.remove("loading");
+    showError("protein-coverage-error", error);
+  })
+}
+
+async function get_consensus_groups() {
+  document.querySelector("#consensus-groups").classList.add("loading");
+  invoke("get_consensus_groups", { onlyDisagreeing: document.querySelector("#consensus-only-disagreeing").checked }).then((result) => {
+    document.querySelector("#consensus-groups").classList.remove("loading");
+    document.querySelector("#consensus-groups-result").innerHTML = result;
+    clearError("consensus-groups-error");
+  }).catch((error) => {
+    document.querySelector("#consensus-groups").classList.remove("loading");
+    showError("consensus-groups-error", error);
+  })
+}
+
+export async function load_consensus_group(psms) {
+  invoke("load_consensus_group", { psms: psms.split(";").map((p) => p.split(":").map(Number)) }).then((result) => {
+    document.querySelector("#peptide").innerText = result.peptide;
+    document.querySelector("#spectrum-charge").value = result.charge;
+    if (result.mode != null) {
+      document.querySelector("#spectrum-model").value = result.mode;
+    }
+    if (result.warning != null) {
+      showError("spectrum-error", result.warning);
+    } else {
+      clearError("spectrum-error");
+    }
+    update_open_raw_files();
+  }).catch((error) => {
+    showError("spectrum-error", error);
+  })
+}
+window.load_consensus_group = load_consensus_group;
+
+export async function select_model(model) {
+  document.querySelector("#spectrum-model").value = model;
+  annotate_spectrum();
+}
+window.select_model = select_model;
+
 async function search_modification() {
   if (document.querySelector("#search-modification").value != "") {
     document.querySelector("#search-modification-button").classList.add("loading");
@@ -502,6 +960,152 @@ async function search_modification() {
   }
 }
 
+async function compare_peptidoforms() {
+  document.querySelector("#compare-button").classList.add("loading");
+  invoke("compare_peptidoforms", {
+    ...annotation_settings(),
+    a: document.querySelector("#compare-a").value,
+    b: document.querySelector("#compare-b").value,
+    annotate: document.querySelector("#compare-annotate").checked,
+  }).then((result) => {
+    document.querySelector("#compare-button").classList.remove("loading");
+    document.querySelector("#compare-result").innerHTML = result;
+    clearError("compare-error");
+  }).catch((error) => {
+    document.querySelector("#compare-button").classList.remove("loading");
+    showError("compare-error", error);
+  })
+}
+
+async function localise_modifications() {
+  document.querySelector("#localise-button").classList.add("loading");
+  invoke("localise_modifications", {
+    ...annotation_settings(),
+    peptide: document.querySelector("#localise-peptide").value,
+  }).then((result) => {
+    document.querySelector("#localise-button").classList.remove("loading");
+    document.querySelector("#localise-result").innerHTML = result;
+    clearError("localise-error");
+  }).catch((error) => {
+    document.querySelector("#localise-button").classList.remove("loading");
+    showError("localise-error", error);
+  })
+}
+
+async function explain_mass_offset() {
+  document.querySelector("#mass-offset-button").classList.add("loading");
+  invoke("explain_mass_offset", {
+    ...annotation_settings(),
+    peptide: document.querySelector("#mass-offset-peptide").value,
+    massTolerance: Number(document.querySelector("#mass-offset-tolerance").value),
+  }).then((result) => {
+    document.querySelector("#mass-offset-button").classList.remove("loading");
+    document.querySelector("#mass-offset-result").innerHTML = result;
+    clearError("mass-offset-error");
+  }).catch((error) => {
+    document.querySelector("#mass-offset-button").classList.remove("loading");
+    showError("mass-offset-error", error);
+  })
+}
+
+async function infer_glycan() {
+  document.querySelector("#glycan-inference-button").classList.add("loading");
+  invoke("infer_glycan", {
+    ...annotation_settings(),
+    peptide: document.querySelector("#glycan-inference-peptide").value,
+    delta: optional_number(document.querySelector("#glycan-inference-delta").value),
+    massTolerance: Number(document.querySelector("#glycan-inference-tolerance").value),
+    theme: Theme,
+  }).then((result) => {
+    document.querySelector("#glycan-inference-button").classList.remove("loading");
+    document.querySelector("#glycan-inference-result").innerHTML = result;
+    clearError("glycan-inference-error");
+  }).catch((error) => {
+    document.querySelector("#glycan-inference-button").classList.remove("loading");
+    showError("glycan-inference-error", error);
+  })
+}
+
+async function search_cross_link_sites() {
+  document.querySelector("#cross-link-button").classList.add("loading");
+  invoke("search_cross_link_sites", {
+    ...annotation_settings(),
+    first: document.querySelector("#cross-link-first").value,
+    second: document.querySelector("#cross-link-second").value,
+    linker: document.querySelector("#cross-link-linker").value,
+  }).then((result) => {
+    document.querySelector("#cross-link-button").classList.remove("loading");
+    document.querySelector("#cross-link-result").innerHTML = result;
+    clearError("cross-link-error");
+  }).catch((error) => {
+    document.querySelector("#cross-link-button").classList.remove("loading");
+    showError("cross-link-error", error);
+  })
+}
+
+async function map_disulfide_bonds() {
+  document.querySelector("#disulfide-button").classList.add("loading");
+  invoke("map_disulfide_bonds", {
+    ...annotation_settings(),
+    peptides: document.querySelector("#disulfide-peptides").value,
+    bonds: optional_number(document.querySelector("#disulfide-bonds").value),
+  }).then((result) => {
+    document.querySelector("#disulfide-button").classList.remove("loading");
+    document.querySelector("#disulfide-result").innerHTML = result;
+    clearError("disulfide-error");
+  }).catch((error) => {
+    document.querySelector("#disulfide-button").classList.remove("loading");
+    showError("disulfide-error", error);
+  })
+}
+
+async function proteoform_map() {
+  document.querySelector("#proteoform-map-button").classList.add("loading");
+  invoke("proteoform_map", {
+    ...annotation_settings(),
+    peptide: document.querySelector("#proteoform-map-peptide").value,
+    deconvolved: document.querySelector("#proteoform-map-deconvolved").checked,
+  }).then((result) => {
+    document.querySelector("#proteoform-map-button").classList.remove("loading");
+    document.querySelector("#proteoform-map-result").innerHTML = result;
+    clearError("proteoform-map-error");
+  }).catch((error) => {
+    document.querySelector("#proteoform-map-button").classList.remove("loading");
+    showError("proteoform-map-error", error);
+  })
+}
+
+async function rank_models() {
+  document.querySelector("#rank-models").classList.add("loading");
+  invoke("rank_models", {
+    ...annotation_settings(),
+    peptide: document.querySelector("#peptide").innerText.trim(),
+  }).then((result) => {
+    document.querySelector("#rank-models").classList.remove("loading");
+    document.querySelector("#rank-models-result").innerHTML = result;
+    clearError("rank-models-error");
+  }).catch((error) => {
+    document.querySelector("#rank-models").classList.remove("loading");
+    showError("rank-models-error", error);
+  })
+}
+
+async function explain_unassigned_peaks() {
+  document.querySelector("#unassigned-peaks").classList.add("loading");
+  invoke("explain_unassigned_peaks", {
+    ...annotation_settings(),
+    peptide: document.querySelector("#peptide").innerText.trim(),
+    amount: Number(document.querySelector("#unassigned-amount").value),
+  }).then((result) => {
+    document.querySelector("#unassigned-peaks").classList.remove("loading");
+    document.querySelector("#unassigned-peaks-result").innerHTML = result;
+    clearError("unassigned-peaks-error");
+  }).catch((error) => {
+    document.querySelector("#unassigned-peaks").classList.remove("loading");
+    showError("unassigned-peaks-error", error);
+  })
+}
+
 async function details_formula(event) {
   const formula = document.getElementById("details-formula");
   invoke("details_formula", { text: formula.innerText }).then((result) => {
@@ -570,6 +1174,11 @@ function updateCustomModels() {
         let built_in = model[0];
         new_element.dataset.id = model[1];
         new_element.innerHTML = "<p class='name'>" + model[2] + "</p>";
+        let export_select = document.createElement("label");
+        export_select.classList.add("export-select");
+        export_select.title = "Select this model for exporting";
+        export_select.innerHTML = "<input type='checkbox' switch/>Export";
+        new_element.appendChild(export_select);
         if (!built_in) {
           let edit_button = document.createElement("button");
           edit_button.classList.add("edit");
@@ -636,6 +1245,34 @@ function updateCustomModels() {
     .catch(error => console.error(error))
 }
 
+function export_custom_models() {
+  let ids = [...document.querySelectorAll("#custom-models li")]
+    .filter(li => li.querySelector(".export-select input").checked)
+    .map(li => Number(li.dataset.id));
+  if (ids.length == 0) {
+    showError("custom-models-transfer-error", "Select at least one model to export");
+    return;
+  }
+  save({ filters: [{ name: "JSON", extensions: ["json"] }] }).then((path) => {
+    if (path == null) return;
+    invoke("export_custom_models", { ids: ids, path: path }).then(() => {
+      document.querySelector("#custom-models-transfer-result").innerText = `Exported ${ids.length} model${ids.length == 1 ? "" : "s"}`;
+      clearError("custom-models-transfer-error");
+    }).catch((error) => showError("custom-models-transfer-error", error));
+  });
+}
+
+function import_custom_models() {
+  open({ directory: false, multiple: false, filters: [{ name: "JSON", extensions: ["json"] }] }).then((path) => {
+    if (path == null) return;
+    invoke("import_custom_models", { path: path }).then((result) => {
+      document.querySelector("#custom-models-transfer-result").innerText = "Imported " + result.map(m => m[1]).join(", ");
+      clearError("custom-models-transfer-error");
+      updateCustomModels();
+    }).catch((error) => showError("custom-models-transfer-error", error));
+  });
+}
+
 function get_location(id) {
   let loc = document.querySelector(id);
   let t = loc.children[0].options[Number(loc.children[0].value)].dataset.value;
@@ -920,21 +1557,26 @@ function loadCustomModelEdit(model) {
   })
 }
 
-async function annotate_spectrum() {
-  document.querySelector("#annotate-button").classList.add("loading");
-  document.querySelector("#peptide").innerText = document.querySelector("#peptide").innerText.trim();
-  var charge = number_or_null("spectrum-charge");
-  var noise_threshold = Number(document.querySelector("#noise-filter").value);
-  invoke("annotate_spectrum", {
+/// The settings from the annotate section, as arguments for all commands that annotate the selected spectrum
+function annotation_settings() {
+  return {
     tolerance: [Number(document.querySelector("#spectrum-tolerance").value), document.querySelector("#spectrum-tolerance-unit").value],
-    charge: charge,
-    noiseFilter: [document.querySelector("#noise-filter-method").value, noise_threshold],
+    charge: number_or_null("spectrum-charge"),
+    noiseFilter: [document.querySelector("#noise-filter-method").value, Number(document.querySelector("#noise-filter").value)],
     model: Number(document.querySelector("#spectrum-model").value),
-    peptide: document.querySelector("#peptide").innerText,
     massMode: document.querySelector("#spectrum-mass-mode").value,
     mzRange: [optional_number(document.querySelector("#model-mz-range-min").value), optional_number(document.querySelector("#model-mz-range-max").value)],
     isotopes: document.querySelector("#spectrum-isotopes").checked,
     isotopeFilter: Number(document.querySelector("#spectrum-isotope-filter").value),
+  };
+}
+
+async function annotate_spectrum() {
+  document.querySelector("#annotate-button").classList.add("loading");
+  document.querySelector("#peptide").innerText = document.querySelector("#peptide").innerText.trim();
+  invoke("annotate_spectrum", {
+    ...annotation_settings(),
+    peptide: document.querySelector("#peptide").innerText,
     theme: Theme
   }).then((result) => {
     set_up_spectrum(result[0]);
@@ -1015,6 +1657,27 @@ window.addEventListener("DOMContentLoaded", () => {
     .querySelector("#load-usi")
     .addEventListener("click", () => load_usi());
   enter_event("#usi", load_usi)
+  document
+    .querySelector("#watch-folder")
+    .addEventListener("click", () => document.querySelector("#watch-folder-dialog").showModal());
+  document
+    .querySelector("#watch-folder-select")
+    .addEventListener("click", () => open({ directory: true, multiple: false }).then((result) => {
+      if (result != null) {
+        let path = document.querySelector("#watch-folder-path");
+        path.dataset.path = result;
+        path.innerText = result;
+      }
+    }));
+  document
+    .querySelector("#watch-folder-start")
+    .addEventListener("click", () => start_watching_folder());
+  document
+    .querySelector("#watch-folder-cancel")
+    .addEventListener("click", () => document.querySelector("#watch-folder-dialog").close());
+  document
+    .querySelector("#stop-watching-folder")
+    .addEventListener("click", () => invoke("stop_watching_folder").then(() => update_watched_folder()));
   document
     .querySelector("#load-identified-peptides")
     .addEventListener("click", (event) => dialog_select_identified_peptides_file(event.target));
@@ -1023,6 +1686,62 @@ window.addEventListener("DOMContentLoaded", () => {
     .addEventListener("change", (event) => {
       select_identified_peptides_file(Number(event.target.options[event.target.selectedIndex].value))
     });
+  document
+    .querySelector("#psm-table-apply")
+    .addEventListener("click", () => update_psm_table(0));
+  document
+    .querySelector("#psm-table-previous")
+    .addEventListener("click", () => update_psm_table(psm_table_page - 1));
+  document
+    .querySelector("#psm-table-next")
+    .addEventListener("click", () => update_psm_table(psm_table_page + 1));
+  document
+    .querySelector("#psm-table-export")
+    .addEventListener("click", () => export_psm_table());
+  document
+    .querySelector("#psm-statistics")
+    .addEventListener("click", () => get_psm_statistics());
+  document
+    .querySelector("#consensus-groups")
+    .addEventListener("click", () => get_consensus_groups());
+  document
+    .querySelector("#raw-file-search-roots-save")
+    .addEventListener("click", () => save_raw_file_search_roots());
+  document
+    .querySelector("#custom-psm-format-create")
+    .addEventListener("click", () => open_custom_psm_format_dialog(null));
+  document
+    .querySelector("#custom-psm-format-save")
+    .addEventListener("click", () => save_custom_psm_format());
+  document
+    .querySelector("#custom-psm-format-cancel")
+    .addEventListener("click", () => document.getElementById("custom-psm-format-dialog").close());
+  document
+    .querySelector("#compute-fdr")
+    .addEventListener("click", () => compute_fdr());
+  document
+    .querySelector("#export-pin")
+    .addEventListener("click", () => export_pin());
+  document
+    .querySelector("#estimate-recalibration")
+    .addEventListener("click", () => estimate_recalibration());
+  document
+    .querySelector("#clear-recalibration")
+    .addEventListener("click", () => clear_recalibration(null));
+  document
+    .querySelector("#recalibration-result")
+    .addEventListener("click", (e) => {
+      if (e.target.classList.contains("clear-recalibration")) {
+        clear_recalibration(Number(e.target.dataset.file));
+      }
+    });
+  document
+    .querySelector("#load-fasta")
+    .addEventListener("click", () => load_fasta());
+  document
+    .querySelector("#protein-coverage")
+    .addEventListener("click", () => get_protein_coverage());
+  enter_event("#protein-coverage-filter", get_protein_coverage)
   document
     .querySelector("#close-identified-peptide-file")
     .addEventListener("click", (event) => close_identified_peptide_file());
@@ -1044,6 +1763,54 @@ window.addEventListener("DOMContentLoaded", () => {
   document
     .querySelector("#details-formula")
     .addEventListener("input", details_formula);
+  document
+    .querySelector("#compare-button")
+    .addEventListener("click", () => compare_peptidoforms());
+  document
+    .querySelector("#localise-button")
+    .addEventListener("click", () => localise_modifications());
+  enter_event("#localise-peptide", localise_modifications)
+  document
+    .querySelector("#mass-offset-button")
+    .addEventListener("click", () => explain_mass_offset());
+  enter_event("#mass-offset-peptide", explain_mass_offset)
+  document
+    .querySelector("#glycan-inference-button")
+    .addEventListener("click", () => infer_glycan());
+  enter_event("#glycan-inference-peptide", infer_glycan)
+  document
+    .querySelector("#cross-link-button")
+    .addEventListener("click", () => search_cross_link_sites());
+  enter_event("#cross-link-linker", search_cross_link_sites)
+  document
+    .querySelector("#disulfide-button")
+    .addEventListener("click", () => map_disulfide_bonds());
+  enter_event("#disulfide-peptides", map_disulfide_bonds)
+  document
+    .querySelector("#proteoform-map-button")
+    .addEventListener("click", () => proteoform_map());
+  enter_event("#proteoform-map-peptide", proteoform_map)
+  document
+    .querySelector("#rank-models")
+    .addEventListener("click", () => rank_models());
+  document
+    .querySelector("#custom-models-export")
+    .addEventListener("click", () => export_custom_models());
+  document
+    .querySelector("#custom-models-import")
+    .addEventListener("click", () => import_custom_models());
+  document
+    .querySelector("#unassigned-peaks")
+    .addEventListener("click", () => explain_unassigned_peaks());
+  document
+    .querySelector("#rank-models-result")
+    .addEventListener("click", (e) => {
+      let row = e.target.closest("tr[data-model]");
+      if (row != null) {
+        document.querySelector("#spectrum-model").value = row.dataset.model;
+        annotate_spectrum();
+      }
+    });
   enter_event("#search-peptide-input", search_peptide)
   enter_event("#search-modification", search_modification)
   add_event("#details-identified-peptide-index", ["change", "focus"], psm_details)
@@ -1292,6 +2059,9 @@ async function refresh() {
     }
     update_identified_peptide_file_select();
     update_open_raw_files();
+    update_watched_folder();
+    update_raw_file_search_roots();
+    update_custom_psm_formats();
     if (result[1]) {
       set_up_spectrum(result[1]);
     }